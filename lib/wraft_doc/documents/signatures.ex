@@ -706,8 +706,12 @@ defmodule WraftDoc.Documents.Signatures do
             # Create new e_signature entries with the signature fields
             create_signature_entries(signature_fields, instance.id, current_user.id, org_id)
 
-          {:error, reason} ->
-            Logger.error("Failed to analyze PDF for signatures: #{reason}")
+          {:error, {:encrypted, details}} ->
+            Logger.error("Cannot analyze encrypted PDF for signatures: #{details}")
+            {:error, "PDF is password protected"}
+
+          {:error, {reason, details}} ->
+            Logger.error("Failed to analyze PDF for signatures (#{reason}): #{details}")
             {:error, "Failed to analyze PDF for signatures"}
         end
 
@@ -720,6 +724,7 @@ defmodule WraftDoc.Documents.Signatures do
   # Helper function to check for engine type
   defp determine_engine_type(%{name: "Pandoc + Typst"}), do: "typst"
   defp determine_engine_type(%{name: "Pandoc"}), do: "latex"
+  defp determine_engine_type(_engine), do: "auto"
 
   # Helper function to create signature entries
   defp create_signature_entries(signature_fields, content_id, user_id, org_id) do
@@ -766,6 +771,9 @@ defmodule WraftDoc.Documents.Signatures do
     Enum.map(rectangles, fn rect ->
       %{
         page: rect["page"],
+        field_id: rect["field_id"],
+        label: rect["label"],
+        role: rect["role"],
         dimensions: %{
           width: rect["dimensions"]["width"],
           height: rect["dimensions"]["height"]
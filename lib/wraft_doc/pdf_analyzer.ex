@@ -4,8 +4,63 @@ defmodule WraftDoc.PdfAnalyzer do
 
   This module provides functionality to analyze PDF documents and extract
   information about rectangles and other shapes within the document.
+
+  ## Errors
+
+  All functions return `{:error, {reason, details}}` on failure, where `details` is a
+  message and `reason` is one of:
+
+  - `:file_not_found` - The input file, font or image does not exist
+  - `:not_a_pdf` - The input has no `%PDF-` header
+  - `:encrypted` - The PDF needs a password, or the `:password` given is wrong
+  - `:corrupt_xref` - The cross-reference table, trailer or object structure is damaged
+  - `:unsupported_feature` - The PDF or an input image uses a feature that is not supported
+  - `:budget_exceeded` - The input is too large to process, e.g. a page too large to render
+  - `:invalid_argument` - An option is missing or invalid
+  - `:io_error` - Reading an input or writing output failed
+  - `:unsupported_filter`, `:corrupt_stream`, `:decode_error` - A content stream could not
+    be read (see the `:strict` option of `analyze_pdf/3`)
+
+  ## Encrypted PDFs
+
+  Functions with a `:password` option open encrypted PDFs with either the user or the
+  owner password. RC4, AES-128 and AES-256 encryption with the standard security handler
+  are supported. Without a password, or with a wrong one, they fail with `:encrypted`.
+
+  ## Analysis results
+
+  `analyze_pdf/3` returns a JSON object with `"total_pages"`, `"total_rectangles"`,
+  `"rectangles"`, `"warnings"` and `"strategy"` (the `"scans"` that ran and, for `"auto"`,
+  the `"reasons"`). Encrypted PDFs add `"encryption"` with the cipher and permission
+  flags; the `:diagnostics` option adds `"pages"` and `"content_streams"`.
+
+  `"warnings"` entries have a `"page"`, `"object_id"`, `"kind"` and `"message"`. The kind
+  is `"unsupported_filter"`, `"corrupt_stream"` or `"decode_error"` for content streams
+  that could not be read, or `"occluded_field"` for rectangles covered by later content.
+
+  Each rectangle has:
+
+  - `"page"`, `"corners"`, `"position"` and `"dimensions"` in PDF points
+  - `"field_id"` - Stable across rebuilds while the field keeps its page, position and
+    `"label"` (nearby text), or its `"destination"` name; see `match_fields/3`
+  - `"role"` - Signer role of a `wraft-sign-<role>` named destination
+  - `"fill_color"`, `"stroke_color"` - `"RGB(r, g, b)"` or `"Gray(n)"` with the color
+    space name, converted from any color space. Channels are 0-255, truncated.
+  - `"fill_opacity"`, `"stroke_opacity"`, `"blend_mode"`, `"soft_mask"` - ExtGState values
+  - `"border"` - Stroke width, `0` for shapes that are only filled
+  - `"marked_content"` - Enclosing BMC/BDC sequences, outermost first
+  - `"visible_region"` - The part inside the clipping path, `nil` when clipped away;
+    `"hidden"` marks such rectangles and hidden optional content
+  - `"occluded"` - Fraction of the visible region covered by opaque content painted later
+  - `"raw_shapes"` - Number of shapes merged into the field, e.g. a fill and a stroke
+
+  A filled rectangle painted first over the whole MediaBox is the page background and
+  is never returned. With `:diagnostics`, each entry of `"pages"` lists the corners of
+  such rectangles under `"Background"`.
   """
 
+  @type error :: {:error, {atom(), String.t()}}
+
   use Rustler,
     otp_app: :wraft_doc,
     crate: "pdf_analyzer",
@@ -21,26 +76,571 @@ defmodule WraftDoc.PdfAnalyzer do
   ## Parameters
 
   - `path`: Path to the PDF file
-  - `engine`: Engine type ("latex" or "typst")
+  - `engine`: Engine type: `"typst"` (the default when `nil`) scans for rectangles in the
+    colors above, `"latex"` reads signature form fields, and `"auto"` chooses one or both
+    from `/Producer`, `/Creator`, AcroForm signature fields and the fonts used
+  - `opts`: Map of options
+
+  ## Options
+
+  - `:diagnostics` - Also return per-page statistics and content stream details
+  - `:strict` - Fail on the first undecodable content stream instead of warning
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+  - `:threads` - Analyze pages on this many threads, `0` for one per CPU
+  - `:destination_prefix` - Prefix of named destinations declaring fields
+    (default `"wraft-sign-"`)
+  - `:skip_invisible` - Leave out rectangles that paint nothing
+  - `:include_hidden` - Also return clipped-away rectangles and hidden optional content
+  - `:occlusion_threshold` - Covered fraction (0 to 1) from which a rectangle is occluded
+  - `:drop_occluded` - Leave out occluded rectangles instead of warning about them
+  - `:merge_iou` - Overlap from which rectangles are merged into one field (default `0.9`)
+  - `:keep_duplicates` - Report every shape on its own instead of merging
+
+  ## Returns
+
+  - `{:ok, json_string}` - The analysis described under "Analysis results" in the
+    module documentation
+  - `{:error, {reason, details}}` - If analysis fails. An unknown `engine` gives
+    `:invalid_argument`
+  """
+  @spec analyze_pdf(String.t(), String.t() | nil, map()) ::
+          {:ok, String.t()} | error()
+  def analyze_pdf(path, engine, opts \\ %{}) do
+    analyze_pdf_nif(path, nil, nil, engine, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec analyze_pdf_nif(
+          String.t(),
+          String.t() | nil,
+          String.t() | nil,
+          String.t() | nil,
+          String.t()
+        ) ::
+          {:ok, String.t()} | error()
+  def analyze_pdf_nif(_path, _target_fill_color, _target_stroke_color, _engine, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Runs `analyze_pdf/3` on many PDFs in one call, e.g. every document generated by a
+  pipeline trigger.
+
+  Items share the analysis thread pool, and items naming the same file or binary with
+  the same password are parsed once. A failing item does not abort the others.
+
+  ## Parameters
+
+  - `items`: List of maps with either `:path` or `:binary` (the PDF content), plus an
+    optional `:engine` and `:opts` accepted by `analyze_pdf/3`
+  - `opts`: Map of options for the whole batch
+
+  ## Options
+
+  - `:threads` - Analyze this many documents in parallel, `0` for one per CPU
+    (default sequential)
+
+  ## Returns
+
+  - `{:ok, results}` - One `{:ok, json_string}` or `{:error, {reason, details}}` per item,
+    in item order
+  - `{:error, {reason, details}}` - If the batch options are invalid
+  """
+  @spec analyze_batch([map()], map()) :: {:ok, [{:ok, String.t()} | error()]} | error()
+  def analyze_batch(items, opts \\ %{}) do
+    items
+    |> Enum.map(&batch_item/1)
+    |> analyze_batch_nif(Jason.encode!(Map.new(opts)))
+  end
+
+  defp batch_item(item) do
+    {source, data} =
+      case item do
+        %{path: path} -> {:path, path}
+        %{binary: binary} -> {:binary, binary}
+      end
+
+    {source, data, Map.get(item, :engine), Jason.encode!(Map.new(Map.get(item, :opts, %{})))}
+  end
+
+  @doc false
+  @spec analyze_batch_nif(
+          [{:path | :binary, binary(), String.t() | nil, String.t()}],
+          String.t()
+        ) ::
+          {:ok, [{:ok, String.t()} | error()]} | error()
+  def analyze_batch_nif(_items, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Pairs the signature fields of two builds of the same document, so records created
+  for the previous build can follow their fields into the new one.
+
+  Fields are matched by equal `"field_id"` first, then by equal `"label"` (nearest
+  first, preferring the same page), then by position on the same page.
+
+  ## Parameters
+
+  - `previous`, `current`: Lists of fields, either `"rectangles"` from `analyze_pdf/3`
+    or maps with `page`, `coordinates` (`x1`, `y1`, `x2`, `y2`) and optional
+    `field_id` and `label`
+  - `opts`: Map of options
+
+  ## Options
+
+  - `:max_distance` - Largest move in points of a field matched by position alone
+    (default `36`)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with `"matches"` (`previous` and `current` list
+    indexes, ids, `method` and `distance`) and the indexes left unmatched on each side
+  - `{:error, {reason, details}}` - If a field or option is invalid
+  """
+  @spec match_fields([map()], [map()], map()) :: {:ok, String.t()} | error()
+  def match_fields(previous, current, opts \\ %{}) do
+    match_fields_nif(
+      Jason.encode!(previous),
+      Jason.encode!(current),
+      Jason.encode!(Map.new(opts))
+    )
+  end
+
+  @doc false
+  @spec match_fields_nif(String.t(), String.t(), String.t()) :: {:ok, String.t()} | error()
+  def match_fields_nif(_previous_json, _current_json, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Finds text anchors such as `{{sign:employer}}` or `[[initials]]` and places a signature
+  field at each, for documents that mark fields with text instead of colored boxes.
+
+  Anchors are matched against the page text using glyph positions, so an anchor split
+  over several text operators is still found. Text anchors hidden with render mode 3 are
+  included.
+
+  ## Parameters
+
+  - `input_path`: Path to the PDF file
+  - `output_path`: Where to write the PDF with the anchors erased, or `nil`
+  - `opts`: Map of options
+
+  ## Options
+
+  - `:patterns` - Anchor patterns, where `*` stands for one or more characters other than
+    whitespace (default `["{{*}}", "[[*]]"]`)
+  - `:width`, `:height` - Size of the field in points (default `180` by `50`)
+  - `:offset_x`, `:offset_y` - Move of the field's top left corner from the anchor's, in
+    points with y up (default `0`)
+  - `:erase` - Remove the anchor text, keeping the surrounding text in place, and save
+    the result to `output_path` (default `false`)
+  - `:pages` - List of 1-based page numbers to search (default all pages)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with one `"rectangles"` entry per anchor, in the
+    format of `analyze_pdf/3` with `"operation_type"` `"TextAnchor"` and the text matched
+    by the first `*` (or the whole anchor) as `"role"`, and the `"erased_pages"`
+  - `{:error, {reason, details}}` - If a pattern is invalid, `:erase` is set without an
+    `output_path`, or reading or writing the PDF fails
+  """
+  @spec find_anchors(String.t(), String.t() | nil, map()) :: {:ok, String.t()} | error()
+  def find_anchors(input_path, output_path, opts \\ %{}) do
+    find_anchors_nif(input_path, output_path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec find_anchors_nif(String.t(), String.t() | nil, String.t()) ::
+          {:ok, String.t()} | error()
+  def find_anchors_nif(_input_path, _output_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Parses a PDF once and returns a handle for the `document_*` queries, so several
+  queries on the same file do not re-parse it.
+
+  The document is freed when the handle is garbage collected.
+
+  ## Options
+
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+
+  ## Returns
+
+  - `{:ok, handle}` - Opaque reference to the parsed document
+  - `{:error, {reason, details}}` - If the PDF cannot be read
+  """
+  @spec open_pdf(String.t(), map()) :: {:ok, reference()} | error()
+  def open_pdf(path, opts \\ %{}) do
+    open_pdf_nif(path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec open_pdf_nif(String.t(), String.t()) :: {:ok, reference()} | error()
+  def open_pdf_nif(_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Finds the signature rectangles of an open document, like `analyze_pdf/3`.
+
+  Accepts the same options as `analyze_pdf/3` except `:password`.
+  """
+  @spec document_rectangles(reference(), String.t() | nil, map()) :: {:ok, String.t()} | error()
+  def document_rectangles(handle, engine, opts \\ %{}) do
+    document_rectangles_nif(handle, engine, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec document_rectangles_nif(reference(), String.t() | nil, String.t()) ::
+          {:ok, String.t()} | error()
+  def document_rectangles_nif(_handle, _engine, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Lists the interactive form fields of an open document.
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with a `"fields"` list giving the page, fully qualified
+    name, field type (`"Tx"`, `"Btn"`, `"Ch"` or `"Sig"`), value, flags and `"corners"` of each
+    widget
+  """
+  @spec document_fields(reference()) :: {:ok, String.t()} | error()
+  def document_fields(handle), do: document_fields_nif(handle)
+
+  @doc false
+  @spec document_fields_nif(reference()) :: {:ok, String.t()} | error()
+  def document_fields_nif(_handle) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Extracts the text of an open document, page by page.
+
+  ## Options
+
+  - `:pages` - List of 1-based page numbers to extract (default all pages)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with a `"pages"` list giving the text of each page,
+    and an `"error"` for pages whose text could not be extracted
+  """
+  @spec document_text(reference(), map()) :: {:ok, String.t()} | error()
+  def document_text(handle, opts \\ %{}) do
+    document_text_nif(handle, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec document_text_nif(reference(), String.t()) :: {:ok, String.t()} | error()
+  def document_text_nif(_handle, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Returns the metadata of an open document.
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with the PDF version, page count, `/Info` entries
+    (title, author, subject, keywords, creator, producer and dates), whether the document
+    has an AcroForm, and its `"encryption"`
+  """
+  @spec document_metadata(reference()) :: {:ok, String.t()} | error()
+  def document_metadata(handle), do: document_metadata_nif(handle)
+
+  @doc false
+  @spec document_metadata_nif(reference()) :: {:ok, String.t()} | error()
+  def document_metadata_nif(_handle) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Returns the page boxes and rotation of an open document.
+
+  ## Options
+
+  - `:pages` - List of 1-based page numbers (default all pages)
 
   ## Returns
 
-  - `{:ok, json_string}` - JSON string containing analysis results
-  - `{:error, reason}` - Error message if analysis fails
+  - `{:ok, json_string}` - JSON string with a `"pages"` list giving the media box, crop box,
+    rotation and the displayed width and height of each page
   """
-  @spec analyze_pdf(String.t(), String.t() | nil) ::
-          {:ok, String.t()} | {:error, String.t()}
-  def analyze_pdf(path, engine) do
-    analyze_pdf_nif(path, nil, nil, engine)
+  @spec document_geometry(reference(), map()) :: {:ok, String.t()} | error()
+  def document_geometry(handle, opts \\ %{}) do
+    document_geometry_nif(handle, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec document_geometry_nif(reference(), String.t()) :: {:ok, String.t()} | error()
+  def document_geometry_nif(_handle, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Stamps text or an image onto pages of a PDF, e.g. "DRAFT" watermarks or approval stamps.
+
+  Text is drawn with an embedded TrueType font (Roboto Bold by default) and may use
+  the `{n}` and `{total}` placeholders for the page number and page count.
+
+  ## Options
+
+  - `:text` - Text to draw, e.g. `"DRAFT"` or `"Page {n} of {total}"`
+  - `:image_path` - PNG or JPEG to draw instead of text
+  - `:font_path` - TrueType font used for text
+  - `:font_size` - Font size in points (default `48`)
+  - `:color` - RGB components between 0 and 1 (default `[0.5, 0.5, 0.5]`)
+  - `:opacity` - Fill and stroke opacity between 0 and 1 (default `0.3`)
+  - `:rotation` - Rotation in degrees
+  - `:position` - One of `"center"`, `"diagonal"`, `"top_left"`, `"top_center"`,
+    `"top_right"`, `"bottom_left"`, `"bottom_center"`, `"bottom_right"`
+  - `:margin` - Distance from the page edge in points (default `36`)
+  - `:pages` - List of 1-based page numbers to stamp (default all pages)
+  - `:image_width` - Width of the image in points
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with the stamped page numbers
+  - `{:error, {reason, details}}` - If stamping fails
+  """
+  @spec stamp_pdf(String.t(), String.t(), map()) :: {:ok, String.t()} | error()
+  def stamp_pdf(input_path, output_path, opts \\ %{}) do
+    opts =
+      opts
+      |> Map.new()
+      |> Map.put_new(:font_path, default_font_path())
+
+    stamp_pdf_nif(input_path, output_path, Jason.encode!(opts))
   end
 
   @doc false
-  @spec analyze_pdf_nif(String.t(), String.t() | nil, String.t() | nil, String.t() | nil) ::
-          {:ok, String.t()} | {:error, String.t()}
-  def analyze_pdf_nif(_path, _target_fill_color, _target_stroke_color, _engine) do
+  @spec stamp_pdf_nif(String.t(), String.t(), String.t()) ::
+          {:ok, String.t()} | error()
+  def stamp_pdf_nif(_input_path, _output_path, _options_json) do
     :erlang.nif_error(:nif_not_loaded)
   end
 
+  @doc """
+  Stamps sequential Bates numbers on every page of one or more PDFs.
+
+  The counter continues across files in the order given, so a document set
+  receives one contiguous range of numbers.
+
+  ## Parameters
+
+  - `files`: List of `{input_path, output_path}` tuples
+  - `opts`: Map of options
+
+  ## Options
+
+  - `:prefix` / `:suffix` - Text around the counter, e.g. `"ACME-"`
+  - `:start` - First number (default `1`)
+  - `:digits` - Zero padding width of the counter (default `6`)
+  - `:template` - Footer text; `{bates}`, `{n}` and `{total}` are replaced per page
+  - `:position` - Margin position, same presets as `stamp_pdf/3` (default `"bottom_right"`)
+  - `:margin`, `:font_path`, `:font_size`, `:color` - As in `stamp_pdf/3`
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with the start and end numbers per file (`nil`
+    for files without pages) and the next free number
+  - `{:error, {reason, details}}` - If numbering fails. No output file is changed then;
+    outputs are saved as `<output_path>.partial` and only renamed once all files succeed,
+    and existing files they replaced are put back if a rename fails
+  """
+  @spec bates_number([{String.t(), String.t()}], map()) ::
+          {:ok, String.t()} | error()
+  def bates_number(files, opts \\ %{}) do
+    opts =
+      opts
+      |> Map.new()
+      |> Map.put_new(:font_path, default_font_path())
+
+    bates_number_nif(files, Jason.encode!(opts))
+  end
+
+  @doc false
+  @spec bates_number_nif([{String.t(), String.t()}], String.t()) ::
+          {:ok, String.t()} | error()
+  def bates_number_nif(_files, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Encrypts a PDF with AES-256 and restricts what can be done with it, e.g. to send
+  contracts that cannot be printed.
+
+  The output is written to `<output_path>.partial` and opened again with the owner
+  password; it is only renamed to `output_path` once it decrypts, and the settings read
+  back from it are returned.
+
+  ## Options
+
+  - `:owner_password` - Password that lifts the restrictions (required)
+  - `:user_password` - Password needed to open the PDF. When empty the PDF opens without
+    a password but the restrictions still apply (default `""`)
+  - `:permissions` - Granted permissions out of `"print"`, `"copy"`, `"modify"`,
+    `"annotate"` and `"fill_forms"` (default all of them)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with the page count and the `"encryption"` settings
+    and `"permissions"` flags of the output
+  - `{:error, {reason, details}}` - If the PDF cannot be read or encrypted
+  """
+  @spec encrypt_pdf(String.t(), String.t(), map()) :: {:ok, String.t()} | error()
+  def encrypt_pdf(input_path, output_path, opts) do
+    encrypt_pdf_nif(input_path, output_path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec encrypt_pdf_nif(String.t(), String.t(), String.t()) :: {:ok, String.t()} | error()
+  def encrypt_pdf_nif(_input_path, _output_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Renders PDF pages to PNG images, e.g. for dashboard thumbnails and signing previews.
+
+  Rendering is done in Rust without external binaries. Each page is written to
+  `<output_dir>/page-<n>.png`.
+
+  ## Options
+
+  - `:output_dir` - Directory for the PNG files (required)
+  - `:dpi` - Resolution, greater than 0 and at most 600 (default `72`). Pages over 40
+    million pixels fail with `:budget_exceeded`
+  - `:pages` - List of 1-based page numbers to render (default all pages)
+  - `:highlight` - Outline the signature fields detected by `analyze_pdf/2` (default `false`)
+  - `:engine` - Engine used for `:highlight` ("latex", "typst" or "auto")
+  - `:fallback_font_path` - TrueType font used for fonts that are not embedded
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with the path and pixel size of each rendered page
+  - `{:error, {reason, details}}` - If rendering fails
+  """
+  @spec render_pages(String.t(), map()) :: {:ok, String.t()} | error()
+  def render_pages(path, opts) do
+    opts =
+      opts
+      |> Map.new()
+      |> Map.put_new(:fallback_font_path, default_font_path("Roboto-Regular.ttf"))
+
+    render_pages_nif(path, Jason.encode!(opts))
+  end
+
+  @doc false
+  @spec render_pages_nif(String.t(), String.t()) :: {:ok, String.t()} | error()
+  def render_pages_nif(_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Extracts the images embedded in a PDF, for asset reuse and auditing uploaded files.
+
+  Both image XObjects (including those inside Form XObjects) and inline images are
+  returned, each with its placement on the page. JPEG and JPEG 2000 data is passed
+  through unchanged; other images are decoded, with soft masks applied as alpha, and
+  encoded as PNG.
+
+  ## Options
+
+  - `:output_dir` - Directory to write `page-<n>-image-<i>.<ext>` files to. Without it the
+    image bytes are returned base64-encoded in each image's `"data"` key
+  - `:pages` - List of 1-based page numbers to scan (default all pages)
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with an `"images"` list giving page, size, color space,
+    filters, format, bounding box (`"corners"`) and the path or data of each image
+  - `{:error, {reason, details}}` - If the PDF cannot be read
+  """
+  @spec extract_images(String.t(), map()) :: {:ok, String.t()} | error()
+  def extract_images(path, opts \\ %{}) do
+    extract_images_nif(path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec extract_images_nif(String.t(), String.t()) :: {:ok, String.t()} | error()
+  def extract_images_nif(_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Lists the fonts used by a PDF and checks that they are embedded.
+
+  Non-embedded fonts render with substitutes on the counterparty's machine, so
+  contracts should be checked before they are sent. Fonts declared inside Form
+  XObjects are included.
+
+  ## Options
+
+  - `:pages` - List of 1-based page numbers to inspect (default all pages)
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with a `"fonts"` list (base font, subtype, encoding,
+    embedded and subset status, `/ToUnicode` presence and whether the content uses the font),
+    and `"non_embedded_fonts"` listing fonts that are neither embedded nor one of the standard 14
+  - `{:error, {reason, details}}` - If the PDF cannot be read
+  """
+  @spec font_inventory(String.t(), map()) :: {:ok, String.t()} | error()
+  def font_inventory(path, opts \\ %{}) do
+    font_inventory_nif(path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec font_inventory_nif(String.t(), String.t()) :: {:ok, String.t()} | error()
+  def font_inventory_nif(_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  @doc """
+  Checks a rendered PDF against a theme's brand palette and fonts.
+
+  Every filled or stroked path and every piece of visible text is checked, including
+  content drawn from Form XObjects.
+
+  ## Options
+
+  - `:colors` - Allowed colors as `"#rrggbb"`, `"RGB(r, g, b)"` or `"Gray(n)"`. When empty,
+    colors are not checked
+  - `:fonts` - Allowed font families, e.g. `"Roboto"` allows `"Roboto-Bold"`. When empty,
+    fonts are not checked
+  - `:tolerance` - Largest per-channel difference (0-255) still treated as a match (default `2`)
+  - `:pages` - List of 1-based page numbers to check (default all pages)
+  - `:password` - User or owner password, see [Encrypted PDFs](#module-encrypted-pdfs)
+
+  ## Returns
+
+  - `{:ok, json_string}` - JSON string with `"compliant"`, the `"violations"` (page, operator
+    index, kind, offending value and bounding box) and the colors and fonts found
+  - `{:error, {reason, details}}` - If the PDF cannot be read or a palette color is invalid
+  """
+  @spec check_brand(String.t(), map()) :: {:ok, String.t()} | error()
+  def check_brand(path, opts) do
+    check_brand_nif(path, Jason.encode!(Map.new(opts)))
+  end
+
+  @doc false
+  @spec check_brand_nif(String.t(), String.t()) :: {:ok, String.t()} | error()
+  def check_brand_nif(_path, _options_json) do
+    :erlang.nif_error(:nif_not_loaded)
+  end
+
+  defp default_font_path(font_file \\ "Roboto-Bold.ttf"),
+    do: Path.join([File.cwd!(), "priv", "wraft_files", "Roboto", font_file])
+
   @doc """
   Parses the JSON result from analyze_pdf into a map.
 
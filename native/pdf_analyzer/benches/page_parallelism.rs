@@ -0,0 +1,98 @@
+// Compares sequential and parallel page analysis on a generated report.
+//
+//     cargo bench --bench page_parallelism -- [pages] [runs]
+
+use lopdf::{dictionary, Document, Object, Stream};
+use std::time::{Duration, Instant};
+
+const DEFAULT_PAGES: u32 = 500;
+const DEFAULT_RUNS: usize = 5;
+
+// A Letter page with a table of filled cells, a paragraph of text and a
+// signature box in the colors the Typst engine looks for
+fn page_content(page: u32) -> Vec<u8> {
+    let mut content = String::new();
+    for row in 0..40 {
+        for column in 0..6 {
+            let shade = ((row * 6 + column + page) % 10) as f64 / 10.0;
+            content.push_str(&format!(
+                "q {:.1} g {} {} 90 14 re f 0.5 G 0.5 w {} {} 90 14 re S Q\n",
+                shade, 36 + column * 90, 700 - row * 15, 36 + column * 90, 700 - row * 15
+            ));
+        }
+    }
+    for line in 0..20 {
+        content.push_str(&format!("BT /F1 9 Tf 36 {} Td (Line {} of page {} in a generated report) Tj ET\n", 90 + line * 10, line, page));
+    }
+    content.push_str("q 0.8392157 1 0.9568627 rg 0 0.7215686 0.5803922 RG 1 w 350 30 200 50 re B Q\n");
+    content.into_bytes()
+}
+
+fn generate_fixture(pages: u32) -> std::path::PathBuf {
+    let mut doc = Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica" });
+    let resources_id = doc.add_object(dictionary! { "Font" => dictionary! { "F1" => font_id } });
+
+    let kids: Vec<Object> = (1..=pages)
+        .map(|page| {
+            let mut stream = Stream::new(dictionary! {}, page_content(page));
+            stream.compress().expect("compress page content");
+            let content_id = doc.add_object(stream);
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            })
+            .into()
+        })
+        .collect();
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => pages }));
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+
+    let path = std::env::temp_dir().join(format!("pdf_analyzer_bench_{}_pages.pdf", pages));
+    doc.save(&path).expect("save fixture");
+    path
+}
+
+// Median wall time of the analysis and its JSON output
+fn measure(path: &str, options_json: &str, runs: usize) -> (Duration, String) {
+    let options = serde_json::from_str(options_json).expect("valid options");
+    let mut times = Vec::with_capacity(runs);
+    let mut output = String::new();
+
+    for _ in 0..runs {
+        let start = Instant::now();
+        let result = pdf_analyzer::analyze_pdf(path, Some("typst"), &options).expect("analysis succeeds");
+        times.push(start.elapsed());
+        output = serde_json::to_string(&result).expect("serialize result");
+    }
+    times.sort();
+    (times[runs / 2], output)
+}
+
+fn main() {
+    // cargo bench passes --bench before the user arguments
+    let args: Vec<usize> = std::env::args().skip(1).filter_map(|arg| arg.parse().ok()).collect();
+    let pages = args.first().map(|&pages| pages as u32).unwrap_or(DEFAULT_PAGES);
+    let runs = args.get(1).copied().unwrap_or(DEFAULT_RUNS).max(1);
+
+    let fixture = generate_fixture(pages);
+    let path = fixture.to_string_lossy().to_string();
+    println!("{} pages, median of {} runs", pages, runs);
+
+    let (sequential, expected) = measure(&path, "{}", runs);
+    println!("{:>12}  {:>10.1?}", "sequential", sequential);
+
+    for threads in [2, 4, 8, 0] {
+        let (elapsed, output) = measure(&path, &format!("{{\"threads\": {}}}", threads), runs);
+        assert_eq!(output, expected, "parallel result with {} threads differs from the sequential one", threads);
+        let label = if threads == 0 { "threads=cpus".to_string() } else { format!("threads={}", threads) };
+        println!("{:>12}  {:>10.1?}  {:.2}x", label, elapsed, sequential.as_secs_f64() / elapsed.as_secs_f64());
+    }
+
+    let _ = std::fs::remove_file(fixture);
+}
@@ -0,0 +1,414 @@
+use lopdf::{Dictionary, Object, ObjectId, StringFormat, content::{Content, Operation}};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::common::{load_document, CornerCoordinates, Dimensions, Point, RectangleData, Transparency};
+use crate::error::PdfError;
+use crate::field_id::assign_field_ids;
+use crate::overlay::compressed_stream;
+use crate::pdf_image::decode_content;
+use crate::render::page_content;
+use crate::text::{place_glyphs, PlacedGlyph};
+
+// Longest text a wildcard of an anchor pattern matches
+const MAX_WILDCARD_CHARS: usize = 64;
+
+// Options for finding text anchors, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct AnchorOptions {
+    // Anchor patterns; `*` stands for one or more characters other than whitespace
+    pub patterns: Vec<String>,
+    // Size of the field placed at each anchor, in points
+    pub width: f64,
+    pub height: f64,
+    // Offset of the field's top left corner from the anchor's, in points, with y up
+    pub offset_x: f64,
+    pub offset_y: f64,
+    // Remove the anchor text from the page content, keeping the rest of the
+    // text in place; the result is written to the output path
+    pub erase: bool,
+    pub pages: Option<Vec<u32>>,
+}
+
+impl Default for AnchorOptions {
+    fn default() -> Self {
+        AnchorOptions {
+            patterns: vec!["{{*}}".to_string(), "[[*]]".to_string()],
+            width: 180.0,
+            height: 50.0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            erase: false,
+            pages: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AnchorResult {
+    pub total_pages: u32,
+    pub total_anchors: usize,
+    // One field per anchor, in page and content order
+    pub rectangles: Vec<RectangleData>,
+    pub erased_pages: Vec<u32>,
+}
+
+enum Token {
+    Literal(char),
+    Wildcard,
+}
+
+// A glyph of the page and the operator that showed it
+struct PageGlyph {
+    operation: usize,
+    glyph: PlacedGlyph,
+}
+
+// Find the text anchors of a document and place a field at each. With `erase`
+// the anchors are removed from the content and the document is saved to
+// `output_path`.
+pub fn find_anchors(input_path: &str, output_path: Option<&str>, options: &AnchorOptions) -> Result<AnchorResult, PdfError> {
+    let patterns = parse_patterns(&options.patterns)?;
+    if options.width <= 0.0 || options.height <= 0.0 {
+        return Err(PdfError::InvalidArgument("Anchor field width and height must be positive".to_string()));
+    }
+    let output_path = match (options.erase, output_path) {
+        (true, None) => return Err(PdfError::InvalidArgument("An output path is required to erase anchors".to_string())),
+        (erase, path) => path.filter(|_| erase),
+    };
+
+    let mut doc = load_document(input_path, None)?;
+    let pages = doc.get_pages();
+    let mut rectangles = Vec::new();
+    let mut erased: Vec<(u32, ObjectId, Vec<Operation>)> = Vec::new();
+
+    for (&page_num, &page_id) in &pages {
+        if options.pages.as_ref().is_some_and(|selected| !selected.contains(&page_num)) {
+            continue;
+        }
+        let Ok(content) = decode_content(&page_content(&doc, page_id)) else { continue };
+        let mut glyphs = Vec::new();
+        place_glyphs(&doc, page_id, &content.operations, |operation, _, placed| {
+            glyphs.extend(placed.into_iter().map(|glyph| PageGlyph { operation, glyph }));
+        });
+
+        let anchors = match_anchors(&patterns, &page_text(&glyphs));
+        if anchors.is_empty() {
+            continue;
+        }
+        for (anchor, role) in &anchors {
+            rectangles.push(anchor_field(&glyphs, anchor, role.clone(), page_num, options));
+        }
+        if output_path.is_some() {
+            let mut by_operation: HashMap<usize, Vec<&PlacedGlyph>> = HashMap::new();
+            for index in anchors.iter().flat_map(|(anchor, _)| anchor.iter().copied()) {
+                by_operation.entry(glyphs[index].operation).or_default().push(&glyphs[index].glyph);
+            }
+            erased.push((page_num, page_id, erase_glyphs(&content.operations, &by_operation)));
+        }
+    }
+
+    assign_field_ids(&doc, &mut rectangles);
+    let mut erased_pages = Vec::new();
+    if let Some(output_path) = output_path {
+        for (page_num, page_id, operations) in erased {
+            let data = Content { operations }.encode().map_err(|e| PdfError::InvalidArgument(format!("Failed to encode page {}: {}", page_num, e)))?;
+            let content_id = doc.add_object(compressed_stream(Dictionary::new(), &data));
+            let page = doc.get_dictionary_mut(page_id).map_err(|e| PdfError::CorruptXref(format!("Failed to update page: {}", e)))?;
+            page.set("Contents", Object::Reference(content_id));
+            erased_pages.push(page_num);
+        }
+        doc.save(output_path).map_err(|e| PdfError::Io(format!("Failed to save PDF {}: {}", output_path, e)))?;
+    }
+
+    Ok(AnchorResult { total_pages: pages.len() as u32, total_anchors: rectangles.len(), rectangles, erased_pages })
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Vec<Token>>, PdfError> {
+    if patterns.is_empty() {
+        return Err(PdfError::InvalidArgument("At least one anchor pattern is required".to_string()));
+    }
+    patterns
+        .iter()
+        .map(|pattern| {
+            let tokens: Vec<Token> = pattern.chars().map(|c| if c == '*' { Token::Wildcard } else { Token::Literal(c) }).collect();
+            if !tokens.iter().any(|token| matches!(token, Token::Literal(c) if !c.is_whitespace())) {
+                return Err(PdfError::InvalidArgument(format!("Anchor pattern {:?} has no literal text", pattern)));
+            }
+            Ok(tokens)
+        })
+        .collect()
+}
+
+// The page text with each character mapped to the glyph it came from.
+// Glyphs further apart than a fifth of their height are separated by a space,
+// and a newline is inserted where the text leaves the previous glyph's line,
+// so anchors never match across lines. Invisible text is included, as
+// anchors are often hidden.
+fn page_text(glyphs: &[PageGlyph]) -> Vec<(char, Option<usize>)> {
+    let mut chars = Vec::new();
+    let mut previous: Option<&PlacedGlyph> = None;
+
+    for (index, PageGlyph { glyph, .. }) in glyphs.iter().enumerate() {
+        if let Some(previous) = previous {
+            let height = glyph.bounds.3 - glyph.bounds.1;
+            let middle = (glyph.bounds.1 + glyph.bounds.3) / 2.0;
+            if middle < previous.bounds.1 || middle > previous.bounds.3 || glyph.bounds.0 < previous.bounds.0 - height {
+                chars.push(('\n', None));
+            } else if glyph.bounds.0 - previous.bounds.2 > height / 5.0 {
+                chars.push((' ', None));
+            }
+        }
+        chars.extend(glyph.text.chars().map(|c| (c, Some(index))));
+        previous = Some(glyph);
+    }
+    chars
+}
+
+// Non-overlapping anchors from left to right, as the indexes of their glyphs
+// and their role: the text of the first wildcard, or the whole anchor
+fn match_anchors(patterns: &[Vec<Token>], text: &[(char, Option<usize>)]) -> Vec<(Vec<usize>, String)> {
+    let chars: Vec<char> = text.iter().map(|(c, _)| *c).collect();
+    let mut anchors = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut captures = Vec::new();
+        let Some(end) = patterns.iter().find_map(|pattern| {
+            captures.clear();
+            match_here(pattern, &chars, start, &mut captures)
+        }) else {
+            start += 1;
+            continue;
+        };
+
+        let mut glyphs: Vec<usize> = text[start..end].iter().filter_map(|(_, glyph)| *glyph).collect();
+        glyphs.dedup();
+        let role = captures.first().cloned().unwrap_or(start..end);
+        anchors.push((glyphs, chars[role].iter().collect()));
+        start = end;
+    }
+    anchors
+}
+
+// End of the match of the pattern at `start`, with the ranges matched by its
+// wildcards. Wildcards match as few characters as possible.
+fn match_here(pattern: &[Token], text: &[char], start: usize, captures: &mut Vec<Range<usize>>) -> Option<usize> {
+    match pattern.first() {
+        None => Some(start),
+        Some(Token::Literal(c)) => {
+            if text.get(start) != Some(c) {
+                return None;
+            }
+            match_here(&pattern[1..], text, start + 1, captures)
+        },
+        Some(Token::Wildcard) => {
+            let limit = text.len().min(start + MAX_WILDCARD_CHARS);
+            let mut end = start;
+            while end < limit && !text[end].is_whitespace() {
+                end += 1;
+                captures.push(start..end);
+                if let Some(found) = match_here(&pattern[1..], text, end, captures) {
+                    return Some(found);
+                }
+                captures.pop();
+            }
+            None
+        },
+    }
+}
+
+// A field of the configured size whose top left corner is at the anchor's,
+// moved by the offset
+fn anchor_field(glyphs: &[PageGlyph], anchor: &[usize], role: String, page: u32, options: &AnchorOptions) -> RectangleData {
+    let (left, top, operation) = anchor
+        .iter()
+        .map(|&index| (glyphs[index].glyph.bounds.0, glyphs[index].glyph.bounds.3, glyphs[index].operation))
+        .fold((f64::INFINITY, f64::NEG_INFINITY, usize::MAX), |a, b| (a.0.min(b.0), a.1.max(b.1), a.2.min(b.2)));
+    let (x1, y2) = (left + options.offset_x, top + options.offset_y);
+    let (x2, y1) = (x1 + options.width, y2 - options.height);
+
+    RectangleData {
+        operation,
+        position: Point { x: x1, y: y1 },
+        dimensions: Dimensions { width: options.width, height: options.height },
+        corners: CornerCoordinates { x1, y1, x2, y2 },
+        visible_region: Some(CornerCoordinates { x1, y1, x2, y2 }),
+        hidden: false,
+        occluded: 0.0,
+        raw_shapes: 1,
+        fill_color: "Unknown".to_string(),
+        stroke_color: "Unknown".to_string(),
+        line_width: 0.0,
+        border: 0.0,
+        transparency: Transparency::default(),
+        font_name: None,
+        operation_type: "TextAnchor".to_string(),
+        fill_color_operands: Vec::new(),
+        page,
+        field_id: String::new(),
+        label: None,
+        role: Some(role),
+        destination: None,
+        marked_content: Vec::new(),
+    }
+}
+
+// Replace the text operators showing erased glyphs by TJ operators in which
+// each erased glyph becomes a position adjustment of its width, so the text
+// around it keeps its place
+fn erase_glyphs(operations: &[Operation], erased: &HashMap<usize, Vec<&PlacedGlyph>>) -> Vec<Operation> {
+    let mut result = Vec::with_capacity(operations.len());
+
+    for (index, op) in operations.iter().enumerate() {
+        let Some(glyphs) = erased.get(&index) else {
+            result.push(op.clone());
+            continue;
+        };
+        let items = match op.operator.as_str() {
+            "TJ" => op.operands.first().and_then(|obj| obj.as_array().ok()).cloned(),
+            "Tj" => op.operands.first().map(|obj| vec![obj.clone()]),
+            "'" => {
+                result.push(Operation::new("T*", vec![]));
+                op.operands.first().map(|obj| vec![obj.clone()])
+            },
+            "\"" => {
+                result.push(Operation::new("Tw", op.operands.first().cloned().into_iter().collect()));
+                result.push(Operation::new("Tc", op.operands.get(1).cloned().into_iter().collect()));
+                result.push(Operation::new("T*", vec![]));
+                op.operands.get(2).map(|obj| vec![obj.clone()])
+            },
+            _ => None,
+        };
+        match items {
+            Some(items) => result.push(Operation::new("TJ", vec![Object::Array(erase_from_items(&items, glyphs))])),
+            None => result.push(op.clone()),
+        }
+    }
+    result
+}
+
+fn erase_from_items(items: &[Object], glyphs: &[&PlacedGlyph]) -> Vec<Object> {
+    let mut result: Vec<Object> = Vec::new();
+
+    for (index, item) in items.iter().enumerate() {
+        let Object::String(bytes, format) = item else {
+            result.push(item.clone());
+            continue;
+        };
+        let mut erased: Vec<&PlacedGlyph> = glyphs.iter().filter(|glyph| glyph.item == index).copied().collect();
+        erased.sort_by_key(|glyph| glyph.bytes.start);
+
+        let mut kept_from = 0;
+        for glyph in erased {
+            push_text(&mut result, &bytes[kept_from..glyph.bytes.start.max(kept_from)], *format);
+            // A positive adjustment moves the next glyph left, so the width is negated
+            match result.last_mut() {
+                Some(Object::Real(adjustment)) => *adjustment -= glyph.displacement as f32,
+                _ => result.push(Object::Real(-glyph.displacement as f32)),
+            }
+            kept_from = glyph.bytes.end;
+        }
+        push_text(&mut result, &bytes[kept_from.min(bytes.len())..], *format);
+    }
+    result
+}
+
+fn push_text(items: &mut Vec<Object>, bytes: &[u8], format: StringFormat) {
+    if !bytes.is_empty() {
+        items.push(Object::String(bytes.to_vec(), format));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Document, Stream};
+
+    // One page showing the given text at (72, 700) in a 12 pt font whose
+    // glyphs are all 600 units wide
+    fn save_page(name: &str, text: &str) -> String {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+            "FirstChar" => 32,
+            "LastChar" => 126,
+            "Widths" => vec![Object::Integer(600); 95],
+        });
+        let content = format!("BT /F1 12 Tf 72 700 Td ({}) Tj ET", text);
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let path = std::env::temp_dir().join(format!("anchors_test_{}.pdf", name));
+        doc.save(&path).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn find(path: &str, output_path: Option<&str>, options: AnchorOptions) -> AnchorResult {
+        find_anchors(path, output_path, &options).unwrap()
+    }
+
+    #[test]
+    fn anchors_place_fields_at_their_text() {
+        let path = save_page("place", "Sign here: {{client}} and [[witness]] {{ }}");
+        let result = find(&path, None, AnchorOptions { offset_y: 10.0, ..Default::default() });
+
+        let roles: Vec<&str> = result.rectangles.iter().filter_map(|field| field.role.as_deref()).collect();
+        assert_eq!(roles, ["client", "witness"]);
+        let field = &result.rectangles[0];
+        // "{{client}}" starts after eleven 7.2 pt glyphs
+        assert!((field.corners.x1 - 151.2).abs() < 1e-6);
+        assert_eq!((field.dimensions.width, field.dimensions.height), (180.0, 50.0));
+        assert!((field.corners.y2 - field.corners.y1 - 50.0).abs() < 1e-6);
+        assert!(!field.field_id.is_empty());
+        assert!(result.erased_pages.is_empty());
+    }
+
+    #[test]
+    fn erased_anchors_keep_the_surrounding_text_in_place() {
+        let path = save_page("erase", "Sign here: {{client}} thanks");
+        let output = std::env::temp_dir().join("anchors_test_erase_out.pdf").to_string_lossy().to_string();
+        let result = find(&path, Some(&output), AnchorOptions { erase: true, ..Default::default() });
+        assert_eq!(result.total_anchors, 1);
+        assert_eq!(result.erased_pages, [1]);
+
+        assert_eq!(find(&output, None, AnchorOptions::default()).total_anchors, 0);
+        let words = |path: &str| {
+            let result = find(path, None, AnchorOptions { patterns: vec!["Sign".to_string(), "thanks".to_string()], ..Default::default() });
+            result.rectangles.iter().map(|field| (field.corners.x1, field.corners.y2)).collect::<Vec<_>>()
+        };
+        let (kept, original) = (words(&output), words(&path));
+        assert_eq!(kept.len(), 2);
+        for ((x, y), (original_x, original_y)) in kept.into_iter().zip(original) {
+            assert!((x - original_x).abs() < 1e-3 && (y - original_y).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let path = save_page("invalid", "{{client}}");
+        let cases = [
+            (AnchorOptions { patterns: vec!["*".to_string()], ..Default::default() }, None),
+            (AnchorOptions { width: 0.0, ..Default::default() }, None),
+            (AnchorOptions { erase: true, ..Default::default() }, None),
+            (AnchorOptions { patterns: Vec::new(), ..Default::default() }, Some("unused.pdf")),
+        ];
+        for (options, output_path) in cases {
+            let error = find_anchors(&path, output_path, &options).unwrap_err();
+            assert_eq!(error.reason(), "invalid_argument");
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use lopdf::Document;
+use serde::Deserialize;
+use std::collections::HashMap;
+use crate::common::{load_document, load_document_mem, AnalysisOptions, DocumentAnalysisResult};
+use crate::error::PdfError;
+use crate::parallel::{optional_pool, ordered_map};
+
+// Options for a whole batch, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BatchOptions {
+    // Worker threads shared by the documents of the batch, 0 for one per CPU;
+    // documents are analyzed one after the other when absent
+    pub threads: Option<usize>,
+}
+
+// Where the PDF of a batch item comes from
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Source<'a> {
+    Path(String),
+    Binary(&'a [u8]),
+}
+
+impl Source<'_> {
+    fn load(&self, index: usize, password: Option<&str>) -> Result<Document, PdfError> {
+        match self {
+            Source::Path(path) => load_document(path, password),
+            Source::Binary(buffer) => load_document_mem(&format!("Binary of batch item {}", index), buffer, password),
+        }
+    }
+}
+
+pub struct BatchItem<'a> {
+    pub source: Source<'a>,
+    pub engine: Option<String>,
+    // AnalysisOptions of this item as JSON
+    pub options_json: String,
+}
+
+// Analyze every item of the batch. A failing item gets its own error and does
+// not affect the others; results are in item order.
+pub fn analyze_batch(items: &[BatchItem], options: &BatchOptions) -> Result<Vec<Result<DocumentAnalysisResult, PdfError>>, PdfError> {
+    let pool = optional_pool(options.threads)?;
+    let item_options: Vec<Result<AnalysisOptions, PdfError>> = items.iter().map(|item| crate::decode_options(&item.options_json)).collect();
+
+    // Items naming the same file or binary with the same password share one
+    // parsed document
+    let mut sources: Vec<(usize, &Source, Option<&str>)> = Vec::new();
+    let mut source_index: HashMap<(&Source, Option<&str>), usize> = HashMap::new();
+    let prepared: Vec<Result<(usize, &AnalysisOptions), PdfError>> = items
+        .iter()
+        .zip(&item_options)
+        .enumerate()
+        .map(|(index, (item, options))| {
+            let options = options.as_ref().map_err(Clone::clone)?;
+            let password = options.password.as_deref();
+            let document = *source_index.entry((&item.source, password)).or_insert_with(|| {
+                sources.push((index, &item.source, password));
+                sources.len() - 1
+            });
+            Ok((document, options))
+        })
+        .collect();
+
+    let documents = ordered_map(pool.as_deref(), &sources, |&(index, source, password)| source.load(index, password));
+
+    let indices: Vec<usize> = (0..items.len()).collect();
+    Ok(ordered_map(pool.as_deref(), &indices, |&index| {
+        let (document, options) = prepared[index].clone()?;
+        let doc = documents[document].as_ref().map_err(Clone::clone)?;
+        crate::analyze_document(doc, items[index].engine.as_deref(), options)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Dictionary, Object, Stream};
+
+    fn pdf_binary(rectangles: usize) -> Vec<u8> {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        // Boxes in the fill and stroke colors of Typst signature fields
+        let content = (0..rectangles).map(|i| format!("0.83922 1 0.95687 rg 0 0.7216 0.5804 RG {} 10 50 50 re B\n", 10 + i * 100)).collect::<String>();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn item<'a>(source: Source<'a>, engine: &str, options_json: &str) -> BatchItem<'a> {
+        BatchItem { source, engine: Some(engine.to_string()), options_json: options_json.to_string() }
+    }
+
+    #[test]
+    fn failing_items_do_not_affect_the_others() {
+        let (one, two) = (pdf_binary(1), pdf_binary(2));
+        let items = vec![
+            item(Source::Binary(&one), "typst", "{}"),
+            item(Source::Path("/nonexistent/batch.pdf".to_string()), "typst", "{}"),
+            item(Source::Binary(b"not a pdf"), "typst", "{}"),
+            item(Source::Binary(&two), "typst", "{\"threads\": 1000}"),
+            item(Source::Binary(&two), "typst", "not json"),
+            item(Source::Binary(&two), "typst", "{}"),
+        ];
+
+        let results = analyze_batch(&items, &BatchOptions { threads: Some(2) }).unwrap();
+        let outcomes: Vec<Result<usize, &str>> = results.iter().map(|result| result.as_ref().map(|r| r.total_rectangles).map_err(PdfError::reason)).collect();
+        assert_eq!(outcomes, vec![Ok(1), Err("file_not_found"), Err("not_a_pdf"), Err("invalid_argument"), Err("invalid_argument"), Ok(2)]);
+    }
+
+    #[test]
+    fn invalid_batch_options_fail_the_batch() {
+        let one = pdf_binary(1);
+        let items = vec![item(Source::Binary(&one), "typst", "{}")];
+        let error = analyze_batch(&items, &BatchOptions { threads: Some(1000) }).err().unwrap();
+        assert_eq!(error.reason(), "invalid_argument");
+    }
+}
@@ -0,0 +1,287 @@
+use lopdf::content::Operation;
+use serde::{Deserialize, Serialize};
+use crate::common::load_document;
+use crate::error::PdfError;
+use crate::overlay::{add_page_resource, append_page_overlay, encode_win_ansi, page_box, text_operations, unique_resource_name, EmbeddedFont};
+use crate::stamp::anchor;
+
+// Options for Bates numbering, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct BatesOptions {
+    pub prefix: String,
+    pub suffix: String,
+    pub start: u64,
+    pub digits: usize,
+    // Footer template; `{bates}`, `{n}` and `{total}` are replaced per page
+    pub template: String,
+    pub position: String,
+    pub margin: f64,
+    pub font_path: Option<String>,
+    pub font_size: f64,
+    pub color: [f64; 3],
+}
+
+impl Default for BatesOptions {
+    fn default() -> Self {
+        BatesOptions {
+            prefix: String::new(),
+            suffix: String::new(),
+            start: 1,
+            digits: 6,
+            template: "{bates}".to_string(),
+            position: "bottom_right".to_string(),
+            margin: 24.0,
+            font_path: None,
+            font_size: 10.0,
+            color: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatesFileResult {
+    pub input_path: String,
+    pub output_path: String,
+    pub pages: u32,
+    // First and last number stamped; None for a document without pages
+    pub start_number: Option<String>,
+    pub end_number: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatesResult {
+    pub files: Vec<BatesFileResult>,
+    pub next_number: u64,
+}
+
+impl BatesOptions {
+    pub fn format_number(&self, counter: u64) -> String {
+        format!("{}{:0width$}{}", self.prefix, counter, self.suffix, width = self.digits)
+    }
+}
+
+// Stamp every page of every file with a sequential Bates number, continuing the
+// counter from one file to the next in the order given. Outputs are written
+// next to their final paths and only moved there once every file succeeded,
+// so a failure leaves no partly numbered set behind and existing outputs as
+// they were.
+pub fn bates_number(files: &[(String, String)], options: &BatesOptions) -> Result<BatesResult, PdfError> {
+    let font_path = options.font_path.as_deref().ok_or_else(|| PdfError::InvalidArgument("font_path is required for Bates numbering".to_string()))?;
+    let mut written: Vec<(String, &str)> = Vec::with_capacity(files.len());
+    let result = number_files(files, options, font_path, &mut written).and_then(|result| {
+        move_outputs(&written)?;
+        Ok(result)
+    });
+
+    if result.is_err() {
+        remove_partial(&written);
+    }
+    result
+}
+
+fn remove_partial(written: &[(String, &str)]) {
+    for (partial_path, _) in written {
+        let _ = std::fs::remove_file(partial_path);
+    }
+}
+
+// Move every finished output to its final path. Files they replace are kept
+// until the whole set is in place and put back if any move fails.
+fn move_outputs(written: &[(String, &str)]) -> Result<(), PdfError> {
+    let mut moved: Vec<(&str, Option<String>)> = Vec::with_capacity(written.len());
+    for (partial_path, output_path) in written {
+        match replace_output(partial_path, output_path) {
+            Ok(backup) => moved.push((output_path, backup)),
+            Err(e) => {
+                for (output_path, backup) in moved.iter().rev() {
+                    let _ = match backup {
+                        Some(backup) => std::fs::rename(backup, output_path),
+                        None => std::fs::remove_file(output_path),
+                    };
+                }
+                return Err(e);
+            },
+        }
+    }
+
+    for (_, backup) in moved {
+        if let Some(backup) = backup {
+            let _ = std::fs::remove_file(backup);
+        }
+    }
+    Ok(())
+}
+
+// Move one output to its final path, returning where the file it replaced was kept
+fn replace_output(partial_path: &str, output_path: &str) -> Result<Option<String>, PdfError> {
+    let backup_path = format!("{}.previous", output_path);
+    let backup = match std::fs::rename(output_path, &backup_path) {
+        Ok(()) => Some(backup_path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(PdfError::Io(format!("Failed to move {} to {}: {}", output_path, backup_path, e))),
+    };
+
+    if let Err(e) = std::fs::rename(partial_path, output_path) {
+        if let Some(backup) = &backup {
+            let _ = std::fs::rename(backup, output_path);
+        }
+        return Err(PdfError::Io(format!("Failed to move {} to {}: {}", partial_path, output_path, e)));
+    }
+    Ok(backup)
+}
+
+fn number_files<'a>(files: &'a [(String, String)], options: &BatesOptions, font_path: &str, written: &mut Vec<(String, &'a str)>) -> Result<BatesResult, PdfError> {
+    let mut counter = options.start;
+    let mut results = Vec::with_capacity(files.len());
+
+    for (input_path, output_path) in files {
+        let mut doc = load_document(input_path, None)?;
+        let font = EmbeddedFont::load(&mut doc, font_path)?;
+        let pages = doc.get_pages();
+        let total_pages = pages.len() as u32;
+        let start_counter = counter;
+
+        for (page_num, page_id) in pages {
+            let text = options
+                .template
+                .replace("{bates}", &options.format_number(counter))
+                .replace("{n}", &page_num.to_string())
+                .replace("{total}", &total_pages.to_string());
+            let encoded = encode_win_ansi(&text);
+            let width = font.text_width(&encoded, options.font_size);
+            let descent = font.descent(options.font_size);
+            let height = font.ascent(options.font_size) - descent;
+
+            let bounds = page_box(&doc, page_id);
+            let (cx, cy) = anchor(&bounds, &options.position, options.margin, width, height);
+            let baseline = [1.0, 0.0, 0.0, 1.0, cx - width / 2.0, cy - height / 2.0 - descent];
+
+            let name = unique_resource_name(&doc, page_id, b"Font", "WraftBates");
+            add_page_resource(&mut doc, page_id, b"Font", &name, font.font_id)?;
+
+            let mut operations = vec![Operation::new("q", vec![])];
+            operations.extend(text_operations(&name, options.font_size, options.color, baseline, encoded));
+            operations.push(Operation::new("Q", vec![]));
+            append_page_overlay(&mut doc, page_id, operations)?;
+
+            counter += 1;
+        }
+
+        // Recorded before saving so a partly written file is removed too
+        let partial_path = format!("{}.partial", output_path);
+        written.push((partial_path.clone(), output_path));
+        doc.save(&partial_path).map_err(|e| PdfError::Io(format!("Failed to save PDF {}: {}", output_path, e)))?;
+
+        // An empty document consumes no numbers, so its range is empty
+        let numbered = counter > start_counter;
+        results.push(BatesFileResult {
+            input_path: input_path.clone(),
+            output_path: output_path.clone(),
+            pages: total_pages,
+            start_number: numbered.then(|| options.format_number(start_counter)),
+            end_number: numbered.then(|| options.format_number(counter - 1)),
+        });
+    }
+
+    Ok(BatesResult { files: results, next_number: counter })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Document, Object, Stream};
+    use std::path::PathBuf;
+
+    const FONT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../priv/wraft_files/Roboto/Roboto-Regular.ttf");
+
+    fn pdf_with_pages(path: &PathBuf, count: usize) {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..count).map(|_| {
+            let content_id = doc.add_object(Stream::new(dictionary! {}, Vec::new()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Contents" => content_id,
+            }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count as i64 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(path).unwrap();
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bates_test_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options() -> BatesOptions {
+        BatesOptions { prefix: "ACME".to_string(), digits: 4, start: 7, font_path: Some(FONT.to_string()), ..Default::default() }
+    }
+
+    fn path(dir: &std::path::Path, name: &str) -> String {
+        dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn leftovers(dir: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn numbers_continue_across_files() {
+        let dir = scratch("continue");
+        pdf_with_pages(&dir.join("a.pdf"), 2);
+        pdf_with_pages(&dir.join("empty.pdf"), 0);
+        pdf_with_pages(&dir.join("b.pdf"), 1);
+        let files: Vec<(String, String)> = ["a", "empty", "b"].iter().map(|name| (path(&dir, &format!("{}.pdf", name)), path(&dir, &format!("{}_out.pdf", name)))).collect();
+
+        let result = bates_number(&files, &options()).unwrap();
+        assert_eq!(result.next_number, 10);
+        let ranges: Vec<_> = result.files.iter().map(|file| (file.start_number.as_deref(), file.end_number.as_deref())).collect();
+        assert_eq!(ranges, vec![(Some("ACME0007"), Some("ACME0008")), (None, None), (Some("ACME0009"), Some("ACME0009"))]);
+
+        let numbered = Document::load(dir.join("b_out.pdf")).unwrap();
+        let page_id = numbered.get_pages()[&1];
+        assert!(String::from_utf8_lossy(&numbered.get_page_content(page_id).unwrap()).contains("ACME0009"));
+        assert!(!leftovers(&dir).iter().any(|name| name.ends_with(".partial")));
+    }
+
+    #[test]
+    fn failed_save_leaves_no_outputs() {
+        let dir = scratch("save");
+        pdf_with_pages(&dir.join("a.pdf"), 1);
+        let files = vec![
+            (path(&dir, "a.pdf"), path(&dir, "a_out.pdf")),
+            (path(&dir, "a.pdf"), path(&dir, "missing/b_out.pdf")),
+        ];
+
+        assert_eq!(bates_number(&files, &options()).unwrap_err().reason(), "io_error");
+        assert_eq!(leftovers(&dir), vec!["a.pdf"]);
+    }
+
+    #[test]
+    fn failed_move_restores_earlier_outputs() {
+        let dir = scratch("move");
+        pdf_with_pages(&dir.join("a.pdf"), 1);
+        std::fs::write(dir.join("a_out.pdf"), "old a").unwrap();
+        std::fs::write(dir.join("b_out.pdf"), "old b").unwrap();
+        // The second output's file cannot be set aside, so its move fails
+        std::fs::create_dir_all(dir.join("b_out.pdf.previous/blocked")).unwrap();
+        let files = vec![
+            (path(&dir, "a.pdf"), path(&dir, "a_out.pdf")),
+            (path(&dir, "a.pdf"), path(&dir, "b_out.pdf")),
+        ];
+
+        assert_eq!(bates_number(&files, &options()).unwrap_err().reason(), "io_error");
+        assert_eq!(std::fs::read_to_string(dir.join("a_out.pdf")).unwrap(), "old a");
+        assert_eq!(std::fs::read_to_string(dir.join("b_out.pdf")).unwrap(), "old b");
+        assert_eq!(leftovers(&dir), vec!["a.pdf", "a_out.pdf", "b_out.pdf", "b_out.pdf.previous"]);
+    }
+}
@@ -0,0 +1,360 @@
+use lopdf::{Dictionary, Document, Object, content::Operation};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use crate::common::{concat_matrix, load_document, object_to_f64, operands_to_matrix, parse_color, transform_point, CornerCoordinates, GraphicsState, Matrix, IDENTITY_MATRIX};
+use crate::error::{PdfError, WarningKind};
+use crate::pdf_image::decode_content;
+use crate::security::{encryption_info, EncryptionInfo};
+use crate::render::{inherited, page_content, MAX_FORM_DEPTH};
+use crate::text::{glyph_bounds, TextLayout, TextState};
+
+// Brand rules, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct BrandOptions {
+    // Allowed colors as "#rrggbb", "RGB(r, g, b)" or "Gray(n)"; empty skips the color check
+    pub colors: Vec<String>,
+    // Allowed font families, e.g. "Roboto" allows "ABCDEF+Roboto-Bold"; empty skips the font check
+    pub fonts: Vec<String>,
+    // Largest per-channel difference (0-255) still treated as a palette match
+    pub tolerance: u8,
+    pub pages: Option<Vec<u32>>,
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+}
+
+impl Default for BrandOptions {
+    fn default() -> Self {
+        BrandOptions {
+            colors: Vec::new(),
+            fonts: Vec::new(),
+            tolerance: 2,
+            pages: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct BrandViolation {
+    pub page: u32,
+    // Index of the painting or text operator within its content stream
+    pub operation: usize,
+    pub operator: String,
+    // Form XObjects the operator is drawn from, e.g. "Fm1/Fm2"
+    pub form: Option<String>,
+    // "fill_color", "stroke_color", "text_color" or "font"
+    pub kind: String,
+    pub value: String,
+    pub corners: Option<CornerCoordinates>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BrandReport {
+    pub total_pages: u32,
+    pub compliant: bool,
+    pub total_violations: usize,
+    pub violations: Vec<BrandViolation>,
+    pub colors_used: Vec<String>,
+    pub fonts_used: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+pub fn check_brand(path: &str, options: &BrandOptions) -> Result<BrandReport, PdfError> {
+    let palette = options
+        .colors
+        .iter()
+        .map(|color| parse_color(color).ok_or_else(|| PdfError::InvalidArgument(format!("Invalid palette color: {}", color))))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let doc = load_document(path, options.password.as_deref())?;
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+
+    let mut checker = BrandChecker {
+        doc: &doc,
+        options,
+        palette,
+        page: 0,
+        layout: TextLayout::new(&doc),
+        violations: Vec::new(),
+        colors_used: BTreeSet::new(),
+        fonts_used: BTreeSet::new(),
+    };
+
+    for (page_num, page_id) in pages {
+        if options.pages.as_ref().is_some_and(|selected| !selected.contains(&page_num)) {
+            continue;
+        }
+        let content = decode_content(&page_content(&doc, page_id)).map_err(|e| PdfError::ContentStream(WarningKind::DecodeError, format!("Failed to decode content of page {}: {}", page_num, e)))?;
+        let resources = inherited(&doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok());
+
+        checker.page = page_num;
+        checker.walk(&content.operations, resources, (GraphicsState::default(), TextState::default(), IDENTITY_MATRIX), None, 0);
+    }
+
+    Ok(BrandReport {
+        total_pages,
+        compliant: checker.violations.is_empty(),
+        total_violations: checker.violations.len(),
+        violations: checker.violations,
+        colors_used: checker.colors_used.into_iter().collect(),
+        fonts_used: checker.fonts_used.into_iter().collect(),
+        encryption: encryption_info(&doc),
+    })
+}
+
+// Strip the subset tag from a base font name, e.g. "ABCDEF+Roboto-Bold" -> "Roboto-Bold"
+pub fn family_name(base_font: &str) -> &str {
+    match base_font.split_once('+') {
+        Some((tag, name)) if tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()) => name,
+        _ => base_font,
+    }
+}
+
+struct BrandChecker<'a, 'o> {
+    doc: &'a Document,
+    options: &'o BrandOptions,
+    palette: Vec<[u8; 3]>,
+    page: u32,
+    layout: TextLayout<'a>,
+    violations: Vec<BrandViolation>,
+    colors_used: BTreeSet<String>,
+    fonts_used: BTreeSet<String>,
+}
+
+impl<'a> BrandChecker<'a, '_> {
+    fn color_allowed(&self, color: &str) -> bool {
+        if self.palette.is_empty() {
+            return true;
+        }
+        // Colors in unsupported color spaces are not reported
+        let Some(rgb) = parse_color(color) else { return true };
+        let tolerance = self.options.tolerance;
+        self.palette.iter().any(|allowed| allowed.iter().zip(rgb.iter()).all(|(a, b)| a.abs_diff(*b) <= tolerance))
+    }
+
+    fn font_allowed(&self, base_font: &str) -> bool {
+        if self.options.fonts.is_empty() {
+            return true;
+        }
+        let family = family_name(base_font).to_lowercase().replace(' ', "");
+        self.options.fonts.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase().replace(' ', "");
+            family == allowed || family.starts_with(&format!("{}-", allowed)) || family.starts_with(&format!("{},", allowed))
+        })
+    }
+
+    fn base_font(&self, resources: Option<&Dictionary>, name: &[u8]) -> String {
+        resources
+            .and_then(|res| res.get(b"Font").ok())
+            .and_then(|obj| self.doc.dereference(obj).ok())
+            .and_then(|(_, obj)| obj.as_dict().ok())
+            .and_then(|fonts| fonts.get(name).ok())
+            .and_then(|obj| self.doc.dereference(obj).ok())
+            .and_then(|(_, obj)| obj.as_dict().ok())
+            .and_then(|font| font.get(b"BaseFont").ok())
+            .and_then(|obj| obj.as_name().ok())
+            .map(|base| String::from_utf8_lossy(base).to_string())
+            .unwrap_or_else(|| String::from_utf8_lossy(name).to_string())
+    }
+
+    fn check_color(&mut self, kind: &str, color: &str, op: (usize, &str), form: Option<&str>, bounds: Option<(f64, f64, f64, f64)>) {
+        self.colors_used.insert(color.to_string());
+        if !self.color_allowed(color) {
+            self.report(kind, color, op, form, bounds);
+        }
+    }
+
+    fn report(&mut self, kind: &str, value: &str, (operation, operator): (usize, &str), form: Option<&str>, bounds: Option<(f64, f64, f64, f64)>) {
+        self.violations.push(BrandViolation {
+            page: self.page,
+            operation,
+            operator: operator.to_string(),
+            form: form.map(str::to_string),
+            kind: kind.to_string(),
+            value: value.to_string(),
+            corners: bounds.map(|(x1, y1, x2, y2)| CornerCoordinates { x1, y1, x2, y2 }),
+        });
+    }
+
+    // Forms start from the graphics state at their `Do` operator
+    fn walk(&mut self, operations: &[Operation], resources: Option<&'a Dictionary>, initial: (GraphicsState, TextState, Matrix), form: Option<&str>, depth: usize) {
+        let (mut state, mut text, mut ctm) = initial;
+        let mut stack: Vec<(GraphicsState, TextState, Matrix)> = Vec::new();
+        // Bounds of the current path in default user space
+        let mut path: Option<(f64, f64, f64, f64)> = None;
+
+        for (i, op) in operations.iter().enumerate() {
+            let mut extend_path = |points: &[(f64, f64)]| {
+                for (x, y) in points {
+                    let (x, y) = transform_point(&ctm, *x, *y);
+                    path = Some(match path {
+                        Some((x1, y1, x2, y2)) => (x1.min(x), y1.min(y), x2.max(x), y2.max(y)),
+                        None => (x, y, x, y),
+                    });
+                }
+            };
+            let number = |i: usize| op.operands.get(i).map(object_to_f64).unwrap_or(0.0);
+
+            match op.operator.as_str() {
+                "q" => stack.push((state.clone(), text.clone(), ctm)),
+                "Q" => {
+                    if let Some(saved) = stack.pop() {
+                        (state, text, ctm) = saved;
+                    }
+                },
+                "cm" => {
+                    if let Some(m) = operands_to_matrix(&op.operands) {
+                        ctm = concat_matrix(&ctm, &m);
+                    }
+                },
+                "CS" | "cs" | "SC" | "sc" | "SCN" | "scn" | "RG" | "rg" | "G" | "g" | "K" | "k" => state.apply_color_operator(self.doc, op, resources),
+                "m" | "l" => extend_path(&[(number(0), number(1))]),
+                "c" => extend_path(&[(number(0), number(1)), (number(2), number(3)), (number(4), number(5))]),
+                "v" | "y" => extend_path(&[(number(0), number(1)), (number(2), number(3))]),
+                "re" => {
+                    let (x, y, w, h) = (number(0), number(1), number(2), number(3));
+                    extend_path(&[(x, y), (x + w, y), (x + w, y + h), (x, y + h)]);
+                },
+                "f" | "F" | "f*" | "S" | "s" | "B" | "B*" | "b" | "b*" | "n" => {
+                    let fills = !matches!(op.operator.as_str(), "S" | "s" | "n");
+                    let strokes = matches!(op.operator.as_str(), "S" | "s" | "B" | "B*" | "b" | "b*");
+                    if fills {
+                        let color = state.fill_color.clone();
+                        self.check_color("fill_color", &color, (i, &op.operator), form, path);
+                    }
+                    if strokes {
+                        let color = state.stroke_color.clone();
+                        self.check_color("stroke_color", &color, (i, &op.operator), form, path);
+                    }
+                    path = None;
+                },
+                "BT" | "Tf" | "Tc" | "Tw" | "Tz" | "TL" | "Ts" | "Tr" | "Td" | "TD" | "Tm" | "T*" => {
+                    self.layout.apply(op, &mut text, resources, &ctm);
+                },
+                "Tj" | "TJ" | "'" | "\"" => {
+                    let glyphs = self.layout.apply(op, &mut text, resources, &ctm);
+                    if !text.is_visible() || glyphs.is_empty() {
+                        continue;
+                    }
+                    let bounds = glyph_bounds(&glyphs);
+                    if text.fills() {
+                        let color = state.fill_color.clone();
+                        self.check_color("text_color", &color, (i, &op.operator), form, bounds);
+                    }
+                    if text.strokes() {
+                        let color = state.stroke_color.clone();
+                        self.check_color("text_color", &color, (i, &op.operator), form, bounds);
+                    }
+                    if let Some(name) = text.font_name.clone() {
+                        let base_font = self.base_font(resources, &name);
+                        self.fonts_used.insert(family_name(&base_font).to_string());
+                        if !self.font_allowed(&base_font) {
+                            self.report("font", &base_font, (i, &op.operator), form, bounds);
+                        }
+                    }
+                },
+                "Do" if depth < MAX_FORM_DEPTH => {
+                    let Some(Object::Name(name)) = op.operands.first() else { continue };
+                    let Some(Object::Stream(xobject)) = resources
+                        .and_then(|res| res.get(b"XObject").ok())
+                        .and_then(|obj| self.doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_dict().ok())
+                        .and_then(|xobjects| xobjects.get(name).ok())
+                        .and_then(|obj| self.doc.dereference(obj).ok())
+                        .map(|(_, obj)| obj)
+                    else {
+                        continue;
+                    };
+                    if !matches!(xobject.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Form")) {
+                        continue;
+                    }
+
+                    let matrix = xobject
+                        .dict
+                        .get(b"Matrix")
+                        .ok()
+                        .and_then(|obj| obj.as_array().ok())
+                        .and_then(|values| operands_to_matrix(values))
+                        .unwrap_or(IDENTITY_MATRIX);
+                    let form_resources = xobject
+                        .dict
+                        .get(b"Resources")
+                        .ok()
+                        .and_then(|obj| self.doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_dict().ok())
+                        .or(resources);
+                    let name = String::from_utf8_lossy(name);
+                    let form_path = form.map(|parent| format!("{}/{}", parent, name)).unwrap_or_else(|| name.to_string());
+
+                    let data = xobject.decompressed_content().unwrap_or_else(|_| xobject.content.clone());
+                    if let Ok(content) = decode_content(&data) {
+                        // The text object of the caller is not visible inside the form
+                        let saved = (self.layout.text_matrix, self.layout.line_matrix);
+                        let initial = (state.clone(), text.clone(), concat_matrix(&ctm, &matrix));
+                        self.walk(&content.operations, form_resources, initial, Some(&form_path), depth + 1);
+                        (self.layout.text_matrix, self.layout.line_matrix) = saved;
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    fn check(name: &str, content: &str, options: &BrandOptions) -> BrandReport {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.as_bytes().to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica" } },
+            },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let path = std::env::temp_dir().join(format!("brand_test_{}.pdf", name));
+        doc.save(&path).unwrap();
+        check_brand(path.to_str().unwrap(), options).unwrap()
+    }
+
+    #[test]
+    fn off_palette_colors_and_fonts_are_reported() {
+        let options = BrandOptions { colors: vec!["#ff0000".to_string(), "Gray(0)".to_string()], fonts: vec!["Roboto".to_string()], ..Default::default() };
+        let report = check("violations", "1 0 0 rg 0 0 10 10 re f 0 0 1 RG 20 20 10 10 re S BT /F1 12 Tf 0 g 50 50 Td (Hi) Tj ET", &options);
+
+        assert!(!report.compliant);
+        let violations: Vec<(&str, &str)> = report.violations.iter().map(|v| (v.kind.as_str(), v.operator.as_str())).collect();
+        assert_eq!(violations, vec![("stroke_color", "S"), ("font", "Tj")]);
+        let stroke = &report.violations[0];
+        assert_eq!(stroke.corners.as_ref().map(|c| (c.x1, c.y1, c.x2, c.y2)), Some((20.0, 20.0, 30.0, 30.0)));
+        assert_eq!(report.violations[1].value, "Helvetica");
+        assert_eq!(report.fonts_used, vec!["Helvetica"]);
+    }
+
+    #[test]
+    fn colors_within_tolerance_match() {
+        let options = BrandOptions { colors: vec!["#ff0000".to_string()], tolerance: 3, ..Default::default() };
+        assert!(check("tolerance", "0.99 0 0 rg 0 0 10 10 re f", &options).compliant);
+        assert!(!check("outside", "0.9 0 0 rg 0 0 10 10 re f", &options).compliant);
+    }
+
+    #[test]
+    fn subset_tags_are_ignored_in_family_names() {
+        assert_eq!(family_name("ABCDEF+Roboto-Bold"), "Roboto-Bold");
+        assert_eq!(family_name("Abcdef+Roboto"), "Abcdef+Roboto");
+    }
+}
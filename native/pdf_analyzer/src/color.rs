@@ -0,0 +1,258 @@
+use lopdf::{Dictionary, Document, Object};
+use std::sync::Arc;
+use crate::common::object_to_f64;
+use crate::function::Function;
+
+// Nesting depth up to which Indexed bases and Separation alternates are
+// resolved, so that cyclic color spaces end
+const MAX_COLOR_SPACE_DEPTH: usize = 8;
+
+// Color spaces resolved from names, resource entries or color space arrays
+#[derive(Debug, Clone)]
+pub enum ColorSpace {
+    DeviceGray,
+    DeviceRGB,
+    DeviceCMYK,
+    // ICC profiles are approximated by their alternate device space
+    IccBased(Box<ColorSpace>),
+    Indexed { base: Box<ColorSpace>, hival: u8, lookup: Vec<u8> },
+    Lab,
+    // Separation and DeviceN colorants, converted to the alternate space by the
+    // tint transform. Without a usable transform they darken like ink.
+    Separation { components: usize, alternate: Box<ColorSpace>, tint_transform: Option<Arc<Function>> },
+    Pattern,
+}
+
+impl ColorSpace {
+    // Number of color components expected by operators and images
+    pub fn components(&self) -> usize {
+        match self {
+            ColorSpace::DeviceGray => 1,
+            ColorSpace::DeviceRGB | ColorSpace::Lab => 3,
+            ColorSpace::DeviceCMYK => 4,
+            ColorSpace::IccBased(alternate) => alternate.components(),
+            ColorSpace::Indexed { .. } => 1,
+            ColorSpace::Separation { components, .. } => *components,
+            ColorSpace::Pattern => 1,
+        }
+    }
+
+    // Convert component values (0..1, or palette index for Indexed) to RGB
+    pub fn to_rgb(&self, values: &[f64]) -> [f64; 3] {
+        let get = |i: usize| values.get(i).copied().unwrap_or(0.0).clamp(0.0, 1.0);
+        match self {
+            ColorSpace::DeviceGray => [get(0); 3],
+            ColorSpace::DeviceRGB => [get(0), get(1), get(2)],
+            ColorSpace::DeviceCMYK => cmyk_to_rgb(get(0), get(1), get(2), get(3)),
+            ColorSpace::IccBased(alternate) => alternate.to_rgb(values),
+            ColorSpace::Indexed { base, hival, lookup } => {
+                let index = values.first().copied().unwrap_or(0.0).round().clamp(0.0, *hival as f64) as usize;
+                let n = base.components();
+                let entry: Vec<f64> = (0..n)
+                    .map(|i| lookup.get(index * n + i).copied().unwrap_or(0) as f64 / 255.0)
+                    .collect();
+                base.to_rgb(&entry)
+            },
+            ColorSpace::Lab => {
+                // Components are expected already normalised to L 0..100, a/b -128..127
+                lab_to_rgb(values.first().copied().unwrap_or(0.0), values.get(1).copied().unwrap_or(0.0), values.get(2).copied().unwrap_or(0.0))
+            },
+            ColorSpace::Separation { components, alternate, tint_transform } => match tint_transform {
+                Some(function) => alternate.to_rgb(&function.eval(&(0..*components).map(get).collect::<Vec<_>>())),
+                None => {
+                    let tint = (0..*components).map(get).fold(0.0, f64::max);
+                    [1.0 - tint; 3]
+                },
+            },
+            ColorSpace::Pattern => [0.5; 3],
+        }
+    }
+
+    // Color a space starts with when it is selected by CS or cs
+    pub fn initial_values(&self) -> Vec<f64> {
+        match self {
+            ColorSpace::DeviceCMYK => vec![0.0, 0.0, 0.0, 1.0],
+            ColorSpace::IccBased(alternate) => alternate.initial_values(),
+            ColorSpace::Separation { components, .. } => vec![1.0; *components],
+            _ => vec![0.0; self.components()],
+        }
+    }
+
+    // Color as reported for analyzed shapes: "Gray(n)" in gray spaces, "Pattern"
+    // for patterns, otherwise "RGB(r, g, b)", with channels truncated to 0..255
+    pub fn describe(&self, values: &[f64]) -> String {
+        match self {
+            ColorSpace::DeviceGray => format!("Gray({})", channel(values.first().copied().unwrap_or(0.0))),
+            ColorSpace::IccBased(alternate) => alternate.describe(values),
+            ColorSpace::Pattern => "Pattern".to_string(),
+            _ => {
+                let [r, g, b] = self.to_rgb(values);
+                format!("RGB({}, {}, {})", channel(r), channel(g), channel(b))
+            },
+        }
+    }
+}
+
+// A 0..1 color component as 0..255. Truncated rather than rounded, as callers
+// match the reported colors, e.g. the Typst field colors, as written.
+pub fn channel(value: f64) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+pub fn cmyk_to_rgb(c: f64, m: f64, y: f64, k: f64) -> [f64; 3] {
+    [(1.0 - c) * (1.0 - k), (1.0 - m) * (1.0 - k), (1.0 - y) * (1.0 - k)]
+}
+
+fn lab_to_rgb(l: f64, a: f64, b: f64) -> [f64; 3] {
+    // CIE L*a*b* (D50) to XYZ to linear sRGB
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let finv = |t: f64| if t > 6.0 / 29.0 { t * t * t } else { 3.0 * (6.0f64 / 29.0).powi(2) * (t - 4.0 / 29.0) };
+    let (x, y, z) = (0.9642 * finv(fx), finv(fy), 0.8249 * finv(fz));
+    let r = 3.1339 * x - 1.6169 * y - 0.4906 * z;
+    let g = -0.9788 * x + 1.9161 * y + 0.0335 * z;
+    let bl = 0.0719 * x - 0.2290 * y + 1.4052 * z;
+    let gamma = |c: f64| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    };
+    [gamma(r), gamma(g), gamma(bl)]
+}
+
+// Resolve a color space operand (name or array) against the page resources
+pub fn resolve_color_space(doc: &Document, object: &Object, resources: Option<&Dictionary>) -> ColorSpace {
+    resolve_nested(doc, object, resources, 0)
+}
+
+fn resolve_nested(doc: &Document, object: &Object, resources: Option<&Dictionary>, depth: usize) -> ColorSpace {
+    if depth > MAX_COLOR_SPACE_DEPTH {
+        return ColorSpace::DeviceRGB;
+    }
+    let object = match doc.dereference(object) {
+        Ok((_, obj)) => obj,
+        Err(_) => return ColorSpace::DeviceGray,
+    };
+
+    match object {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" | b"G" | b"CalGray" => ColorSpace::DeviceGray,
+            b"DeviceRGB" | b"RGB" | b"CalRGB" => ColorSpace::DeviceRGB,
+            b"DeviceCMYK" | b"CMYK" => ColorSpace::DeviceCMYK,
+            b"Pattern" => ColorSpace::Pattern,
+            other => {
+                let named = resources
+                    .and_then(|res| res.get(b"ColorSpace").ok())
+                    .and_then(|obj| doc.dereference(obj).ok())
+                    .and_then(|(_, obj)| obj.as_dict().ok())
+                    .and_then(|dict| dict.get(other).ok());
+                match named {
+                    // Avoid infinite recursion on self-referencing names
+                    Some(Object::Name(inner)) if inner == name => ColorSpace::DeviceGray,
+                    Some(obj) => resolve_nested(doc, obj, None, depth + 1),
+                    // Typst names its spaces /srgb and /d65gray; read them as
+                    // their device equivalents when the resource is missing
+                    None if other == b"srgb" => ColorSpace::DeviceRGB,
+                    None => ColorSpace::DeviceGray,
+                }
+            },
+        },
+        Object::Array(items) => {
+            let family = items.first().and_then(|obj| obj.as_name().ok()).unwrap_or(b"");
+            match family {
+                b"ICCBased" => {
+                    let n = items
+                        .get(1)
+                        .and_then(|obj| doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_stream().ok())
+                        .and_then(|stream| stream.dict.get(b"N").ok().and_then(|n| n.as_i64().ok()))
+                        .unwrap_or(3);
+                    ColorSpace::IccBased(Box::new(match n {
+                        1 => ColorSpace::DeviceGray,
+                        4 => ColorSpace::DeviceCMYK,
+                        _ => ColorSpace::DeviceRGB,
+                    }))
+                },
+                b"Indexed" | b"I" => {
+                    let base = items.get(1).map(|obj| resolve_nested(doc, obj, resources, depth + 1)).unwrap_or(ColorSpace::DeviceRGB);
+                    let hival = items.get(2).map(object_to_f64).unwrap_or(255.0).clamp(0.0, 255.0) as u8;
+                    let lookup = match items.get(3).and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+                        Some(Object::String(bytes, _)) => bytes.clone(),
+                        Some(Object::Stream(stream)) => stream.decompressed_content().unwrap_or_else(|_| stream.content.clone()),
+                        _ => Vec::new(),
+                    };
+                    ColorSpace::Indexed { base: Box::new(base), hival, lookup }
+                },
+                b"Separation" | b"DeviceN" => {
+                    // [/Separation name alternate tintTransform] or [/DeviceN names alternate tintTransform attributes]
+                    let components = match family {
+                        b"DeviceN" => items
+                            .get(1)
+                            .and_then(|obj| doc.dereference(obj).ok())
+                            .and_then(|(_, obj)| obj.as_array().ok())
+                            .map(|names| names.len())
+                            .unwrap_or(1),
+                        _ => 1,
+                    };
+                    let alternate = items.get(2).map(|obj| resolve_nested(doc, obj, resources, depth + 1)).unwrap_or(ColorSpace::DeviceGray);
+                    // Separation and DeviceN alternates cannot themselves be special spaces
+                    let tint_transform = match alternate {
+                        ColorSpace::Separation { .. } | ColorSpace::Indexed { .. } | ColorSpace::Pattern => None,
+                        _ => items.get(3).and_then(|obj| Function::load(doc, obj)).map(Arc::new),
+                    };
+                    ColorSpace::Separation { components, alternate: Box::new(alternate), tint_transform }
+                },
+                b"CalGray" => ColorSpace::DeviceGray,
+                b"CalRGB" => ColorSpace::DeviceRGB,
+                b"Lab" => ColorSpace::Lab,
+                b"Pattern" => ColorSpace::Pattern,
+                _ => ColorSpace::DeviceGray,
+            }
+        },
+        _ => ColorSpace::DeviceGray,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::StringFormat;
+
+    #[test]
+    fn cyclic_indexed_base_ends() {
+        let mut doc = Document::with_version("1.7");
+        let id = doc.new_object_id();
+        let lookup = Object::String(vec![255, 0, 0], StringFormat::Hexadecimal);
+        doc.objects.insert(id, Object::Array(vec!["Indexed".into(), id.into(), 0.into(), lookup]));
+
+        let space = resolve_color_space(&doc, &Object::Reference(id), None);
+        assert!(matches!(space, ColorSpace::Indexed { .. }));
+        space.to_rgb(&[0.0]);
+    }
+
+    #[test]
+    fn cyclic_alternates_end() {
+        let mut doc = Document::with_version("1.7");
+        let (first, second) = (doc.new_object_id(), doc.new_object_id());
+        doc.objects.insert(first, Object::Array(vec!["Separation".into(), "Spot".into(), second.into(), Object::Null]));
+        doc.objects.insert(second, Object::Array(vec!["DeviceN".into(), vec!["Ink".into()].into(), first.into(), Object::Null]));
+
+        let space = resolve_color_space(&doc, &Object::Reference(first), None);
+        assert!(matches!(space, ColorSpace::Separation { .. }));
+        space.to_rgb(&[1.0]);
+    }
+
+    #[test]
+    fn unresolved_typst_spaces_fall_back_to_device_spaces() {
+        let doc = Document::with_version("1.7");
+        let srgb = resolve_color_space(&doc, &"srgb".into(), None);
+        assert_eq!(srgb.describe(&[0.83922, 1.0, 0.95687]), "RGB(214, 255, 244)");
+        let gray = resolve_color_space(&doc, &"d65gray".into(), Some(&Dictionary::new()));
+        assert_eq!(gray.describe(&[0.5]), "Gray(127)");
+    }
+
+    #[test]
+    fn channels_are_truncated() {
+        assert_eq!(ColorSpace::DeviceRGB.describe(&[0.999, 0.5, 0.0]), "RGB(254, 127, 0)");
+    }
+}
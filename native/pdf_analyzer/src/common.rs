@@ -1,10 +1,45 @@
-use lopdf::{Object};
-use serde::Serialize;
+use lopdf::{Dictionary, Document, Object, content::Operation};
+use serde::{Deserialize, Serialize};
+use crate::color::{resolve_color_space, ColorSpace};
+use crate::error::{PdfError, WarningKind};
+use crate::engine::EngineStrategy;
+use crate::marked_content::MarkedContent;
+use crate::security::EncryptionInfo;
 
 // Define constants for the target colors
 pub const TARGET_FILL_COLOR: &str = "RGB(214, 255, 244)";
 pub const TARGET_STROKE_COLOR: &str = "RGB(0, 184, 148)";
 
+// Load a document, decrypting it with the user or owner password if it is encrypted.
+// Documents with an empty user password open without one.
+pub fn load_document(path: &str, password: Option<&str>) -> Result<Document, PdfError> {
+    let doc = match password {
+        Some(password) => Document::load_with_password(path, password),
+        None => Document::load(path),
+    }
+    .map_err(|e| PdfError::load_failed(path, e))?;
+
+    // lopdf leaves the document encrypted when no password was given
+    if doc.is_encrypted() {
+        return Err(PdfError::Encrypted(format!("{} is encrypted and requires a password", path)));
+    }
+    Ok(doc)
+}
+
+// Load a document held in memory, e.g. a binary passed from Elixir
+pub fn load_document_mem(source: &str, buffer: &[u8], password: Option<&str>) -> Result<Document, PdfError> {
+    let doc = match password {
+        Some(password) => Document::load_mem_with_password(buffer, password),
+        None => Document::load_mem(buffer),
+    }
+    .map_err(|e| PdfError::load_mem_failed(source, buffer, e))?;
+
+    if doc.is_encrypted() {
+        return Err(PdfError::Encrypted(format!("{} is encrypted and requires a password", source)));
+    }
+    Ok(doc)
+}
+
 // Helper function to extract f64 from Object, handling Integer and Real
 pub fn object_to_f64(obj: &Object) -> f64 {
     match obj {
@@ -14,18 +49,76 @@ pub fn object_to_f64(obj: &Object) -> f64 {
     }
 }
 
+// Transformation matrix [a, b, c, d, e, f] where:
+// x' = a*x + c*y + e
+// y' = b*x + d*y + f
+pub type Matrix = [f64; 6];
+
+pub const IDENTITY_MATRIX: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+// Read a matrix from six numeric operands, as used by `cm`, `Tm` and /Matrix
+pub fn operands_to_matrix(operands: &[Object]) -> Option<Matrix> {
+    if operands.len() < 6 {
+        return None;
+    }
+    Some([
+        object_to_f64(&operands[0]),
+        object_to_f64(&operands[1]),
+        object_to_f64(&operands[2]),
+        object_to_f64(&operands[3]),
+        object_to_f64(&operands[4]),
+        object_to_f64(&operands[5]),
+    ])
+}
+
+// Concatenate `m` onto the current matrix, as the `cm` operator does (m × current)
+pub fn concat_matrix(current: &Matrix, m: &Matrix) -> Matrix {
+    [
+        current[0] * m[0] + current[2] * m[1],
+        current[1] * m[0] + current[3] * m[1],
+        current[0] * m[2] + current[2] * m[3],
+        current[1] * m[2] + current[3] * m[3],
+        current[0] * m[4] + current[2] * m[5] + current[4],
+        current[1] * m[4] + current[3] * m[5] + current[5],
+    ]
+}
+
+pub fn transform_point(m: &Matrix, x: f64, y: f64) -> (f64, f64) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+// Axis-aligned bounds (x1, y1, x2, y2) of a rectangle after transformation
+pub fn transform_bounds(m: &Matrix, x1: f64, y1: f64, x2: f64, y2: f64) -> (f64, f64, f64, f64) {
+    let points = [
+        transform_point(m, x1, y1),
+        transform_point(m, x2, y1),
+        transform_point(m, x2, y2),
+        transform_point(m, x1, y2),
+    ];
+    points.iter().fold((f64::MAX, f64::MAX, f64::MIN, f64::MIN), |(min_x, min_y, max_x, max_y), (x, y)| {
+        (min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+    })
+}
+
 // Simplified GraphicsState
 #[derive(Debug, Clone)]
 pub struct GraphicsState {
     pub stroke_color: String,
     pub fill_color: String,
     pub line_width: f64,
-    pub border_width: f64,
     pub current_x: f64,
     pub current_y: f64,
+    // Color space names as written in the content, e.g. "srgb" or "DeviceCMYK"
     pub stroke_color_space: String,
     pub fill_color_space: String,
+    pub stroke_space: ColorSpace,
+    pub fill_space: ColorSpace,
+    pub transparency: Transparency,
     pub current_font_name: Option<Vec<u8>>,
+    pub text_render_mode: i64,
+    // Bounds of the clipping path in default user space as (left, bottom,
+    // right, top); None for no clipping
+    pub clip: Option<(f64, f64, f64, f64)>,
 }
 
 impl Default for GraphicsState {
@@ -34,16 +127,136 @@ impl Default for GraphicsState {
             stroke_color: "Gray(0)".to_string(),
             fill_color: "Gray(0)".to_string(),
             line_width: 1.0,
-            border_width: 1.0,
             current_x: 0.0,
             current_y: 0.0,
             stroke_color_space: "DeviceGray".to_string(),
             fill_color_space: "DeviceGray".to_string(),
+            stroke_space: ColorSpace::DeviceGray,
+            fill_space: ColorSpace::DeviceGray,
+            transparency: Transparency::default(),
             current_font_name: None,
+            text_render_mode: 0,
+            clip: None,
+        }
+    }
+}
+
+impl GraphicsState {
+    // Update the fill or stroke color from a color operator (CS, cs, SC, sc,
+    // SCN, scn, RG, rg, G, g, K, k). Color space names other than the device
+    // spaces are looked up in the /ColorSpace resources.
+    pub fn apply_color_operator(&mut self, doc: &Document, op: &Operation, resources: Option<&Dictionary>) {
+        let is_stroke = op.operator.starts_with(|c: char| c.is_ascii_uppercase());
+        match op.operator.as_str() {
+            "CS" | "cs" => {
+                if let Some(Object::Name(name)) = op.operands.first() {
+                    let space = resolve_color_space(doc, &op.operands[0], resources);
+                    let values = space.initial_values();
+                    self.set_color(is_stroke, String::from_utf8_lossy(name).to_string(), space, &values);
+                }
+            },
+            "SC" | "sc" | "SCN" | "scn" => {
+                // The pattern name of scn in a Pattern space is not a component
+                let values: Vec<f64> = op.operands.iter().filter(|obj| !matches!(obj, Object::Name(_))).map(object_to_f64).collect();
+                let (space, color) = match is_stroke {
+                    true => (&self.stroke_space, &mut self.stroke_color),
+                    false => (&self.fill_space, &mut self.fill_color),
+                };
+                if values.len() >= space.components() || matches!(space, ColorSpace::Pattern) {
+                    *color = space.describe(&values);
+                }
+            },
+            "RG" | "rg" | "G" | "g" | "K" | "k" => {
+                let (name, space) = match op.operator.to_ascii_lowercase().as_str() {
+                    "rg" => ("DeviceRGB", ColorSpace::DeviceRGB),
+                    "g" => ("DeviceGray", ColorSpace::DeviceGray),
+                    _ => ("DeviceCMYK", ColorSpace::DeviceCMYK),
+                };
+                let values: Vec<f64> = op.operands.iter().map(object_to_f64).collect();
+                if values.len() >= space.components() {
+                    self.set_color(is_stroke, name.to_string(), space, &values);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Apply the parameters of the named /ExtGState resource selected by `gs`
+    pub fn apply_ext_gstate(&mut self, doc: &Document, name: &[u8], resources: Option<&Dictionary>) {
+        let deref = |obj| doc.dereference(obj).map(|(_, obj)| obj).ok();
+        let Some(ext) = resources
+            .and_then(|res| res.get(b"ExtGState").ok())
+            .and_then(deref)
+            .and_then(|obj| obj.as_dict().ok())
+            .and_then(|states| states.get(name).ok())
+            .and_then(deref)
+            .and_then(|obj| obj.as_dict().ok())
+        else {
+            return;
+        };
+
+        for (key, value) in ext.iter() {
+            let Some(value) = deref(value) else { continue };
+            match key.as_slice() {
+                b"LW" => self.line_width = object_to_f64(value),
+                b"CA" => self.transparency.stroke_opacity = object_to_f64(value).clamp(0.0, 1.0),
+                b"ca" => self.transparency.fill_opacity = object_to_f64(value).clamp(0.0, 1.0),
+                b"BM" => {
+                    // An array lists blend modes in order of preference
+                    let mode = match value {
+                        Object::Array(modes) => modes.first().and_then(|obj| obj.as_name().ok()),
+                        obj => obj.as_name().ok(),
+                    };
+                    if let Some(mode) = mode {
+                        self.transparency.blend_mode = String::from_utf8_lossy(mode).to_string();
+                    }
+                },
+                b"SMask" => self.transparency.soft_mask = !matches!(value, Object::Name(none) if none == b"None"),
+                _ => {},
+            }
+        }
+    }
+
+    // Text render modes 3 and 7 neither fill nor stroke the glyphs
+    pub fn text_is_visible(&self) -> bool {
+        !matches!(self.text_render_mode, 3 | 7)
+    }
+
+    fn set_color(&mut self, is_stroke: bool, name: String, space: ColorSpace, values: &[f64]) {
+        let color = space.describe(values);
+        if is_stroke {
+            (self.stroke_color, self.stroke_color_space, self.stroke_space) = (color, name, space);
+        } else {
+            (self.fill_color, self.fill_color_space, self.fill_space) = (color, name, space);
         }
     }
 }
 
+// Parse a color written as "RGB(r, g, b)", "Gray(n)" or "#rrggbb" into RGB channels
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let color = color.trim();
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some([channel(0)?, channel(2)?, channel(4)?]);
+    }
+
+    let (name, rest) = color.split_once('(')?;
+    let values: Vec<u8> = rest
+        .split(')')
+        .next()?
+        .split(',')
+        .map(|value| value.trim().parse::<f64>().ok().map(|v| v.clamp(0.0, 255.0) as u8))
+        .collect::<Option<_>>()?;
+    match (name.trim(), values.as_slice()) {
+        ("RGB", [r, g, b]) => Some([*r, *g, *b]),
+        ("Gray", [gray]) => Some([*gray; 3]),
+        _ => None,
+    }
+}
+
 // Structs for JSON serialization
 #[derive(Serialize, Debug)]
 pub struct OperatorCount {
@@ -51,13 +264,13 @@ pub struct OperatorCount {
     pub occurrences: usize,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CornerCoordinates {
     pub x1: f64,
     pub y1: f64,
@@ -65,28 +278,67 @@ pub struct CornerCoordinates {
     pub y2: f64,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct Dimensions {
     pub width: f64,
     pub height: f64,
 }
 
-#[derive(Serialize, Debug)]
+// Constant alpha, blend mode and soft mask from the ExtGState, reported with
+// every rectangle
+#[derive(Serialize, Debug, Clone)]
+pub struct Transparency {
+    pub fill_opacity: f64,
+    pub stroke_opacity: f64,
+    pub blend_mode: String,
+    // A soft mask was active; its effect on visibility is not evaluated
+    pub soft_mask: bool,
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Transparency { fill_opacity: 1.0, stroke_opacity: 1.0, blend_mode: "Normal".to_string(), soft_mask: false }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct RectangleData {
     pub operation: usize,
     pub position: Point,
     pub dimensions: Dimensions,
     pub corners: CornerCoordinates,
+    // Part of the rectangle inside the clipping path and crop box; None when
+    // it is clipped away entirely
+    pub visible_region: Option<CornerCoordinates>,
+    // Clipped away or in optional content that is off by default; such
+    // rectangles are only reported with the include_hidden option
+    pub hidden: bool,
+    // Fraction of the visible region that opaque content painted later covers
+    pub occluded: f64,
+    // Shapes in the content that were merged into this field
+    pub raw_shapes: usize,
     pub fill_color: String,
     pub stroke_color: String,
     pub line_width: f64,
+    // Width of the stroked outline, 0 for rectangles that are only filled
     pub border: f64,
+    #[serde(flatten)]
+    pub transparency: Transparency,
     pub font_name: Option<String>,
     pub operation_type: String,
     pub fill_color_operands: Vec<f64>,
     pub page: u32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub fill_color_override: Option<String>,
+    // Identifier that survives template changes elsewhere in the document
+    pub field_id: String,
+    // Text next to or inside the rectangle, e.g. "Client signature"
+    pub label: Option<String>,
+    // Signer role and name of the named destination declaring the field,
+    // e.g. "client" for "wraft-sign-client"
+    pub role: Option<String>,
+    pub destination: Option<String>,
+    // Marked-content sequences the rectangle was drawn in, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub marked_content: Vec<MarkedContent>,
 }
 
 #[derive(Serialize, Debug)]
@@ -95,6 +347,12 @@ pub struct SummaryStats {
     pub rectangle_operations: usize,
     pub path_operations: usize,
     pub text_operations: usize,
+    // Text-showing operators in render mode 3 or 7, which paint nothing
+    pub invisible_text_operations: usize,
+    // Rectangles painted first over the whole MediaBox, left out as page background
+    pub background_rectangles: usize,
+    // BMC, BDC, EMC, MP and DP
+    pub marked_content_operations: usize,
     pub other_operations: usize,
 }
 
@@ -108,6 +366,48 @@ pub struct PageAnalysisResult {
     pub most_common_operators: Vec<OperatorCount>,
     #[serde(rename = "Rectangles")]
     pub rectangles: Vec<RectangleData>,
+    // Corners of the page background rectangles counted in the summary
+    #[serde(rename = "Background")]
+    pub background: Vec<CornerCoordinates>,
+}
+
+// Size and decode status of one content stream, reported in diagnostic mode
+#[derive(Serialize, Debug)]
+pub struct ContentStreamInfo {
+    pub page: u32,
+    // None for content streams stored directly in the page dictionary
+    pub object_id: Option<(u32, u16)>,
+    pub raw_size: usize,
+    pub decoded_size: Option<usize>,
+    pub operations: Option<usize>,
+    pub error: Option<String>,
+}
+
+// A content stream that was skipped, so its page may be missing rectangles,
+// or a detected rectangle that may not be usable as a field
+#[derive(Serialize, Debug, Clone)]
+pub struct AnalysisWarning {
+    pub page: u32,
+    pub object_id: Option<(u32, u16)>,
+    pub kind: AnalysisWarningKind,
+    pub message: String,
+}
+
+// Both kinds serialize to their own snake_case name
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum AnalysisWarningKind {
+    ContentStream(WarningKind),
+    Field(FieldWarningKind),
+}
+
+// Why a detected rectangle may not be usable as a field. Unlike content
+// stream warnings these never become errors in strict mode.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldWarningKind {
+    // Content painted later covers the rectangle
+    OccludedField,
 }
 
 #[derive(Serialize, Debug)]
@@ -115,6 +415,50 @@ pub struct DocumentAnalysisResult {
     pub total_pages: u32,
     pub total_rectangles: usize,
     pub rectangles: Vec<RectangleData>,
+    pub warnings: Vec<AnalysisWarning>,
+    // Per-page statistics and content streams, only present in diagnostic mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pages: Option<Vec<PageAnalysisResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_streams: Option<Vec<ContentStreamInfo>>,
+    // Cipher and permissions of encrypted documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+    // Scans that produced the rectangles, set by `analyze_document`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<EngineStrategy>,
+}
+
+// Options for `analyze_pdf`, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct AnalysisOptions {
+    // Include per-page operator statistics and content stream details
+    pub diagnostics: bool,
+    // Fail with the first warning instead of skipping unreadable content streams
+    pub strict: bool,
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+    // Worker threads for page analysis, 0 for one per CPU; pages are analyzed
+    // sequentially when absent
+    pub threads: Option<usize>,
+    // Prefix of named destinations declaring fields, "wraft-sign-" when absent
+    pub destination_prefix: Option<String>,
+    // Leave out rectangles that paint nothing visible: fully transparent or
+    // not painted at all
+    pub skip_invisible: bool,
+    // Also report rectangles that are clipped away or in hidden optional content
+    pub include_hidden: bool,
+    // Fraction of its visible area, between 0 and 1, from which later content
+    // occludes a rectangle. Occluded rectangles are reported in the warnings.
+    pub occlusion_threshold: Option<f64>,
+    // Leave out occluded rectangles instead of warning about them
+    pub drop_occluded: bool,
+    // Overlap, as intersection over union, from which rectangles on a page are
+    // merged into one field; DEFAULT_MERGE_IOU when absent
+    pub merge_iou: Option<f64>,
+    // Report every shape on its own instead of merging duplicates
+    pub keep_duplicates: bool,
 }
 
 // Add this struct for LaTeX engine output
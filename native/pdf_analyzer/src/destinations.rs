@@ -0,0 +1,282 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashMap;
+use crate::common::{object_to_f64, CornerCoordinates, Dimensions, Point, RectangleData, Transparency};
+use crate::overlay::page_box;
+
+// Destinations named `<prefix><role>` declare signature fields
+pub const DEFAULT_DESTINATION_PREFIX: &str = "wraft-sign-";
+
+// A destination this close to a rectangle, in points, names that rectangle
+const DESTINATION_DISTANCE: f64 = 24.0;
+// Size of the field placed at a destination that names no rectangle
+const DESTINATION_FIELD_WIDTH: f64 = 180.0;
+const DESTINATION_FIELD_HEIGHT: f64 = 50.0;
+// Maximum depth of the /Dests name tree
+const MAX_NAME_TREE_DEPTH: usize = 32;
+
+// Target of a named destination in default user space
+struct NamedDestination {
+    name: String,
+    page: u32,
+    left: Option<f64>,
+    top: Option<f64>,
+    // The area of /FitR destinations as (left, bottom, right, top)
+    area: Option<(f64, f64, f64, f64)>,
+}
+
+// Attach the role and name of each `<prefix><role>` destination to the
+// rectangle at that spot, or add a field for destinations with no rectangle
+// nearby. Destinations come from the /Dests name tree of the /Names
+// dictionary and the older /Dests dictionary of the catalog.
+pub fn attach_named_fields(doc: &Document, rectangles: &mut Vec<RectangleData>, prefix: &str) {
+    let destinations = named_destinations(doc, prefix);
+    let pages = doc.get_pages();
+    let mut claimed = vec![false; rectangles.len()];
+
+    for destination in destinations {
+        let role = destination.name[prefix.len()..].to_string();
+        let nearest = rectangles
+            .iter()
+            .enumerate()
+            .filter(|(index, rect)| !claimed.get(*index).copied().unwrap_or(true) && rect.page == destination.page)
+            .map(|(index, rect)| (index, distance_to(&destination, &rect.corners)))
+            .filter(|(_, distance)| *distance <= DESTINATION_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let rect = match nearest {
+            Some((index, _)) => {
+                claimed[index] = true;
+                &mut rectangles[index]
+            },
+            None => {
+                rectangles.push(destination_field(doc, &destination, pages.get(&destination.page).copied()));
+                rectangles.last_mut().expect("field was just added")
+            },
+        };
+        rect.role = Some(role);
+        rect.destination = Some(destination.name);
+    }
+}
+
+// Distance from the destination to the rectangle: its area for /FitR
+// destinations, otherwise the point at its top left
+fn distance_to(destination: &NamedDestination, corners: &CornerCoordinates) -> f64 {
+    let (x1, y1, x2, y2) = (corners.x1.min(corners.x2), corners.y1.min(corners.y2), corners.x1.max(corners.x2), corners.y1.max(corners.y2));
+    let (left, bottom, right, top) = destination.area.unwrap_or_else(|| {
+        let (x, y) = (destination.left.unwrap_or(x1), destination.top.unwrap_or(y2));
+        (x, y, x, y)
+    });
+    let dx = (left - x2).max(x1 - right).max(0.0);
+    let dy = (bottom - y2).max(y1 - top).max(0.0);
+    dx.hypot(dy)
+}
+
+// A field of the default size hanging below the destination point
+fn destination_field(doc: &Document, destination: &NamedDestination, page_id: Option<ObjectId>) -> RectangleData {
+    let (x1, y1, x2, y2) = destination.area.unwrap_or_else(|| {
+        let media_box = page_id.map(|id| page_box(doc, id));
+        let left = destination.left.or(media_box.as_ref().map(|b| b.llx)).unwrap_or(0.0);
+        let top = destination.top.or(media_box.as_ref().map(|b| b.ury)).unwrap_or(0.0);
+        (left, top - DESTINATION_FIELD_HEIGHT, left + DESTINATION_FIELD_WIDTH, top)
+    });
+
+    RectangleData {
+        operation: 0,
+        position: Point { x: x1, y: y1 },
+        dimensions: Dimensions { width: x2 - x1, height: y2 - y1 },
+        corners: CornerCoordinates { x1, y1, x2, y2 },
+        visible_region: Some(CornerCoordinates { x1, y1, x2, y2 }),
+        hidden: false,
+        occluded: 0.0,
+        raw_shapes: 1,
+        fill_color: "Unknown".to_string(),
+        stroke_color: "Unknown".to_string(),
+        line_width: 0.0,
+        border: 0.0,
+        transparency: Transparency::default(),
+        font_name: None,
+        operation_type: "NamedDestination".to_string(),
+        fill_color_operands: Vec::new(),
+        page: destination.page,
+        field_id: String::new(),
+        label: None,
+        role: None,
+        destination: None,
+        marked_content: Vec::new(),
+    }
+}
+
+fn deref<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    doc.dereference(object).map(|(_, obj)| obj).unwrap_or(object)
+}
+
+// Destinations whose names start with the prefix, sorted by name
+fn named_destinations(doc: &Document, prefix: &str) -> Vec<NamedDestination> {
+    let Ok(catalog) = doc.catalog() else { return Vec::new() };
+    let page_numbers: HashMap<ObjectId, u32> = doc.get_pages().into_iter().map(|(number, id)| (id, number)).collect();
+    let mut entries: Vec<(String, &Object)> = Vec::new();
+
+    if let Some(tree) = catalog
+        .get(b"Names")
+        .ok()
+        .and_then(|obj| deref(doc, obj).as_dict().ok())
+        .and_then(|names| names.get(b"Dests").ok())
+        .and_then(|obj| deref(doc, obj).as_dict().ok())
+    {
+        name_tree_entries(doc, tree, 0, &mut entries);
+    }
+    if let Some(dests) = catalog.get(b"Dests").ok().and_then(|obj| deref(doc, obj).as_dict().ok()) {
+        for (key, value) in dests.iter() {
+            entries.push((String::from_utf8_lossy(key).to_string(), value));
+        }
+    }
+
+    let mut destinations: Vec<NamedDestination> = entries
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(prefix) && name.len() > prefix.len())
+        .filter_map(|(name, value)| resolve(doc, &page_numbers, name, value))
+        .collect();
+    destinations.sort_by(|a, b| a.name.cmp(&b.name));
+    // A name in both places resolves the same way
+    destinations.dedup_by(|a, b| a.name == b.name);
+    destinations
+}
+
+// Key and value pairs of a name tree's /Names arrays, following /Kids
+fn name_tree_entries<'a>(doc: &'a Document, node: &'a Dictionary, depth: usize, entries: &mut Vec<(String, &'a Object)>) {
+    if depth > MAX_NAME_TREE_DEPTH {
+        return;
+    }
+    if let Some(names) = node.get(b"Names").ok().and_then(|obj| deref(doc, obj).as_array().ok()) {
+        for pair in names.chunks_exact(2) {
+            if let Object::String(key, _) = deref(doc, &pair[0]) {
+                entries.push((String::from_utf8_lossy(key).to_string(), &pair[1]));
+            }
+        }
+    }
+    if let Some(kids) = node.get(b"Kids").ok().and_then(|obj| deref(doc, obj).as_array().ok()) {
+        for kid in kids {
+            if let Ok(kid) = deref(doc, kid).as_dict() {
+                name_tree_entries(doc, kid, depth + 1, entries);
+            }
+        }
+    }
+}
+
+// A destination is an array [page /Type params...], possibly wrapped in a
+// dictionary under /D
+fn resolve(doc: &Document, page_numbers: &HashMap<ObjectId, u32>, name: String, value: &Object) -> Option<NamedDestination> {
+    let array = match deref(doc, value) {
+        Object::Array(array) => array,
+        Object::Dictionary(dict) => deref(doc, dict.get(b"D").ok()?).as_array().ok()?,
+        _ => return None,
+    };
+    let page = *page_numbers.get(&array.first()?.as_reference().ok()?)?;
+    // Null parameters keep the viewer's current value
+    let number = |index: usize| match array.get(index).map(|obj| deref(doc, obj)) {
+        Some(obj @ (Object::Integer(_) | Object::Real(_))) => Some(object_to_f64(obj)),
+        _ => None,
+    };
+
+    let (left, top, area) = match array.get(1).and_then(|obj| obj.as_name().ok()) {
+        Some(b"XYZ") => (number(2), number(3), None),
+        Some(b"FitH") | Some(b"FitBH") => (None, number(2), None),
+        Some(b"FitV") | Some(b"FitBV") => (number(2), None, None),
+        Some(b"FitR") => {
+            let (left, bottom, right, top) = (number(2)?, number(3)?, number(4)?, number(5)?);
+            (Some(left.min(right)), Some(top.max(bottom)), Some((left.min(right), bottom.min(top), left.max(right), bottom.max(top))))
+        },
+        _ => (None, None, None),
+    };
+    // Whole-page destinations such as /Fit do not point at a field
+    if left.is_none() && top.is_none() {
+        return None;
+    }
+    Some(NamedDestination { name, page, left, top, area })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::AnalysisOptions;
+    use lopdf::{dictionary, Stream};
+
+    // A page with one Typst signature box and the given named destinations,
+    // the first half in a /Names tree leaf and the rest in the catalog /Dests
+    fn document(destinations: Vec<(&str, Vec<Object>)>) -> Document {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), b"0.83922 1 0.95687 rg 0 0.7216 0.5804 RG 200 300 150 40 re B".to_vec()));
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+
+        let mut names = Vec::new();
+        let mut dests = Dictionary::new();
+        let half = destinations.len().div_ceil(2);
+        for (index, (name, params)) in destinations.into_iter().enumerate() {
+            let target: Vec<Object> = std::iter::once(page_id.into()).chain(params).collect();
+            if index < half {
+                names.push(Object::string_literal(name));
+                names.push(target.into());
+            } else {
+                dests.set(name, target);
+            }
+        }
+        let leaf_id = doc.add_object(dictionary! { "Names" => names });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "Names" => dictionary! { "Dests" => dictionary! { "Kids" => vec![leaf_id.into()] } },
+            "Dests" => dests,
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc
+    }
+
+    fn xyz(left: i64, top: i64) -> Vec<Object> {
+        vec!["XYZ".into(), left.into(), top.into(), Object::Null]
+    }
+
+    fn fields(doc: &Document) -> Vec<RectangleData> {
+        crate::analyze_document(doc, Some("typst"), &AnalysisOptions::default()).unwrap().rectangles
+    }
+
+    #[test]
+    fn destinations_name_the_rectangle_they_point_at() {
+        let doc = document(vec![("wraft-sign-client", xyz(205, 345)), ("unrelated", xyz(200, 340))]);
+        let fields = fields(&doc);
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].role.as_deref(), Some("client"));
+        assert_eq!(fields[0].destination.as_deref(), Some("wraft-sign-client"));
+    }
+
+    #[test]
+    fn destinations_without_a_rectangle_add_a_field() {
+        let fit_r = vec!["FitR".into(), 400.into(), 100.into(), 500.into(), 60.into()];
+        let doc = document(vec![("wraft-sign-witness", xyz(50, 600)), ("wraft-sign-manager", vec!["Fit".into()]), ("wraft-sign-notary", fit_r)]);
+        let fields = fields(&doc);
+        let added: Vec<(Option<&str>, [f64; 4])> = fields
+            .iter()
+            .filter(|field| field.operation_type == "NamedDestination")
+            .map(|field| (field.role.as_deref(), [field.corners.x1, field.corners.y1, field.corners.x2, field.corners.y2]))
+            .collect();
+        // Destinations are visited by name; /Fit names no spot on the page
+        assert_eq!(added, vec![(Some("notary"), [400.0, 60.0, 500.0, 100.0]), (Some("witness"), [50.0, 550.0, 230.0, 600.0])]);
+        assert!(fields.iter().any(|field| field.operation_type != "NamedDestination" && field.role.is_none()));
+    }
+
+    #[test]
+    fn prefix_is_configurable() {
+        let doc = document(vec![("sig:client", xyz(205, 345))]);
+        let mut rectangles = fields(&doc);
+        assert!(rectangles[0].role.is_none());
+        attach_named_fields(&doc, &mut rectangles, "sig:");
+        assert_eq!(rectangles[0].role.as_deref(), Some("client"));
+    }
+}
@@ -0,0 +1,324 @@
+use lopdf::{decode_text_string, Dictionary, Document, Object, ObjectId};
+use rustler::Resource;
+use serde::{Deserialize, Serialize};
+use crate::common::{object_to_f64, CornerCoordinates};
+use crate::overlay::page_box;
+use crate::render::{inherited, visible_box};
+use crate::security::{encryption_info, EncryptionInfo};
+
+// A parsed document kept between NIF calls, so one parse serves many queries
+pub struct PdfDocument {
+    pub doc: Document,
+}
+
+#[rustler::resource_impl]
+impl Resource for PdfDocument {}
+
+// Options for `open_pdf`, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct OpenOptions {
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+}
+
+// Options for page-selective queries on an open document
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PageOptions {
+    pub pages: Option<Vec<u32>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FormField {
+    pub page: u32,
+    pub object_id: (u32, u16),
+    // Fully qualified name, e.g. "signatures.client"
+    pub name: Option<String>,
+    // Tx, Btn, Ch or Sig, inherited from parent fields
+    pub field_type: Option<String>,
+    pub value: Option<String>,
+    pub flags: i64,
+    pub corners: CornerCoordinates,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldsResult {
+    pub total_fields: usize,
+    pub fields: Vec<FormField>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageText {
+    pub page: u32,
+    pub text: String,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TextResult {
+    pub total_pages: u32,
+    pub pages: Vec<PageText>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Metadata {
+    pub version: String,
+    pub total_pages: u32,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    // PDF date strings, e.g. "D:20240131120000+05'30'"
+    pub creation_date: Option<String>,
+    pub modification_date: Option<String>,
+    pub has_acroform: bool,
+    pub encryption: Option<EncryptionInfo>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageGeometry {
+    pub page: u32,
+    // [llx, lly, urx, ury] in default user space
+    pub media_box: [f64; 4],
+    pub crop_box: [f64; 4],
+    pub rotation: i64,
+    // Size of the crop box as displayed, after rotation
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GeometryResult {
+    pub total_pages: u32,
+    pub pages: Vec<PageGeometry>,
+}
+
+fn deref<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    doc.dereference(object).map(|(_, obj)| obj).unwrap_or(object)
+}
+
+fn text_entry(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<String> {
+    match deref(doc, dict.get(key).ok()?) {
+        Object::Name(name) => Some(String::from_utf8_lossy(name).to_string()),
+        object => decode_text_string(object).ok(),
+    }
+}
+
+// Widget annotations of interactive form fields, in page order
+pub fn form_fields(doc: &Document) -> FieldsResult {
+    let mut fields = Vec::new();
+
+    for (page_num, page_id) in doc.get_pages() {
+        let Some(annots) = doc
+            .get_dictionary(page_id)
+            .ok()
+            .and_then(|page| page.get(b"Annots").ok())
+            .and_then(|obj| deref(doc, obj).as_array().ok())
+        else {
+            continue;
+        };
+
+        for annot in annots {
+            let Ok(id) = annot.as_reference() else { continue };
+            let Ok(dict) = doc.get_dictionary(id) else { continue };
+            if !matches!(dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Widget")) {
+                continue;
+            }
+            let Some(rect) = dict.get(b"Rect").ok().and_then(|obj| deref(doc, obj).as_array().ok()).filter(|rect| rect.len() == 4) else {
+                continue;
+            };
+            let (x0, y0, x1, y1) = (object_to_f64(&rect[0]), object_to_f64(&rect[1]), object_to_f64(&rect[2]), object_to_f64(&rect[3]));
+
+            fields.push(FormField {
+                page: page_num,
+                object_id: id,
+                name: field_name(doc, dict),
+                field_type: inherited_field_entry(doc, dict, b"FT").and_then(|obj| obj.as_name().ok()).map(|name| String::from_utf8_lossy(name).to_string()),
+                value: inherited_field_entry(doc, dict, b"V").and_then(|obj| match obj {
+                    Object::Name(name) => Some(String::from_utf8_lossy(name).to_string()),
+                    other => decode_text_string(other).ok(),
+                }),
+                flags: inherited_field_entry(doc, dict, b"Ff").and_then(|obj| obj.as_i64().ok()).unwrap_or(0),
+                corners: CornerCoordinates { x1: x0.min(x1), y1: y0.min(y1), x2: x0.max(x1), y2: y0.max(y1) },
+            });
+        }
+    }
+
+    FieldsResult { total_fields: fields.len(), fields }
+}
+
+// Maximum depth of the field hierarchy followed through /Parent
+const MAX_FIELD_DEPTH: usize = 32;
+
+// Field entries may be set on any ancestor in the field hierarchy
+fn inherited_field_entry<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut current = dict;
+    for _ in 0..MAX_FIELD_DEPTH {
+        if let Ok(value) = current.get(key) {
+            return Some(deref(doc, value));
+        }
+        current = current.get(b"Parent").ok().and_then(|obj| deref(doc, obj).as_dict().ok())?;
+    }
+    None
+}
+
+// Join the partial names /T of the field and its ancestors with periods
+fn field_name(doc: &Document, dict: &Dictionary) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut current = Some(dict);
+    for _ in 0..MAX_FIELD_DEPTH {
+        let Some(field) = current else { break };
+        if let Some(name) = text_entry(doc, field, b"T") {
+            parts.push(name);
+        }
+        current = field.get(b"Parent").ok().and_then(|obj| deref(doc, obj).as_dict().ok());
+    }
+    parts.reverse();
+    (!parts.is_empty()).then(|| parts.join("."))
+}
+
+pub fn page_text(doc: &Document, options: &PageOptions) -> TextResult {
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+    let pages = pages
+        .into_keys()
+        .filter(|page_num| options.pages.as_ref().is_none_or(|selected| selected.contains(page_num)))
+        .map(|page_num| match doc.extract_text(&[page_num]) {
+            Ok(text) => PageText { page: page_num, text, error: None },
+            Err(e) => PageText { page: page_num, text: String::new(), error: Some(e.to_string()) },
+        })
+        .collect();
+
+    TextResult { total_pages, pages }
+}
+
+pub fn metadata(doc: &Document) -> Metadata {
+    let info = doc.trailer.get(b"Info").ok().and_then(|obj| deref(doc, obj).as_dict().ok());
+    let entry = |key: &[u8]| info.and_then(|info| text_entry(doc, info, key));
+    let has_acroform = doc
+        .catalog()
+        .ok()
+        .is_some_and(|catalog| catalog.has(b"AcroForm"));
+
+    Metadata {
+        version: doc.version.clone(),
+        total_pages: doc.get_pages().len() as u32,
+        title: entry(b"Title"),
+        author: entry(b"Author"),
+        subject: entry(b"Subject"),
+        keywords: entry(b"Keywords"),
+        creator: entry(b"Creator"),
+        producer: entry(b"Producer"),
+        creation_date: entry(b"CreationDate"),
+        modification_date: entry(b"ModDate"),
+        has_acroform,
+        encryption: encryption_info(doc),
+    }
+}
+
+pub fn page_geometry(doc: &Document, options: &PageOptions) -> GeometryResult {
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+    let pages = pages
+        .into_iter()
+        .filter(|(page_num, _)| options.pages.as_ref().is_none_or(|selected| selected.contains(page_num)))
+        .map(|(page_num, page_id)| geometry(doc, page_num, page_id))
+        .collect();
+
+    GeometryResult { total_pages, pages }
+}
+
+fn geometry(doc: &Document, page_num: u32, page_id: ObjectId) -> PageGeometry {
+    let media_box = page_box(doc, page_id);
+    let crop_box = visible_box(doc, page_id);
+    let rotation = (inherited(doc, page_id, b"Rotate").map(object_to_f64).unwrap_or(0.0) as i64).rem_euclid(360);
+    let (width, height) = match rotation {
+        90 | 270 => (crop_box.height(), crop_box.width()),
+        _ => (crop_box.width(), crop_box.height()),
+    };
+
+    PageGeometry {
+        page: page_num,
+        media_box: [media_box.llx, media_box.lly, media_box.urx, media_box.ury],
+        crop_box: [crop_box.llx, crop_box.lly, crop_box.urx, crop_box.ury],
+        rotation,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, StringFormat};
+
+    // A rotated page with one signature widget whose field name and type live on its parent
+    fn form_document() -> Document {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let parent_id = doc.add_object(dictionary! {
+            "T" => Object::string_literal("signatures"),
+            "FT" => "Sig",
+        });
+        let widget_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "Parent" => parent_id,
+            "T" => Object::string_literal("client"),
+            "Rect" => vec![300.into(), 120.into(), 100.into(), 80.into()],
+        });
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "CropBox" => vec![10.into(), 20.into(), 410.into(), 320.into()],
+            "Annots" => vec![widget_id.into()],
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Rotate" => 90,
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "AcroForm" => dictionary! {} });
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::String(b"\xfe\xff\x00N\x00D\x00A".to_vec(), StringFormat::Hexadecimal),
+            "Producer" => Object::string_literal("Typst 0.12"),
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.trailer.set("Info", info_id);
+        doc
+    }
+
+    #[test]
+    fn widgets_take_names_and_types_from_their_parents() {
+        let result = form_fields(&form_document());
+        assert_eq!(result.total_fields, 1);
+        let field = &result.fields[0];
+        assert_eq!(field.name.as_deref(), Some("signatures.client"));
+        assert_eq!(field.field_type.as_deref(), Some("Sig"));
+        assert_eq!((field.corners.x1, field.corners.y1, field.corners.x2, field.corners.y2), (100.0, 80.0, 300.0, 120.0));
+    }
+
+    #[test]
+    fn metadata_decodes_text_strings() {
+        let metadata = metadata(&form_document());
+        assert_eq!(metadata.title.as_deref(), Some("NDA"));
+        assert_eq!(metadata.producer.as_deref(), Some("Typst 0.12"));
+        assert!(metadata.has_acroform && metadata.encryption.is_none());
+    }
+
+    #[test]
+    fn geometry_follows_inherited_boxes_and_rotation() {
+        let result = page_geometry(&form_document(), &PageOptions::default());
+        let page = &result.pages[0];
+        assert_eq!(page.media_box, [0.0, 0.0, 612.0, 792.0]);
+        assert_eq!(page.crop_box, [10.0, 20.0, 410.0, 320.0]);
+        assert_eq!((page.rotation, page.width, page.height), (90, 300.0, 400.0));
+        assert!(page_geometry(&form_document(), &PageOptions { pages: Some(vec![2]) }).pages.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use lopdf::Document;
+use serde::Serialize;
+use crate::brand::family_name;
+use crate::document::{form_fields, metadata};
+use crate::error::PdfError;
+
+// Pages whose fonts are checked for typesetter defaults
+const FONT_SAMPLE_PAGES: usize = 3;
+
+// Base font prefixes of the Computer Modern and Latin Modern families TeX uses
+const TEX_FONTS: [&str; 8] = ["CMR", "CMMI", "CMSY", "CMBX", "CMTI", "LMRoman", "LMSans", "SFRM"];
+// Typst's default text fonts
+const TYPST_FONTS: [&str; 2] = ["Libertinus", "LinLibertine"];
+// Words in /Producer or /Creator naming a TeX engine or driver, e.g.
+// "pdfTeX-1.40.25" or "dvipdfmx (20230313)"
+const TEX_PRODUCERS: [&str; 7] = ["tex", "pdftex", "xetex", "luatex", "latex", "dvipdfm", "dvipdfmx"];
+
+// Field scans an `analyze_pdf` engine argument asks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    // Rectangles painted in the signature colors
+    Typst,
+    // Signature form fields
+    Latex,
+    // Choose from the document itself
+    Auto,
+}
+
+impl Engine {
+    // No engine means typst, as before auto detection existed
+    pub fn parse(engine: Option<&str>) -> Result<Engine, PdfError> {
+        match engine.unwrap_or("typst") {
+            "typst" => Ok(Engine::Typst),
+            "latex" => Ok(Engine::Latex),
+            "auto" => Ok(Engine::Auto),
+            other => Err(PdfError::InvalidArgument(format!("Unknown engine {:?}, expected \"typst\", \"latex\" or \"auto\"", other))),
+        }
+    }
+}
+
+// The scans that ran and, in auto mode, why
+#[derive(Serialize, Debug, Clone)]
+pub struct EngineStrategy {
+    // "typst", "latex" or "auto"
+    pub requested: String,
+    // "typst" and/or "latex"
+    pub scans: Vec<String>,
+    pub reasons: Vec<String>,
+}
+
+impl EngineStrategy {
+    pub fn runs_typst(&self) -> bool {
+        self.scans.iter().any(|scan| scan == "typst")
+    }
+
+    pub fn runs_latex(&self) -> bool {
+        self.scans.iter().any(|scan| scan == "latex")
+    }
+}
+
+pub fn strategy(doc: &Document, engine: Engine) -> EngineStrategy {
+    match engine {
+        Engine::Typst => EngineStrategy { requested: "typst".to_string(), scans: vec!["typst".to_string()], reasons: Vec::new() },
+        Engine::Latex => EngineStrategy { requested: "latex".to_string(), scans: vec!["latex".to_string()], reasons: Vec::new() },
+        Engine::Auto => detect(doc),
+    }
+}
+
+// Pick the scans from the producing application, signature form fields and
+// the fonts of the first pages. Documents that give no hint get both scans.
+fn detect(doc: &Document) -> EngineStrategy {
+    let mut reasons = Vec::new();
+    let mut typst = false;
+    let mut tex = false;
+
+    let info = metadata(doc);
+    for (key, value) in [("Producer", &info.producer), ("Creator", &info.creator)] {
+        let Some(value) = value else { continue };
+        let lower = value.to_lowercase();
+        if lower.contains("typst") {
+            typst = true;
+            reasons.push(format!("{} is {:?}", key, value));
+        } else if is_tex_producer(&lower) {
+            tex = true;
+            reasons.push(format!("{} is {:?}", key, value));
+        }
+    }
+
+    let signature_fields = if info.has_acroform {
+        form_fields(doc).fields.iter().filter(|field| field.field_type.as_deref() == Some("Sig")).count()
+    } else {
+        0
+    };
+    if signature_fields > 0 {
+        reasons.push(format!("AcroForm has {} signature field(s)", signature_fields));
+    }
+
+    if !typst && !tex {
+        for (_, page_id) in doc.get_pages().into_iter().take(FONT_SAMPLE_PAGES) {
+            for font in doc.get_page_fonts(page_id).unwrap_or_default().values() {
+                let Ok(base_font) = font.get(b"BaseFont").and_then(|obj| obj.as_name()) else { continue };
+                let name = String::from_utf8_lossy(base_font);
+                let family = family_name(&name);
+                if !tex && TEX_FONTS.iter().any(|prefix| family.starts_with(prefix)) {
+                    tex = true;
+                    reasons.push(format!("Uses TeX font {}", family));
+                } else if !typst && TYPST_FONTS.iter().any(|prefix| family.starts_with(prefix)) {
+                    typst = true;
+                    reasons.push(format!("Uses Typst default font {}", family));
+                }
+            }
+        }
+    }
+
+    let mut scans = Vec::new();
+    if typst || !tex {
+        scans.push("typst".to_string());
+    }
+    if tex || signature_fields > 0 || !typst {
+        scans.push("latex".to_string());
+    }
+    if !typst && !tex {
+        reasons.push("Producing engine unknown".to_string());
+    }
+
+    EngineStrategy { requested: "auto".to_string(), scans, reasons }
+}
+
+// Whether a lowercased producer names a TeX engine. Whole words are compared so
+// that names merely containing "tex", such as "TextEdit", do not count.
+fn is_tex_producer(producer: &str) -> bool {
+    producer
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|word| TEX_PRODUCERS.contains(&word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_tex_producer;
+
+    #[test]
+    fn recognizes_tex_producers() {
+        for producer in ["pdftex-1.40.25", "xetex 0.999995", "luatex-1.17.0", "latex with hyperref", "dvipdfmx (20230313)", "miktex pdftex", "tex output 2023.01.01:1200"] {
+            assert!(is_tex_producer(producer), "{}", producer);
+        }
+    }
+
+    #[test]
+    fn ignores_words_containing_tex() {
+        for producer in ["textedit", "cortex", "apache tika text extractor", "libreoffice writer (text)", "macos quartz pdfcontext", "microsoft word"] {
+            assert!(!is_tex_producer(producer), "{}", producer);
+        }
+    }
+}
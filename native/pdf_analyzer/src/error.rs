@@ -0,0 +1,135 @@
+use serde::Serialize;
+
+// Why a content stream could not be analyzed
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    UnsupportedFilter,
+    CorruptStream,
+    DecodeError,
+}
+
+// Errors returned to Elixir, encoded as `{:error, {reason, details}}` so callers
+// can branch on the cause
+#[derive(Debug, Clone)]
+pub enum PdfError {
+    FileNotFound(String),
+    // The file does not start with a %PDF header
+    NotAPdf(String),
+    // The document needs a password that was not given
+    Encrypted(String),
+    // The cross-reference table, trailer or object structure cannot be read
+    CorruptXref(String),
+    UnsupportedFeature(String),
+    // The input is too large to process within the configured limits
+    BudgetExceeded(String),
+    InvalidArgument(String),
+    // Reading an auxiliary file or writing output failed
+    Io(String),
+    // A content stream could not be read
+    ContentStream(WarningKind, String),
+}
+
+impl PdfError {
+    // Atom name of the error reason
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PdfError::FileNotFound(_) => "file_not_found",
+            PdfError::NotAPdf(_) => "not_a_pdf",
+            PdfError::Encrypted(_) => "encrypted",
+            PdfError::CorruptXref(_) => "corrupt_xref",
+            PdfError::UnsupportedFeature(_) => "unsupported_feature",
+            PdfError::BudgetExceeded(_) => "budget_exceeded",
+            PdfError::InvalidArgument(_) => "invalid_argument",
+            PdfError::Io(_) => "io_error",
+            PdfError::ContentStream(WarningKind::UnsupportedFilter, _) => "unsupported_filter",
+            PdfError::ContentStream(WarningKind::CorruptStream, _) => "corrupt_stream",
+            PdfError::ContentStream(WarningKind::DecodeError, _) => "decode_error",
+        }
+    }
+
+    pub fn details(&self) -> &str {
+        match self {
+            PdfError::FileNotFound(details)
+            | PdfError::NotAPdf(details)
+            | PdfError::Encrypted(details)
+            | PdfError::CorruptXref(details)
+            | PdfError::UnsupportedFeature(details)
+            | PdfError::BudgetExceeded(details)
+            | PdfError::InvalidArgument(details)
+            | PdfError::Io(details)
+            | PdfError::ContentStream(_, details) => details,
+        }
+    }
+
+    // Failure to read a file the caller pointed us at
+    pub fn read_failed(path: &str, error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            PdfError::FileNotFound(format!("No such file: {}", path))
+        } else {
+            PdfError::Io(format!("Failed to read {}: {}", path, error))
+        }
+    }
+
+    // Classify an error from loading a document with lopdf
+    pub fn load_failed(path: &str, error: lopdf::Error) -> Self {
+        PdfError::classify_load(path, error, || has_pdf_header(path))
+    }
+
+    // Same for a document loaded from memory, named `source` in messages
+    pub fn load_mem_failed(source: &str, buffer: &[u8], error: lopdf::Error) -> Self {
+        PdfError::classify_load(source, error, || starts_with_pdf_header(buffer))
+    }
+
+    fn classify_load(path: &str, error: lopdf::Error, has_header: impl FnOnce() -> bool) -> Self {
+        use lopdf::Error;
+
+        match error {
+            Error::IO(error) => PdfError::read_failed(path, error),
+            Error::Parse(_) if !has_header() => PdfError::NotAPdf(format!("{} is not a PDF file", path)),
+            Error::InvalidPassword => PdfError::Encrypted(format!("Wrong password for {}", path)),
+            Error::Decryption(_) => PdfError::Encrypted(format!("Failed to decrypt {}: {}", path, error)),
+            Error::UnsupportedSecurityHandler(_) | Error::Unimplemented(_) => {
+                PdfError::UnsupportedFeature(format!("Failed to open {}: {}", path, error))
+            },
+            error => PdfError::CorruptXref(format!("Failed to open {}: {}", path, error)),
+        }
+    }
+}
+
+// Viewers accept the %PDF- marker anywhere in the first 1024 bytes
+fn has_pdf_header(path: &str) -> bool {
+    use std::io::Read;
+
+    let mut start = Vec::new();
+    std::fs::File::open(path).and_then(|file| file.take(1024).read_to_end(&mut start)).is_ok() && starts_with_pdf_header(&start)
+}
+
+fn starts_with_pdf_header(buffer: &[u8]) -> bool {
+    buffer[..buffer.len().min(1024)].windows(5).any(|window| window == b"%PDF-")
+}
+
+impl std::fmt::Display for PdfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.details())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{load_document, load_document_mem};
+
+    fn reason_for_file(name: &str, contents: &[u8]) -> &'static str {
+        let path = std::env::temp_dir().join(format!("error_test_{}", name));
+        std::fs::write(&path, contents).unwrap();
+        load_document(path.to_str().unwrap(), None).unwrap_err().reason()
+    }
+
+    #[test]
+    fn load_failures_are_classified() {
+        assert_eq!(load_document("/nonexistent/input.pdf", None).unwrap_err().reason(), "file_not_found");
+        assert_eq!(reason_for_file("text.pdf", b"just some text"), "not_a_pdf");
+        assert_eq!(reason_for_file("truncated.pdf", b"%PDF-1.7\n1 0 obj\n<< /Type /Catalog"), "corrupt_xref");
+        assert_eq!(load_document_mem("upload", b"\x89PNG\r\n", None).unwrap_err().reason(), "not_a_pdf");
+    }
+}
@@ -0,0 +1,275 @@
+use lopdf::Document;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::common::{CornerCoordinates, RectangleData};
+use crate::text::{page_text_runs, TextRun};
+
+// Text closer to a rectangle than this counts as its label, in points
+const LABEL_DISTANCE: f64 = 24.0;
+const MAX_LABEL_CHARS: usize = 64;
+
+// Options for `match_fields`, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MatchOptions {
+    // Largest move of a field's center, in points, for fields matched by
+    // position alone
+    pub max_distance: f64,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions { max_distance: 36.0 }
+    }
+}
+
+// A field of one build: an analyzed rectangle or a stored signature position
+#[derive(Deserialize, Debug)]
+pub struct FieldRef {
+    #[serde(default)]
+    pub field_id: Option<String>,
+    pub page: u32,
+    #[serde(alias = "coordinates")]
+    pub corners: CornerCoordinates,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldMatch {
+    // Indexes into the previous and current field lists
+    pub previous: usize,
+    pub current: usize,
+    pub previous_id: Option<String>,
+    pub current_id: Option<String>,
+    // "id", "label" or "geometry"
+    pub method: &'static str,
+    // Distance between the field centers, in points
+    pub distance: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MatchResult {
+    pub matches: Vec<FieldMatch>,
+    pub unmatched_previous: Vec<usize>,
+    pub unmatched_current: Vec<usize>,
+}
+
+// Give every rectangle a label and an ID hashed from its page, its corners
+// rounded to whole points and its label. The operator index is left out, so
+// the ID only changes when the field itself moves or is relabeled.
+pub fn assign_field_ids(doc: &Document, rectangles: &mut [RectangleData]) {
+    let pages = doc.get_pages();
+    let mut runs: HashMap<u32, Vec<TextRun>> = HashMap::new();
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+
+    for rect in rectangles.iter_mut() {
+        let page_runs = runs
+            .entry(rect.page)
+            .or_insert_with(|| pages.get(&rect.page).map(|&page_id| page_text_runs(doc, page_id)).unwrap_or_default());
+        let (x1, y1, x2, y2) = bounds(&rect.corners);
+        rect.label = nearby_label(page_runs, (x1, y1, x2, y2));
+
+        // Fields declared by a named destination keep its name wherever they move
+        let key = match &rect.destination {
+            Some(destination) => format!("destination|{}", destination),
+            None => format!(
+                "{}|{}|{}|{}|{}|{}",
+                rect.page,
+                x1.round(),
+                y1.round(),
+                x2.round(),
+                y2.round(),
+                rect.label.as_deref().unwrap_or_default()
+            ),
+        };
+        let hash = fnv1a(key.as_bytes());
+        // Identical rectangles are told apart by their order
+        let count = seen.entry(hash).or_insert(0);
+        *count += 1;
+        rect.field_id = match *count {
+            1 => format!("{:016x}", hash),
+            n => format!("{:016x}-{}", hash, n),
+        };
+    }
+}
+
+// 64-bit FNV-1a, which unlike the std hashers is the same in every build
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Corners normalized to (left, bottom, right, top)
+fn bounds(corners: &CornerCoordinates) -> (f64, f64, f64, f64) {
+    (corners.x1.min(corners.x2), corners.y1.min(corners.y2), corners.x1.max(corners.x2), corners.y1.max(corners.y2))
+}
+
+fn box_distance(a: (f64, f64, f64, f64), b: (f64, f64, f64, f64)) -> f64 {
+    let dx = (a.0 - b.2).max(b.0 - a.2).max(0.0);
+    let dy = (a.1 - b.3).max(b.1 - a.3).max(0.0);
+    dx.hypot(dy)
+}
+
+fn center_distance(a: (f64, f64, f64, f64), b: (f64, f64, f64, f64)) -> f64 {
+    ((a.0 + a.2) / 2.0 - (b.0 + b.2) / 2.0).hypot((a.1 + a.3) / 2.0 - (b.1 + b.3) / 2.0)
+}
+
+// The line of text nearest to the rectangle: the closest run plus the runs
+// continuing it to the left and right on the same baseline
+fn nearby_label(runs: &[TextRun], rect: (f64, f64, f64, f64)) -> Option<String> {
+    let nearest = runs
+        .iter()
+        .filter(|run| !run.text.trim().is_empty())
+        .map(|run| (box_distance(run.bounds, rect), run))
+        .filter(|(distance, _)| *distance <= LABEL_DISTANCE)
+        .min_by(|a, b| a.0.total_cmp(&b.0))?
+        .1;
+
+    let (_, bottom, _, top) = nearest.bounds;
+    let height = top - bottom;
+    let mut line: Vec<&TextRun> = runs
+        .iter()
+        .filter(|run| {
+            let middle = (run.bounds.1 + run.bounds.3) / 2.0;
+            middle > bottom && middle < top && !run.text.trim().is_empty()
+        })
+        .collect();
+    line.sort_by(|a, b| a.bounds.0.total_cmp(&b.bounds.0));
+
+    // Keep the runs connected to the nearest one by gaps of at most two glyph heights
+    let index = line.iter().position(|run| std::ptr::eq(*run, nearest))?;
+    let mut start = index;
+    while start > 0 && line[start].bounds.0 - line[start - 1].bounds.2 <= 2.0 * height {
+        start -= 1;
+    }
+    let mut end = index;
+    while end + 1 < line.len() && line[end + 1].bounds.0 - line[end].bounds.2 <= 2.0 * height {
+        end += 1;
+    }
+
+    let text = line[start..=end].iter().map(|run| run.text.as_str()).collect::<Vec<_>>().join(" ");
+    let label: String = text.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(MAX_LABEL_CHARS).collect();
+    (!label.is_empty()).then_some(label)
+}
+
+// Pair the fields of two builds of a document: first by equal IDs, then by
+// equal labels (nearest first, preferring the same page), then by position on
+// the same page within `max_distance`
+pub fn match_fields(previous: &[FieldRef], current: &[FieldRef], options: &MatchOptions) -> MatchResult {
+    let mut previous_free = vec![true; previous.len()];
+    let mut current_free = vec![true; current.len()];
+    let mut matches = Vec::new();
+
+    let mut pair = |method: &'static str, accept: &dyn Fn(&FieldRef, &FieldRef) -> bool| {
+        let mut candidates = Vec::new();
+        for (p, old) in previous.iter().enumerate().filter(|(p, _)| previous_free[*p]) {
+            for (c, new) in current.iter().enumerate().filter(|(c, _)| current_free[*c]) {
+                if accept(old, new) {
+                    let distance = center_distance(bounds(&old.corners), bounds(&new.corners));
+                    candidates.push((old.page.abs_diff(new.page), distance, p, c));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(&b.2)).then(a.3.cmp(&b.3)));
+
+        for (_, distance, p, c) in candidates {
+            if previous_free[p] && current_free[c] {
+                previous_free[p] = false;
+                current_free[c] = false;
+                matches.push(FieldMatch {
+                    previous: p,
+                    current: c,
+                    previous_id: previous[p].field_id.clone(),
+                    current_id: current[c].field_id.clone(),
+                    method,
+                    distance,
+                });
+            }
+        }
+    };
+
+    pair("id", &|old, new| old.field_id.is_some() && old.field_id == new.field_id);
+    pair("label", &|old, new| old.label.is_some() && old.label == new.label);
+    pair("geometry", &|old, new| {
+        let (a, b) = (bounds(&old.corners), bounds(&new.corners));
+        old.page == new.page
+            && center_distance(a, b) <= options.max_distance
+            && ((a.2 - a.0) - (b.2 - b.0)).abs() <= options.max_distance
+            && ((a.3 - a.1) - (b.3 - b.1)).abs() <= options.max_distance
+    });
+
+    matches.sort_by_key(|m| m.previous);
+    MatchResult {
+        unmatched_previous: (0..previous.len()).filter(|&p| previous_free[p]).collect(),
+        unmatched_current: (0..current.len()).filter(|&c| current_free[c]).collect(),
+        matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::AnalysisOptions;
+    use lopdf::{dictionary, Dictionary, Object, Stream};
+
+    // A Typst signature box at (x, y) with a label to its left, after `preamble`
+    fn template(preamble: &str, x: f64, y: f64) -> Document {
+        let content = format!(
+            "{}\nBT /F1 10 Tf {} {} Td (Client signature) Tj ET\n0.83922 1 0.95687 rg 0 0.7216 0.5804 RG {} {} 150 40 re B",
+            preamble, x - 90.0, y + 15.0, x, y
+        );
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica" } },
+            },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc
+    }
+
+    fn fields(doc: &Document) -> Vec<RectangleData> {
+        crate::analyze_document(doc, Some("typst"), &AnalysisOptions::default()).unwrap().rectangles
+    }
+
+    fn field_ref(rect: &RectangleData) -> FieldRef {
+        FieldRef { field_id: Some(rect.field_id.clone()), page: rect.page, corners: rect.corners.clone(), label: rect.label.clone() }
+    }
+
+    #[test]
+    fn ids_survive_content_changes_elsewhere() {
+        let original = fields(&template("", 200.0, 300.0));
+        let edited = fields(&template("0 0 1 rg 20 700 300 20 re f q 1 0 0 1 5 5 cm Q", 200.0, 300.0));
+        assert_eq!((original.len(), edited.len()), (1, 1));
+        assert_eq!(original[0].label.as_deref(), Some("Client signature"));
+        assert_ne!(original[0].operation, edited[0].operation);
+        assert_eq!(original[0].field_id, edited[0].field_id);
+    }
+
+    #[test]
+    fn moved_fields_are_matched_by_label() {
+        let original = fields(&template("", 200.0, 300.0));
+        let shifted = fields(&template("", 200.0, 220.0));
+        assert_ne!(original[0].field_id, shifted[0].field_id);
+
+        let result = match_fields(&[field_ref(&original[0])], &[field_ref(&shifted[0])], &MatchOptions::default());
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!((result.matches[0].method, result.matches[0].distance), ("label", 80.0));
+    }
+
+    #[test]
+    fn unlabeled_fields_are_matched_by_nearby_position() {
+        let field = |x: f64| FieldRef { field_id: None, page: 1, corners: CornerCoordinates { x1: x, y1: 100.0, x2: x + 150.0, y2: 140.0 }, label: None };
+        let result = match_fields(&[field(100.0), field(400.0)], &[field(110.0), field(300.0)], &MatchOptions::default());
+        assert_eq!(result.matches.iter().map(|m| (m.previous, m.current, m.method)).collect::<Vec<_>>(), vec![(0, 0, "geometry")]);
+        assert_eq!((result.unmatched_previous, result.unmatched_current), (vec![1], vec![1]));
+    }
+}
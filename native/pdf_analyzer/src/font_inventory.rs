@@ -0,0 +1,329 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, content::Operation};
+use serde::{Deserialize, Serialize};
+use crate::common::load_document;
+use crate::error::{PdfError, WarningKind};
+use crate::pdf_image::decode_content;
+use crate::security::{encryption_info, EncryptionInfo};
+use crate::render::{inherited, page_content, MAX_FORM_DEPTH};
+
+// The standard 14 fonts every viewer must provide, so they may be left unembedded
+const STANDARD_FONTS: [&str; 14] = [
+    "Courier", "Courier-Bold", "Courier-Oblique", "Courier-BoldOblique",
+    "Helvetica", "Helvetica-Bold", "Helvetica-Oblique", "Helvetica-BoldOblique",
+    "Times-Roman", "Times-Bold", "Times-Italic", "Times-BoldItalic",
+    "Symbol", "ZapfDingbats",
+];
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FontInventoryOptions {
+    pub pages: Option<Vec<u32>>,
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FontInfo {
+    pub page: u32,
+    // Name of the font in the /Font resource dictionary
+    pub resource_name: String,
+    // Form XObjects the font is declared in, e.g. "Fm1/Fm2"; None for page fonts
+    pub form: Option<String>,
+    pub object_id: Option<(u32, u16)>,
+    pub base_font: String,
+    pub subtype: String,
+    // CIDFontType0 or CIDFontType2 for Type0 fonts
+    pub descendant_subtype: Option<String>,
+    pub encoding: Option<String>,
+    pub embedded: bool,
+    // FontFile, FontFile2 or FontFile3 (with its /Subtype)
+    pub font_file: Option<String>,
+    pub subset: bool,
+    pub standard_font: bool,
+    pub has_to_unicode: bool,
+    // Whether the content selects the font with `Tf`
+    pub used: bool,
+    pub text_operations: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FontInventoryResult {
+    pub total_pages: u32,
+    pub total_fonts: usize,
+    pub fonts: Vec<FontInfo>,
+    // Base fonts without an embedded font program, excluding the standard 14
+    pub non_embedded_fonts: Vec<String>,
+    pub all_embedded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+pub fn font_inventory(path: &str, options: &FontInventoryOptions) -> Result<FontInventoryResult, PdfError> {
+    let doc = load_document(path, options.password.as_deref())?;
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+    let mut fonts = Vec::new();
+
+    for (page_num, page_id) in pages {
+        if options.pages.as_ref().is_some_and(|selected| !selected.contains(&page_num)) {
+            continue;
+        }
+
+        let content = decode_content(&page_content(&doc, page_id)).map_err(|e| PdfError::ContentStream(WarningKind::DecodeError, format!("Failed to decode content of page {}: {}", page_num, e)))?;
+        let resources = inherited(&doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok());
+
+        let mut walker = FontWalker { doc: &doc, page: page_num, fonts: Vec::new() };
+        walker.declare(resources, None);
+        walker.walk(&content.operations, resources, None, 0);
+        fonts.extend(walker.fonts);
+    }
+
+    let mut non_embedded_fonts: Vec<String> = fonts
+        .iter()
+        .filter(|font| !font.embedded && !font.standard_font)
+        .map(|font| font.base_font.clone())
+        .collect();
+    non_embedded_fonts.sort();
+    non_embedded_fonts.dedup();
+
+    Ok(FontInventoryResult {
+        total_pages,
+        total_fonts: fonts.len(),
+        all_embedded: fonts.iter().all(|font| font.embedded),
+        non_embedded_fonts,
+        fonts,
+        encryption: encryption_info(&doc),
+    })
+}
+
+struct FontWalker<'a> {
+    doc: &'a Document,
+    page: u32,
+    fonts: Vec<FontInfo>,
+}
+
+impl<'a> FontWalker<'a> {
+    fn deref(&self, object: &'a Object) -> &'a Object {
+        self.doc.dereference(object).map(|(_, obj)| obj).unwrap_or(object)
+    }
+
+    fn position(&self, form: Option<&str>, name: &[u8]) -> Option<usize> {
+        let name = String::from_utf8_lossy(name);
+        self.fonts.iter().position(|font| font.form.as_deref() == form && font.resource_name == name)
+    }
+
+    // Record every font in a resource dictionary
+    fn declare(&mut self, resources: Option<&'a Dictionary>, form: Option<&str>) {
+        let Some(entries) = resources
+            .and_then(|res| res.get(b"Font").ok())
+            .and_then(|obj| self.deref(obj).as_dict().ok())
+        else {
+            return;
+        };
+
+        for (name, entry) in entries.iter() {
+            if self.position(form, name).is_some() {
+                continue;
+            }
+            let Ok(dict) = self.deref(entry).as_dict() else { continue };
+            let info = describe_font(self.doc, dict, entry.as_reference().ok(), self.page, &String::from_utf8_lossy(name), form);
+            self.fonts.push(info);
+        }
+    }
+
+    fn walk(&mut self, operations: &[Operation], resources: Option<&'a Dictionary>, form: Option<&str>, depth: usize) {
+        // The current font is part of the graphics state, so q/Q restore it
+        let mut current: Option<usize> = None;
+        let mut stack: Vec<Option<usize>> = Vec::new();
+
+        for op in operations {
+            match op.operator.as_str() {
+                "q" => stack.push(current),
+                "Q" => current = stack.pop().unwrap_or(current),
+                "Tf" => {
+                    if let Some(Object::Name(name)) = op.operands.first() {
+                        current = self.position(form, name);
+                        if let Some(index) = current {
+                            self.fonts[index].used = true;
+                        }
+                    }
+                },
+                "Tj" | "TJ" | "'" | "\"" => {
+                    if let Some(index) = current {
+                        self.fonts[index].text_operations += 1;
+                    }
+                },
+                "Do" if depth < MAX_FORM_DEPTH => {
+                    let Some(Object::Name(name)) = op.operands.first() else { continue };
+                    let Some(Object::Stream(xobject)) = resources
+                        .and_then(|res| res.get(b"XObject").ok())
+                        .and_then(|obj| self.deref(obj).as_dict().ok())
+                        .and_then(|xobjects| xobjects.get(name).ok())
+                        .map(|obj| self.deref(obj))
+                    else {
+                        continue;
+                    };
+                    if !matches!(xobject.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Form")) {
+                        continue;
+                    }
+
+                    // Forms without their own resources use the ones of the caller
+                    let (form_resources, form_path) = match xobject.dict.get(b"Resources").ok().and_then(|obj| self.deref(obj).as_dict().ok()) {
+                        Some(own) => {
+                            let name = String::from_utf8_lossy(name);
+                            (Some(own), Some(form.map(|parent| format!("{}/{}", parent, name)).unwrap_or_else(|| name.to_string())))
+                        },
+                        None => (resources, form.map(str::to_string)),
+                    };
+                    self.declare(form_resources, form_path.as_deref());
+
+                    let data = xobject.decompressed_content().unwrap_or_else(|_| xobject.content.clone());
+                    if let Ok(content) = decode_content(&data) {
+                        self.walk(&content.operations, form_resources, form_path.as_deref(), depth + 1);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+fn name_string(object: Option<&Object>) -> Option<String> {
+    object.and_then(|obj| obj.as_name().ok()).map(|name| String::from_utf8_lossy(name).to_string())
+}
+
+fn describe_font(doc: &Document, dict: &Dictionary, object_id: Option<ObjectId>, page: u32, resource_name: &str, form: Option<&str>) -> FontInfo {
+    let deref = |obj: &Object| doc.dereference(obj).map(|(_, obj)| obj.clone()).ok();
+    let subtype = name_string(dict.get(b"Subtype").ok()).unwrap_or_else(|| "Unknown".to_string());
+    let base_font = name_string(dict.get(b"BaseFont").ok()).unwrap_or_else(|| "Unnamed".to_string());
+
+    // Type0 fonts keep their descriptor on the descendant CIDFont
+    let descendant = dict
+        .get(b"DescendantFonts")
+        .ok()
+        .and_then(deref)
+        .and_then(|obj| obj.as_array().ok().and_then(|fonts| fonts.first().cloned()))
+        .and_then(|obj| deref(&obj))
+        .and_then(|obj| obj.as_dict().ok().cloned());
+    let descriptor = descendant
+        .as_ref()
+        .unwrap_or(dict)
+        .get(b"FontDescriptor")
+        .ok()
+        .and_then(deref)
+        .and_then(|obj| obj.as_dict().ok().cloned());
+
+    let font_file = descriptor.as_ref().and_then(|descriptor| {
+        [&b"FontFile"[..], b"FontFile2", b"FontFile3"].iter().find_map(|key| {
+            let stream = descriptor.get(key).ok().and_then(deref)?;
+            let key = String::from_utf8_lossy(key).to_string();
+            Some(match name_string(stream.as_stream().ok().and_then(|s| s.dict.get(b"Subtype").ok())) {
+                Some(program) => format!("{} ({})", key, program),
+                None => key,
+            })
+        })
+    });
+
+    let encoding = match dict.get(b"Encoding").ok().and_then(deref) {
+        Some(Object::Name(name)) => Some(String::from_utf8_lossy(&name).to_string()),
+        Some(Object::Dictionary(encoding)) => {
+            let base = name_string(encoding.get(b"BaseEncoding").ok()).unwrap_or_else(|| "StandardEncoding".to_string());
+            Some(if encoding.has(b"Differences") { format!("{} with Differences", base) } else { base })
+        },
+        // Embedded CMaps are identified by name
+        Some(Object::Stream(cmap)) => Some(name_string(cmap.dict.get(b"CMapName").ok()).unwrap_or_else(|| "Embedded CMap".to_string())),
+        _ => None,
+    };
+
+    // Subset fonts are named with a six-letter tag, e.g. ABCDEF+Roboto
+    let subset = base_font.len() > 7 && base_font.as_bytes()[6] == b'+' && base_font.bytes().take(6).all(|b| b.is_ascii_uppercase());
+    // Type3 glyphs are content streams inside the font dictionary itself
+    let embedded = font_file.is_some() || subtype == "Type3";
+
+    FontInfo {
+        page,
+        resource_name: resource_name.to_string(),
+        form: form.map(str::to_string),
+        object_id,
+        standard_font: !embedded && STANDARD_FONTS.contains(&base_font.as_str()),
+        base_font,
+        subtype,
+        descendant_subtype: descendant.as_ref().and_then(|font| name_string(font.get(b"Subtype").ok())),
+        encoding,
+        embedded,
+        font_file,
+        subset,
+        has_to_unicode: dict.has(b"ToUnicode"),
+        used: false,
+        text_operations: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    fn inventory(name: &str) -> FontInventoryResult {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let program_id = doc.add_object(Stream::new(Dictionary::new(), vec![0; 16]));
+        let descriptor_id = doc.add_object(dictionary! { "Type" => "FontDescriptor", "FontName" => "ABCDEF+Roboto", "FontFile2" => program_id });
+        let to_unicode_id = doc.add_object(Stream::new(Dictionary::new(), Vec::new()));
+        let roboto_id = doc.add_object(dictionary! {
+            "Type" => "Font", "Subtype" => "TrueType", "BaseFont" => "ABCDEF+Roboto",
+            "FontDescriptor" => descriptor_id, "ToUnicode" => to_unicode_id, "Encoding" => "WinAnsiEncoding",
+        });
+        let form_id = doc.add_object(Stream::new(dictionary! {
+            "Type" => "XObject", "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Arial" } } },
+        }, b"BT /F1 8 Tf (in form) Tj ET".to_vec()));
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), b"BT /F1 12 Tf (a) Tj /F2 10 Tf (b) Tj (c) Tj ET /Fm1 Do".to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! {
+                    "F1" => dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica" },
+                    "F2" => roboto_id,
+                    "F3" => dictionary! { "Type" => "Font", "Subtype" => "TrueType", "BaseFont" => "Verdana" },
+                },
+                "XObject" => dictionary! { "Fm1" => form_id },
+            },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let path = std::env::temp_dir().join(format!("font_inventory_test_{}.pdf", name));
+        doc.save(&path).unwrap();
+        font_inventory(path.to_str().unwrap(), &FontInventoryOptions::default()).unwrap()
+    }
+
+    fn font<'r>(result: &'r FontInventoryResult, form: Option<&str>, resource_name: &str) -> &'r FontInfo {
+        result.fonts.iter().find(|font| font.form.as_deref() == form && font.resource_name == resource_name).unwrap()
+    }
+
+    #[test]
+    fn fonts_are_classified() {
+        let result = inventory("classified");
+        assert_eq!(result.total_fonts, 4);
+
+        let helvetica = font(&result, None, "F1");
+        assert!(helvetica.standard_font && !helvetica.embedded && helvetica.used);
+        let roboto = font(&result, None, "F2");
+        assert!(roboto.embedded && roboto.subset && roboto.has_to_unicode);
+        assert_eq!(roboto.font_file.as_deref(), Some("FontFile2"));
+        assert_eq!(roboto.text_operations, 2);
+        assert!(!font(&result, None, "F3").used);
+
+        // Form fonts are reported separately from page fonts of the same name
+        let arial = font(&result, Some("Fm1"), "F1");
+        assert_eq!((arial.base_font.as_str(), arial.text_operations), ("Arial", 1));
+        assert_eq!(result.non_embedded_fonts, vec!["Arial", "Verdana"]);
+        assert!(!result.all_embedded);
+    }
+}
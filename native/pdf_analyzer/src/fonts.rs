@@ -0,0 +1,613 @@
+use lopdf::{Dictionary, Document, Object};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use ttf_parser::{GlyphId, OutlineBuilder};
+use crate::common::object_to_f64;
+use crate::overlay::win_ansi_char;
+
+// Embedded font program, kept as raw bytes and parsed on demand
+pub enum FontProgram {
+    // TrueType or OpenType (glyf or CFF outlines in an sfnt wrapper)
+    Sfnt(Vec<u8>),
+    // Bare CFF data from /FontFile3 /Subtype /Type1C or /CIDFontType0C
+    Cff(Vec<u8>),
+    None,
+}
+
+// A PDF font resolved enough to measure and draw glyphs
+pub struct PdfFont {
+    pub subtype: String,
+    pub two_byte: bool,
+    pub is_fallback: bool,
+    pub font_matrix: [f64; 6],
+    // Extent above and below the baseline in text space (1.0 = one em)
+    pub ascent: f64,
+    pub descent: f64,
+    first_char: u32,
+    widths: Vec<f64>,
+    missing_width: f64,
+    cid_widths: HashMap<u32, f64>,
+    default_width: f64,
+    code_to_gid: Vec<Option<u16>>,
+    cid_to_gid: Option<Vec<u16>>,
+    // Text of each code, from /ToUnicode or the simple font encoding
+    unicode: HashMap<u32, String>,
+    program: FontProgram,
+    // Tables read from the font program on first use, so it is parsed once
+    // rather than for every glyph
+    program_advances: OnceCell<Vec<Option<f64>>>,
+    cid_glyphs: OnceCell<HashMap<u16, u16>>,
+}
+
+impl PdfFont {
+    // Load a font dictionary. Fonts without an embedded program use `fallback`
+    // (a TrueType file) for outlines, keeping the PDF's own widths.
+    pub fn load(doc: &Document, font: &Dictionary, fallback: Option<&[u8]>) -> PdfFont {
+        let name = |dict: &Dictionary, key: &[u8]| {
+            dict.get(key).ok().and_then(|obj| obj.as_name().ok()).map(|n| String::from_utf8_lossy(n).to_string())
+        };
+        let subtype = name(font, b"Subtype").unwrap_or_default();
+        let two_byte = subtype == "Type0";
+
+        // For Type0 fonts the widths, descriptor and program live on the descendant
+        let descendant = if two_byte {
+            deref(doc, font.get(b"DescendantFonts").ok())
+                .and_then(|obj| obj.as_array().ok())
+                .and_then(|fonts| deref(doc, fonts.first()))
+                .and_then(|obj| obj.as_dict().ok())
+        } else {
+            None
+        };
+        let metrics_dict = descendant.unwrap_or(font);
+        let descriptor = deref(doc, metrics_dict.get(b"FontDescriptor").ok()).and_then(|obj| obj.as_dict().ok());
+
+        let mut program = descriptor.map(|d| load_program(doc, d)).unwrap_or(FontProgram::None);
+        let mut is_fallback = false;
+        if matches!(program, FontProgram::None) && subtype != "Type3" {
+            if let Some(data) = fallback {
+                program = FontProgram::Sfnt(data.to_vec());
+                is_fallback = true;
+            }
+        }
+
+        let font_matrix = deref(doc, font.get(b"FontMatrix").ok())
+            .and_then(|obj| obj.as_array().ok())
+            .filter(|values| values.len() == 6)
+            .map(|values| {
+                let mut matrix = [0.0; 6];
+                for (slot, value) in matrix.iter_mut().zip(values) {
+                    *slot = object_to_f64(value);
+                }
+                matrix
+            })
+            .unwrap_or([0.001, 0.0, 0.0, 0.001, 0.0, 0.0]);
+
+        // Type3 glyph space is mapped by the FontMatrix; other fonts use 1/1000 em
+        let (ascent, descent) = if subtype == "Type3" {
+            deref(doc, font.get(b"FontBBox").ok())
+                .and_then(|obj| obj.as_array().ok())
+                .filter(|values| values.len() == 4)
+                .map(|values| (object_to_f64(&values[3]) * font_matrix[3], object_to_f64(&values[1]) * font_matrix[3]))
+                .unwrap_or((0.8, -0.2))
+        } else {
+            let metric = |key: &[u8]| descriptor.and_then(|d| d.get(key).ok()).map(object_to_f64).filter(|v| *v != 0.0);
+            (metric(b"Ascent").unwrap_or(800.0) / 1000.0, metric(b"Descent").unwrap_or(-200.0) / 1000.0)
+        };
+
+        let first_char = font.get(b"FirstChar").ok().map(object_to_f64).unwrap_or(0.0) as u32;
+        let widths = deref(doc, font.get(b"Widths").ok())
+            .and_then(|obj| obj.as_array().ok())
+            .map(|values| values.iter().map(|v| object_to_f64(deref(doc, Some(v)).unwrap_or(v))).collect())
+            .unwrap_or_default();
+        let missing_width = descriptor
+            .and_then(|d| d.get(b"MissingWidth").ok())
+            .map(object_to_f64)
+            .unwrap_or(0.0);
+
+        let (cid_widths, default_width) = match descendant {
+            Some(cid_font) => (
+                parse_cid_widths(doc, cid_font),
+                cid_font.get(b"DW").ok().map(object_to_f64).unwrap_or(1000.0),
+            ),
+            None => (HashMap::new(), 1000.0),
+        };
+
+        let cid_to_gid = descendant
+            .and_then(|cid_font| deref(doc, cid_font.get(b"CIDToGIDMap").ok()))
+            .and_then(|obj| obj.as_stream().ok())
+            .and_then(|stream| stream.decompressed_content().ok().or_else(|| Some(stream.content.clone())))
+            .map(|bytes| bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect());
+
+        let mut pdf_font = PdfFont {
+            subtype,
+            two_byte,
+            is_fallback,
+            font_matrix,
+            ascent,
+            descent,
+            first_char,
+            widths,
+            missing_width,
+            cid_widths,
+            default_width,
+            code_to_gid: Vec::new(),
+            cid_to_gid,
+            unicode: HashMap::new(),
+            program,
+            program_advances: OnceCell::new(),
+            cid_glyphs: OnceCell::new(),
+        };
+        if !two_byte {
+            let symbolic = descriptor
+                .and_then(|d| d.get(b"Flags").ok())
+                .map(|flags| (object_to_f64(flags) as u32) & 4 != 0)
+                .unwrap_or(false);
+            pdf_font.code_to_gid = pdf_font.simple_glyph_map(doc, font, symbolic);
+            pdf_font.unicode = simple_unicode(doc, font);
+        }
+        if let Some(cmap) = deref(doc, font.get(b"ToUnicode").ok())
+            .and_then(|obj| obj.as_stream().ok())
+            .and_then(|stream| stream.decompressed_content().ok().or_else(|| Some(stream.content.clone())))
+        {
+            pdf_font.unicode.extend(parse_to_unicode(&cmap));
+        }
+        pdf_font
+    }
+
+    // Split a shown string into character codes
+    pub fn codes(&self, bytes: &[u8]) -> Vec<u32> {
+        if self.two_byte {
+            bytes.chunks(2).map(|pair| ((pair[0] as u32) << 8) | *pair.get(1).unwrap_or(&0) as u32).collect()
+        } else {
+            bytes.iter().map(|b| *b as u32).collect()
+        }
+    }
+
+    // Text a code stands for, if the font says
+    pub fn unicode(&self, code: u32) -> Option<&str> {
+        self.unicode.get(&code).map(String::as_str)
+    }
+
+    // Horizontal advance of a code in glyph space units (1/1000 em for non-Type3 fonts)
+    pub fn width(&self, code: u32) -> f64 {
+        if self.two_byte {
+            return self.cid_widths.get(&code).copied().unwrap_or(self.default_width);
+        }
+        if code >= self.first_char {
+            if let Some(width) = self.widths.get((code - self.first_char) as usize) {
+                return *width;
+            }
+        }
+        if self.missing_width > 0.0 {
+            return self.missing_width;
+        }
+        // Fall back to the advance stored in the font program
+        self.glyph_id(code)
+            .and_then(|gid| self.program_advance(gid))
+            .unwrap_or(500.0)
+    }
+
+    // Horizontal advance of a code in text space (1.0 = one em)
+    pub fn advance(&self, code: u32) -> f64 {
+        if self.subtype == "Type3" {
+            self.width(code) * self.font_matrix[0]
+        } else {
+            self.width(code) / 1000.0
+        }
+    }
+
+    pub fn glyph_id(&self, code: u32) -> Option<u16> {
+        if self.two_byte {
+            return match &self.cid_to_gid {
+                Some(map) => map.get(code as usize).copied(),
+                None => Some(self.cid_glyph(code)),
+            };
+        }
+        self.code_to_gid.get(code as usize).copied().flatten()
+    }
+
+    // Outline a glyph with coordinates in text space (1.0 = one em)
+    pub fn outline(&self, gid: u16, builder: &mut dyn OutlineBuilder) -> bool {
+        match &self.program {
+            FontProgram::Sfnt(data) => {
+                let Ok(face) = ttf_parser::Face::parse(data, 0) else { return false };
+                let scale = 1.0 / face.units_per_em() as f32;
+                let mut scaled = ScaledBuilder { inner: builder, matrix: [scale, 0.0, 0.0, scale, 0.0, 0.0] };
+                face.outline_glyph(GlyphId(gid), &mut scaled).is_some()
+            },
+            FontProgram::Cff(data) => {
+                let Some(table) = ttf_parser::cff::Table::parse(data) else { return false };
+                let m = table.matrix();
+                let mut scaled = ScaledBuilder { inner: builder, matrix: [m.sx, m.ky, m.kx, m.sy, m.tx, m.ty] };
+                table.outline(GlyphId(gid), &mut scaled).is_ok()
+            },
+            FontProgram::None => false,
+        }
+    }
+
+    fn program_advance(&self, gid: u16) -> Option<f64> {
+        self.program_advances.get_or_init(|| self.load_program_advances()).get(gid as usize).copied().flatten()
+    }
+
+    // Advance of every glyph in the font program, in 1/1000 em
+    fn load_program_advances(&self) -> Vec<Option<f64>> {
+        match &self.program {
+            FontProgram::Sfnt(data) => {
+                let Ok(face) = ttf_parser::Face::parse(data, 0) else { return Vec::new() };
+                let scale = 1000.0 / face.units_per_em() as f64;
+                (0..face.number_of_glyphs()).map(|gid| face.glyph_hor_advance(GlyphId(gid)).map(|w| w as f64 * scale)).collect()
+            },
+            FontProgram::Cff(data) => {
+                let Some(table) = ttf_parser::cff::Table::parse(data) else { return Vec::new() };
+                (0..table.number_of_glyphs()).map(|gid| table.glyph_width(GlyphId(gid)).map(|w| w as f64)).collect()
+            },
+            FontProgram::None => Vec::new(),
+        }
+    }
+
+    // CID-keyed CFF fonts map CIDs through their charset; everything else is identity
+    fn cid_glyph(&self, cid: u32) -> u16 {
+        let cid = cid as u16;
+        self.cid_glyphs.get_or_init(|| self.load_cid_glyphs()).get(&cid).copied().unwrap_or(cid)
+    }
+
+    // Glyph of each CID in a CID-keyed CFF font, empty for other programs
+    fn load_cid_glyphs(&self) -> HashMap<u16, u16> {
+        let mut glyphs = HashMap::new();
+        if let FontProgram::Cff(data) = &self.program {
+            if let Some(table) = ttf_parser::cff::Table::parse(data) {
+                for gid in 0..table.number_of_glyphs() {
+                    if let Some(cid) = table.glyph_cid(GlyphId(gid)) {
+                        // The first glyph with a CID wins
+                        glyphs.entry(cid).or_insert(gid);
+                    }
+                }
+            }
+        }
+        glyphs
+    }
+
+    fn simple_glyph_map(&self, doc: &Document, font: &Dictionary, symbolic: bool) -> Vec<Option<u16>> {
+        let (base_encoding, differences) = simple_encoding(doc, font);
+        let mut map = vec![None; 256];
+
+        match &self.program {
+            FontProgram::Sfnt(data) => {
+                let Ok(face) = ttf_parser::Face::parse(data, 0) else { return map };
+                for (code, slot) in map.iter_mut().enumerate() {
+                    let glyph_name = differences.get(&(code as u32));
+                    let unicode = glyph_name
+                        .and_then(|name| glyph_name_to_char(name))
+                        .or_else(|| base_char(base_encoding.as_deref(), code as u8));
+
+                    let by_name = glyph_name.and_then(|name| face.glyph_index_by_name(name));
+                    let by_unicode = if symbolic && !self.is_fallback { None } else { unicode.and_then(|ch| face.glyph_index(ch)) };
+                    // Symbolic TrueType fonts use the (3,0) cmap at 0xF000 + code
+                    let by_symbol = face.tables().cmap.and_then(|cmap| {
+                        cmap.subtables.into_iter().find_map(|subtable| {
+                            subtable.glyph_index(0xF000 + code as u32).or_else(|| subtable.glyph_index(code as u32))
+                        })
+                    });
+                    *slot = by_name.or(by_unicode).or(by_symbol).map(|gid| gid.0);
+                }
+            },
+            FontProgram::Cff(data) => {
+                let Some(table) = ttf_parser::cff::Table::parse(data) else { return map };
+                for (code, slot) in map.iter_mut().enumerate() {
+                    let glyph_name = differences
+                        .get(&(code as u32))
+                        .cloned()
+                        .or_else(|| if base_encoding.is_some() { base_char(base_encoding.as_deref(), code as u8).and_then(char_to_glyph_name) } else { None });
+                    *slot = glyph_name
+                        .and_then(|name| table.glyph_index_by_name(&name))
+                        .or_else(|| table.glyph_index(code as u8))
+                        .map(|gid| gid.0);
+                }
+            },
+            FontProgram::None => {},
+        }
+        map
+    }
+}
+
+fn deref<'a>(doc: &'a Document, object: Option<&'a Object>) -> Option<&'a Object> {
+    object.and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj)
+}
+
+fn load_program(doc: &Document, descriptor: &Dictionary) -> FontProgram {
+    let stream_bytes = |key: &[u8]| {
+        deref(doc, descriptor.get(key).ok())
+            .and_then(|obj| obj.as_stream().ok())
+            .map(|stream| (stream.decompressed_content().unwrap_or_else(|_| stream.content.clone()), stream.dict.clone()))
+    };
+
+    if let Some((data, _)) = stream_bytes(b"FontFile2") {
+        return FontProgram::Sfnt(data);
+    }
+    if let Some((data, dict)) = stream_bytes(b"FontFile3") {
+        let subtype = dict.get(b"Subtype").ok().and_then(|obj| obj.as_name().ok()).unwrap_or(b"");
+        return if subtype == b"OpenType" { FontProgram::Sfnt(data) } else { FontProgram::Cff(data) };
+    }
+    // Type 1 programs (/FontFile) are not supported
+    FontProgram::None
+}
+
+// Parse the /W array of a CIDFont: [c [w1 w2 ...]] or [c_first c_last w]
+fn parse_cid_widths(doc: &Document, cid_font: &Dictionary) -> HashMap<u32, f64> {
+    let mut widths = HashMap::new();
+    let Some(items) = deref(doc, cid_font.get(b"W").ok()).and_then(|obj| obj.as_array().ok()) else { return widths };
+
+    let mut pos = 0;
+    while pos + 1 < items.len() {
+        let first = object_to_f64(&items[pos]) as u32;
+        match deref(doc, Some(&items[pos + 1])) {
+            Some(Object::Array(values)) => {
+                for (offset, value) in values.iter().enumerate() {
+                    widths.insert(first + offset as u32, object_to_f64(value));
+                }
+                pos += 2;
+            },
+            _ if pos + 2 < items.len() => {
+                let last = object_to_f64(&items[pos + 1]) as u32;
+                let width = object_to_f64(&items[pos + 2]);
+                for cid in first..=last.min(first + 0xFFFF) {
+                    widths.insert(cid, width);
+                }
+                pos += 3;
+            },
+            _ => break,
+        }
+    }
+    widths
+}
+
+// Base encoding name and /Differences of a simple font
+pub fn simple_encoding(doc: &Document, font: &Dictionary) -> (Option<String>, HashMap<u32, String>) {
+    let mut differences = HashMap::new();
+    let base = match deref(doc, font.get(b"Encoding").ok()) {
+        Some(Object::Name(name)) => Some(String::from_utf8_lossy(name).to_string()),
+        Some(Object::Dictionary(dict)) => {
+            if let Some(items) = deref(doc, dict.get(b"Differences").ok()).and_then(|obj| obj.as_array().ok()) {
+                let mut code = 0u32;
+                for item in items {
+                    match item {
+                        Object::Integer(start) => code = *start as u32,
+                        Object::Name(name) => {
+                            differences.insert(code, String::from_utf8_lossy(name).to_string());
+                            code += 1;
+                        },
+                        _ => {},
+                    }
+                }
+            }
+            dict.get(b"BaseEncoding").ok().and_then(|obj| obj.as_name().ok()).map(|n| String::from_utf8_lossy(n).to_string())
+        },
+        _ => None,
+    };
+    (base, differences)
+}
+
+// Text of the codes of a simple font without /ToUnicode
+fn simple_unicode(doc: &Document, font: &Dictionary) -> HashMap<u32, String> {
+    let (base, differences) = simple_encoding(doc, font);
+    (0..=255u32)
+        .filter_map(|code| {
+            let ch = match differences.get(&code) {
+                Some(name) => glyph_name_to_char(name),
+                None => base_char(base.as_deref(), code as u8),
+            };
+            ch.map(|ch| (code, ch.to_string()))
+        })
+        .collect()
+}
+
+// Maximum number of codes a single bfrange may map
+const MAX_CMAP_RANGE: u32 = 0x10000;
+
+// Code to text mappings of a /ToUnicode CMap (bfchar and bfrange sections)
+fn parse_to_unicode(data: &[u8]) -> HashMap<u32, String> {
+    let tokens = cmap_tokens(data);
+    let mut map = HashMap::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match &tokens[i] {
+            CMapToken::Keyword(keyword) if keyword == "beginbfchar" => {
+                i += 1;
+                while let (Some(CMapToken::Hex(code)), Some(CMapToken::Hex(text))) = (tokens.get(i), tokens.get(i + 1)) {
+                    map.insert(code_value(code), utf16_text(text));
+                    i += 2;
+                }
+            },
+            CMapToken::Keyword(keyword) if keyword == "beginbfrange" => {
+                i += 1;
+                while let (Some(CMapToken::Hex(low)), Some(CMapToken::Hex(high))) = (tokens.get(i), tokens.get(i + 1)) {
+                    let (low, high) = (code_value(low), code_value(high));
+                    let count = high.saturating_sub(low).min(MAX_CMAP_RANGE);
+                    match tokens.get(i + 2) {
+                        // Consecutive codes map to consecutive values of the last UTF-16 unit
+                        Some(CMapToken::Hex(start)) => {
+                            let units = utf16_units(start);
+                            for offset in 0..=count {
+                                let mut units = units.clone();
+                                if let Some(last) = units.last_mut() {
+                                    *last = last.wrapping_add(offset as u16);
+                                }
+                                map.insert(low + offset, String::from_utf16_lossy(&units));
+                            }
+                            i += 3;
+                        },
+                        Some(CMapToken::Array(texts)) => {
+                            for (offset, text) in texts.iter().enumerate().take(count as usize + 1) {
+                                map.insert(low + offset as u32, utf16_text(text));
+                            }
+                            i += 3;
+                        },
+                        _ => break,
+                    }
+                }
+            },
+            _ => i += 1,
+        }
+    }
+    map
+}
+
+enum CMapToken {
+    Hex(Vec<u8>),
+    Array(Vec<Vec<u8>>),
+    Keyword(String),
+}
+
+// Hex strings, arrays of hex strings and keywords; everything else in the
+// CMap is skipped
+fn cmap_tokens(data: &[u8]) -> Vec<CMapToken> {
+    let mut tokens = Vec::new();
+    let mut array: Option<Vec<Vec<u8>>> = None;
+    let mut i = 0;
+
+    while i < data.len() {
+        match data[i] {
+            b'%' => {
+                while i < data.len() && data[i] != b'\n' && data[i] != b'\r' {
+                    i += 1;
+                }
+            },
+            b'<' if data.get(i + 1) == Some(&b'<') => i += 2,
+            b'<' => {
+                let end = data[i..].iter().position(|&b| b == b'>').map(|end| i + end).unwrap_or(data.len());
+                let hex = hex_bytes(&data[i + 1..end]);
+                match array.as_mut() {
+                    Some(items) => items.push(hex),
+                    None => tokens.push(CMapToken::Hex(hex)),
+                }
+                i = end + 1;
+            },
+            b'[' => {
+                array = Some(Vec::new());
+                i += 1;
+            },
+            b']' => {
+                tokens.push(CMapToken::Array(array.take().unwrap_or_default()));
+                i += 1;
+            },
+            b if b.is_ascii_alphabetic() => {
+                let start = i;
+                while i < data.len() && data[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                tokens.push(CMapToken::Keyword(String::from_utf8_lossy(&data[start..i]).to_string()));
+            },
+            _ => i += 1,
+        }
+    }
+    tokens
+}
+
+fn hex_bytes(hex: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = hex.iter().filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8)).collect();
+    digits.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0)).collect()
+}
+
+fn code_value(bytes: &[u8]) -> u32 {
+    bytes.iter().take(4).fold(0, |value, &b| (value << 8) | b as u32)
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect()
+}
+
+fn utf16_text(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&utf16_units(bytes))
+}
+
+// Character for a code in a base encoding; Standard and MacRoman are
+// approximated by WinAnsi, which agrees on the printable ASCII range.
+pub fn base_char(_encoding: Option<&str>, code: u8) -> Option<char> {
+    win_ansi_char(code)
+}
+
+// Glyph names for printable ASCII, indexed from 0x20
+const ASCII_GLYPH_NAMES: [&str; 95] = [
+    "space", "exclam", "quotedbl", "numbersign", "dollar", "percent", "ampersand", "quotesingle",
+    "parenleft", "parenright", "asterisk", "plus", "comma", "hyphen", "period", "slash",
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    "colon", "semicolon", "less", "equal", "greater", "question", "at",
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+    "bracketleft", "backslash", "bracketright", "asciicircum", "underscore", "grave",
+    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+    "braceleft", "bar", "braceright", "asciitilde",
+];
+
+// A few non-ASCII glyph names that show up in typical documents
+const EXTRA_GLYPH_NAMES: [(&str, char); 16] = [
+    ("quoteright", '\u{2019}'), ("quoteleft", '\u{2018}'), ("quotedblleft", '\u{201C}'), ("quotedblright", '\u{201D}'),
+    ("endash", '\u{2013}'), ("emdash", '\u{2014}'), ("bullet", '\u{2022}'), ("ellipsis", '\u{2026}'),
+    ("fi", '\u{FB01}'), ("fl", '\u{FB02}'), ("Euro", '\u{20AC}'), ("trademark", '\u{2122}'),
+    ("copyright", '\u{00A9}'), ("registered", '\u{00AE}'), ("degree", '\u{00B0}'), ("section", '\u{00A7}'),
+];
+
+pub fn glyph_name_to_char(name: &str) -> Option<char> {
+    if let Some(pos) = ASCII_GLYPH_NAMES.iter().position(|n| *n == name) {
+        return char::from_u32(0x20 + pos as u32);
+    }
+    if let Some((_, ch)) = EXTRA_GLYPH_NAMES.iter().find(|(n, _)| *n == name) {
+        return Some(*ch);
+    }
+    // uniXXXX (first code point of a ligature name) or uXXXX[XX]
+    let hex = match name.strip_prefix("uni") {
+        Some(rest) => rest.get(..4)?,
+        None => name.strip_prefix('u').filter(|rest| (4..=6).contains(&rest.len()))?,
+    };
+    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+}
+
+fn char_to_glyph_name(ch: char) -> Option<String> {
+    let code = ch as u32;
+    if (0x20..0x7F).contains(&code) {
+        return Some(ASCII_GLYPH_NAMES[(code - 0x20) as usize].to_string());
+    }
+    EXTRA_GLYPH_NAMES.iter().find(|(_, c)| *c == ch).map(|(name, _)| name.to_string())
+}
+
+// Applies an affine matrix to outline coordinates before forwarding them
+struct ScaledBuilder<'a> {
+    inner: &'a mut dyn OutlineBuilder,
+    matrix: [f32; 6],
+}
+
+impl ScaledBuilder<'_> {
+    fn map(&self, x: f32, y: f32) -> (f32, f32) {
+        let m = self.matrix;
+        (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+    }
+}
+
+impl OutlineBuilder for ScaledBuilder<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.map(x, y);
+        self.inner.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.map(x, y);
+        self.inner.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x1, y1) = self.map(x1, y1);
+        let (x, y) = self.map(x, y);
+        self.inner.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x1, y1) = self.map(x1, y1);
+        let (x2, y2) = self.map(x2, y2);
+        let (x, y) = self.map(x, y);
+        self.inner.curve_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
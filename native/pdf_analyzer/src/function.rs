@@ -0,0 +1,431 @@
+use lopdf::{Dictionary, Document, Object};
+use crate::common::object_to_f64;
+
+// Nesting depth up to which stitching functions and PostScript procedures are followed
+const MAX_FUNCTION_DEPTH: usize = 8;
+// Operand stack size of the PostScript calculator, as the spec allows
+const MAX_STACK: usize = 100;
+// Inputs of sampled functions beyond this are ignored; interpolation visits 2^m samples
+const MAX_SAMPLED_INPUTS: usize = 8;
+
+// PDF function (Type 0, 2, 3 or 4), used for the tint transforms of
+// Separation and DeviceN color spaces
+#[derive(Debug, Clone)]
+pub struct Function {
+    domain: Vec<(f64, f64)>,
+    range: Option<Vec<(f64, f64)>>,
+    kind: FunctionKind,
+}
+
+#[derive(Debug, Clone)]
+enum FunctionKind {
+    Sampled { size: Vec<usize>, bits: u32, encode: Vec<(f64, f64)>, decode: Vec<(f64, f64)>, samples: Vec<u8> },
+    Exponential { c0: Vec<f64>, c1: Vec<f64>, exponent: f64 },
+    Stitching { functions: Vec<Function>, bounds: Vec<f64>, encode: Vec<(f64, f64)> },
+    PostScript(Vec<PsOp>),
+    // An array of single-output functions, one per output
+    Array(Vec<Function>),
+}
+
+#[derive(Debug, Clone)]
+enum PsOp {
+    Number(f64),
+    Bool(bool),
+    Operator(String),
+    If(Vec<PsOp>),
+    IfElse(Vec<PsOp>, Vec<PsOp>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PsValue {
+    Number(f64),
+    Bool(bool),
+}
+
+impl Function {
+    // Load a function dictionary, stream or array of functions
+    pub fn load(doc: &Document, object: &Object) -> Option<Function> {
+        Function::load_nested(doc, object, 0)
+    }
+
+    fn load_nested(doc: &Document, object: &Object, depth: usize) -> Option<Function> {
+        if depth > MAX_FUNCTION_DEPTH {
+            return None;
+        }
+        let object = doc.dereference(object).ok()?.1;
+        if let Object::Array(items) = object {
+            let functions = items.iter().map(|item| Function::load_nested(doc, item, depth + 1)).collect::<Option<Vec<_>>>()?;
+            return Some(Function { domain: Vec::new(), range: None, kind: FunctionKind::Array(functions) });
+        }
+
+        let (dict, data) = match object {
+            Object::Stream(stream) => (&stream.dict, Some(stream.decompressed_content().unwrap_or_else(|_| stream.content.clone()))),
+            Object::Dictionary(dict) => (dict, None),
+            _ => return None,
+        };
+        let domain = pairs(doc, dict, b"Domain")?;
+        let range = pairs(doc, dict, b"Range");
+
+        let kind = match dict.get(b"FunctionType").ok().and_then(|obj| obj.as_i64().ok())? {
+            0 => {
+                let size: Vec<usize> = numbers(doc, dict, b"Size")?.into_iter().map(|n| n.max(1.0) as usize).collect();
+                let range = range.clone()?;
+                let encode = pairs(doc, dict, b"Encode").unwrap_or_else(|| size.iter().map(|&n| (0.0, (n - 1) as f64)).collect());
+                let bits = dict.get(b"BitsPerSample").ok().and_then(|obj| obj.as_i64().ok()).filter(|b| matches!(b, 1 | 2 | 4 | 8 | 12 | 16 | 24 | 32))? as u32;
+                let decode = pairs(doc, dict, b"Decode").unwrap_or_else(|| range.clone());
+                // A sample table whose bit positions overflow cannot be addressed
+                size.iter().try_fold(decode.len(), |total, &n| total.checked_mul(n))?.checked_mul(bits as usize)?;
+                FunctionKind::Sampled {
+                    bits,
+                    decode,
+                    encode,
+                    size,
+                    samples: data?,
+                }
+            },
+            2 => FunctionKind::Exponential {
+                c0: numbers(doc, dict, b"C0").unwrap_or_else(|| vec![0.0]),
+                c1: numbers(doc, dict, b"C1").unwrap_or_else(|| vec![1.0]),
+                exponent: dict.get(b"N").ok().map(object_to_f64)?,
+            },
+            3 => {
+                let functions = dict
+                    .get(b"Functions")
+                    .ok()
+                    .and_then(|obj| doc.dereference(obj).ok())
+                    .and_then(|(_, obj)| obj.as_array().ok())?
+                    .iter()
+                    .map(|item| Function::load_nested(doc, item, depth + 1))
+                    .collect::<Option<Vec<_>>>()?;
+                FunctionKind::Stitching {
+                    bounds: numbers(doc, dict, b"Bounds").unwrap_or_default(),
+                    encode: pairs(doc, dict, b"Encode").unwrap_or_default(),
+                    functions,
+                }
+            },
+            4 => FunctionKind::PostScript(parse_program(&data?)?),
+            _ => return None,
+        };
+        Some(Function { domain, range, kind })
+    }
+
+    // Evaluate the function; inputs are clipped to the domain and outputs to the range
+    pub fn eval(&self, inputs: &[f64]) -> Vec<f64> {
+        let inputs: Vec<f64> = match self.domain.is_empty() {
+            true => inputs.to_vec(),
+            false => self.domain.iter().enumerate().map(|(i, &(min, max))| inputs.get(i).copied().unwrap_or(min).clamp(min.min(max), max.max(min))).collect(),
+        };
+
+        let outputs = match &self.kind {
+            FunctionKind::Sampled { size, bits, encode, decode, samples } => self.sample(&inputs, size, *bits, encode, decode, samples).unwrap_or_default(),
+            FunctionKind::Exponential { c0, c1, exponent } => {
+                let x = inputs.first().copied().unwrap_or(0.0).powf(*exponent);
+                c0.iter().zip(c1).map(|(a, b)| a + x * (b - a)).collect()
+            },
+            FunctionKind::Stitching { functions, bounds, encode } => {
+                let x = inputs.first().copied().unwrap_or(0.0);
+                let (low, high) = self.domain.first().copied().unwrap_or((0.0, 1.0));
+                let index = bounds.iter().take_while(|&&bound| x >= bound).count().min(functions.len().saturating_sub(1));
+                let start = if index == 0 { low } else { bounds[index - 1] };
+                let end = bounds.get(index).copied().unwrap_or(high);
+                let (e0, e1) = encode.get(index).copied().unwrap_or((0.0, 1.0));
+                match functions.get(index) {
+                    Some(function) => function.eval(&[interpolate(x, start, end, e0, e1)]),
+                    None => Vec::new(),
+                }
+            },
+            FunctionKind::PostScript(program) => {
+                let mut stack: Vec<PsValue> = inputs.iter().map(|&x| PsValue::Number(x)).collect();
+                run_program(program, &mut stack, 0);
+                stack.into_iter().map(|value| value.number()).collect()
+            },
+            FunctionKind::Array(functions) => functions.iter().flat_map(|function| function.eval(&inputs).into_iter().take(1)).collect(),
+        };
+
+        match &self.range {
+            Some(range) => outputs.iter().zip(range).map(|(&y, &(min, max))| y.clamp(min.min(max), max.max(min))).collect(),
+            None => outputs,
+        }
+    }
+
+    // Multilinear interpolation between the samples around the encoded inputs
+    fn sample(&self, inputs: &[f64], size: &[usize], bits: u32, encode: &[(f64, f64)], decode: &[(f64, f64)], samples: &[u8]) -> Option<Vec<f64>> {
+        let outputs = decode.len();
+        let m = inputs.len().min(size.len()).min(self.domain.len()).min(MAX_SAMPLED_INPUTS);
+        let positions: Vec<f64> = (0..m)
+            .map(|i| {
+                let (d0, d1) = self.domain[i];
+                let (e0, e1) = encode.get(i).copied().unwrap_or((0.0, (size[i] - 1) as f64));
+                interpolate(inputs[i], d0, d1, e0, e1).clamp(0.0, (size[i] - 1) as f64)
+            })
+            .collect();
+        let max_sample = 2f64.powi(bits as i32) - 1.0;
+        let mut result = vec![0.0; outputs];
+
+        for corner in 0..(1usize << m) {
+            let mut weight = 1.0;
+            let mut offset = 0;
+            let mut stride = 1;
+            for (i, &position) in positions.iter().enumerate() {
+                let low = position.floor();
+                let upper = corner & (1 << i) != 0;
+                let index = if upper { (low as usize + 1).min(size[i] - 1) } else { low as usize };
+                weight *= if upper { position - low } else { 1.0 - (position - low) };
+                offset += index * stride;
+                stride = stride.checked_mul(size[i])?;
+            }
+            if weight == 0.0 {
+                continue;
+            }
+            for (j, value) in result.iter_mut().enumerate() {
+                let raw = read_sample(samples, (offset * outputs + j) * bits as usize, bits) as f64;
+                *value += weight * interpolate(raw, 0.0, max_sample, decode[j].0, decode[j].1);
+            }
+        }
+        Some(result)
+    }
+}
+
+fn interpolate(x: f64, x0: f64, x1: f64, y0: f64, y1: f64) -> f64 {
+    if x1 == x0 {
+        return y0;
+    }
+    y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+}
+
+// Big-endian sample of `bits` bits starting at bit `position`
+fn read_sample(data: &[u8], position: usize, bits: u32) -> u64 {
+    (0..bits as usize).fold(0, |value, i| {
+        let bit = position + i;
+        let set = data.get(bit / 8).is_some_and(|byte| byte & (0x80 >> (bit % 8)) != 0);
+        (value << 1) | set as u64
+    })
+}
+
+fn numbers(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<Vec<f64>> {
+    let array = dict.get(key).ok().and_then(|obj| doc.dereference(obj).ok()).and_then(|(_, obj)| obj.as_array().ok())?;
+    Some(array.iter().map(object_to_f64).collect())
+}
+
+fn pairs(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<Vec<(f64, f64)>> {
+    Some(numbers(doc, dict, key)?.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+impl PsValue {
+    fn number(self) -> f64 {
+        match self {
+            PsValue::Number(x) => x,
+            PsValue::Bool(b) => b as i64 as f64,
+        }
+    }
+}
+
+// Parse the outermost `{ ... }` procedure of a Type 4 function
+fn parse_program(data: &[u8]) -> Option<Vec<PsOp>> {
+    let text = String::from_utf8_lossy(data).replace('{', " { ").replace('}', " } ");
+    let mut tokens = text.split_whitespace();
+    if tokens.next()? != "{" {
+        return None;
+    }
+    parse_procedure(&mut tokens, 0)
+}
+
+fn parse_procedure<'a>(tokens: &mut impl Iterator<Item = &'a str>, depth: usize) -> Option<Vec<PsOp>> {
+    if depth > MAX_FUNCTION_DEPTH {
+        return None;
+    }
+    let mut ops = Vec::new();
+    // Procedures waiting for their `if` or `ifelse`
+    let mut blocks: Vec<Vec<PsOp>> = Vec::new();
+
+    loop {
+        match tokens.next()? {
+            "}" => return Some(ops),
+            "{" => blocks.push(parse_procedure(tokens, depth + 1)?),
+            "if" => ops.push(PsOp::If(blocks.pop()?)),
+            "ifelse" => {
+                let otherwise = blocks.pop()?;
+                ops.push(PsOp::IfElse(blocks.pop()?, otherwise));
+            },
+            "true" => ops.push(PsOp::Bool(true)),
+            "false" => ops.push(PsOp::Bool(false)),
+            token => match token.parse::<f64>() {
+                Ok(number) => ops.push(PsOp::Number(number)),
+                Err(_) => ops.push(PsOp::Operator(token.to_string())),
+            },
+        }
+    }
+}
+
+// Run a procedure; an error such as a stack underflow stops the program
+fn run_program(program: &[PsOp], stack: &mut Vec<PsValue>, depth: usize) -> Option<()> {
+    if depth > MAX_FUNCTION_DEPTH {
+        return None;
+    }
+    for op in program {
+        if stack.len() >= MAX_STACK {
+            return None;
+        }
+        match op {
+            PsOp::Number(x) => stack.push(PsValue::Number(*x)),
+            PsOp::Bool(b) => stack.push(PsValue::Bool(*b)),
+            PsOp::If(procedure) => {
+                if let PsValue::Bool(true) = stack.pop()? {
+                    run_program(procedure, stack, depth + 1)?;
+                }
+            },
+            PsOp::IfElse(then, otherwise) => match stack.pop()? {
+                PsValue::Bool(true) => run_program(then, stack, depth + 1)?,
+                _ => run_program(otherwise, stack, depth + 1)?,
+            },
+            PsOp::Operator(name) => run_operator(name, stack)?,
+        }
+    }
+    Some(())
+}
+
+fn run_operator(name: &str, stack: &mut Vec<PsValue>) -> Option<()> {
+    let mut pop = || stack.pop().map(PsValue::number);
+    let value = match name {
+        "abs" => PsValue::Number(pop()?.abs()),
+        "neg" => PsValue::Number(-pop()?),
+        "ceiling" => PsValue::Number(pop()?.ceil()),
+        "floor" => PsValue::Number(pop()?.floor()),
+        "round" => PsValue::Number((pop()? + 0.5).floor()),
+        "truncate" | "cvi" => PsValue::Number(pop()?.trunc()),
+        "cvr" => PsValue::Number(pop()?),
+        "sqrt" => PsValue::Number(pop()?.max(0.0).sqrt()),
+        "sin" => PsValue::Number(pop()?.to_radians().sin()),
+        "cos" => PsValue::Number(pop()?.to_radians().cos()),
+        "ln" => PsValue::Number(pop()?.ln()),
+        "log" => PsValue::Number(pop()?.log10()),
+        "add" | "sub" | "mul" | "div" | "idiv" | "mod" | "exp" | "atan" | "bitshift" => {
+            let (b, a) = (pop()?, pop()?);
+            PsValue::Number(match name {
+                "add" => a + b,
+                "sub" => a - b,
+                "mul" => a * b,
+                "div" if b != 0.0 => a / b,
+                "idiv" if b as i64 != 0 => (a as i64 / b as i64) as f64,
+                "mod" if b as i64 != 0 => (a as i64 % b as i64) as f64,
+                "exp" => a.powf(b),
+                "atan" => b.atan2(a).to_degrees().rem_euclid(360.0),
+                "bitshift" if b >= 0.0 => ((a as i64) << (b as u32).min(63)) as f64,
+                "bitshift" => ((a as i64) >> (-b as u32).min(63)) as f64,
+                _ => return None,
+            })
+        },
+        "eq" | "ne" | "gt" | "ge" | "lt" | "le" => {
+            let (b, a) = (pop()?, pop()?);
+            PsValue::Bool(match name {
+                "eq" => a == b,
+                "ne" => a != b,
+                "gt" => a > b,
+                "ge" => a >= b,
+                "lt" => a < b,
+                _ => a <= b,
+            })
+        },
+        "and" | "or" | "xor" => match (stack.pop()?, stack.pop()?) {
+            (PsValue::Bool(b), PsValue::Bool(a)) => PsValue::Bool(match name {
+                "and" => a && b,
+                "or" => a || b,
+                _ => a ^ b,
+            }),
+            (b, a) => {
+                let (a, b) = (a.number() as i64, b.number() as i64);
+                PsValue::Number(match name {
+                    "and" => a & b,
+                    "or" => a | b,
+                    _ => a ^ b,
+                } as f64)
+            },
+        },
+        "not" => match stack.pop()? {
+            PsValue::Bool(b) => PsValue::Bool(!b),
+            PsValue::Number(x) => PsValue::Number(!(x as i64) as f64),
+        },
+        "pop" => {
+            stack.pop()?;
+            return Some(());
+        },
+        "dup" => *stack.last()?,
+        "exch" => {
+            let (b, a) = (stack.pop()?, stack.pop()?);
+            stack.push(b);
+            a
+        },
+        "copy" => {
+            let n = pop()? as usize;
+            let start = stack.len().checked_sub(n)?;
+            if stack.len() + n > MAX_STACK {
+                return None;
+            }
+            stack.extend_from_within(start..);
+            return Some(());
+        },
+        "index" => {
+            let n = pop()? as usize;
+            *stack.get(stack.len().checked_sub(n.checked_add(1)?)?)?
+        },
+        "roll" => {
+            let (j, n) = (pop()? as i64, pop()? as usize);
+            let start = stack.len().checked_sub(n)?;
+            if n > 0 {
+                stack[start..].rotate_right(j.rem_euclid(n as i64) as usize);
+            }
+            return Some(());
+        },
+        _ => return None,
+    };
+    stack.push(value);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    fn sampled(size: Vec<Object>) -> Object {
+        Object::Stream(Stream::new(
+            dictionary! {
+                "FunctionType" => 0,
+                "Domain" => vec![0.into(), 1.into(), 0.into(), 1.into()],
+                "Range" => vec![0.into(), 1.into()],
+                "Size" => size,
+                "BitsPerSample" => 8,
+            },
+            vec![0, 255, 255, 0],
+        ))
+    }
+
+    fn postscript(program: &str) -> Function {
+        let object = Object::Stream(Stream::new(
+            dictionary! { "FunctionType" => 4, "Domain" => vec![0.into(), 1.into()] },
+            program.as_bytes().to_vec(),
+        ));
+        Function::load(&Document::with_version("1.7"), &object).unwrap()
+    }
+
+    #[test]
+    fn sampled_functions_interpolate() {
+        let function = Function::load(&Document::with_version("1.7"), &sampled(vec![2.into(), 2.into()])).unwrap();
+        assert_eq!(function.eval(&[0.0, 0.0]), [0.0]);
+        assert_eq!(function.eval(&[1.0, 0.0]), [1.0]);
+        assert_eq!(function.eval(&[0.5, 0.5]), [0.5]);
+    }
+
+    #[test]
+    fn oversized_sample_tables_are_rejected() {
+        let huge = Object::Real(1e12);
+        assert!(Function::load(&Document::with_version("1.7"), &sampled(vec![huge.clone(), huge])).is_none());
+    }
+
+    #[test]
+    fn out_of_range_stack_operands_stop_the_program() {
+        assert_eq!(postscript("{ 2 mul 0 index }").eval(&[0.25]), [0.5, 0.5]);
+        assert_eq!(postscript("{ 18446744073709551615 index }").eval(&[0.25]), [0.25]);
+        assert_eq!(postscript("{ 1e30 copy }").eval(&[0.25]), [0.25]);
+    }
+}
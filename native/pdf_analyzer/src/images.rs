@@ -0,0 +1,368 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, content::Operation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::common::{concat_matrix, load_document, operands_to_matrix, transform_bounds, CornerCoordinates, Dimensions, Matrix, IDENTITY_MATRIX};
+use crate::error::{PdfError, WarningKind};
+use crate::pdf_image::{decode_content, decode_image, image_data, image_entry, ImageEncoding};
+use crate::security::{encryption_info, EncryptionInfo};
+use crate::render::{inherited, page_content, MAX_FORM_DEPTH};
+
+// Options for image extraction, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ImageOptions {
+    // Write images to `<output_dir>/page-<n>-image-<i>.<ext>`; when absent the
+    // bytes are returned base64-encoded in `data`
+    pub output_dir: Option<String>,
+    pub pages: Option<Vec<u32>>,
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExtractedImage {
+    pub page: u32,
+    // Order of the image in the page's content
+    pub index: usize,
+    // Resource name for XObjects, None for inline images
+    pub name: Option<String>,
+    pub object_id: Option<(u32, u16)>,
+    pub inline: bool,
+    pub width: i64,
+    pub height: i64,
+    pub bits_per_component: i64,
+    pub color_space: String,
+    pub filters: Vec<String>,
+    pub has_alpha: bool,
+    // Placement on the page in default user space
+    pub corners: CornerCoordinates,
+    pub dimensions: Dimensions,
+    // "jpeg", "jp2" or "png"
+    pub format: Option<String>,
+    pub path: Option<String>,
+    pub data: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImageExtractionResult {
+    pub total_pages: u32,
+    pub total_images: usize,
+    pub images: Vec<ExtractedImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+// Encoded output for one image; XObjects drawn several times are encoded once
+#[derive(Clone, Default)]
+struct SavedImage {
+    format: Option<String>,
+    path: Option<String>,
+    data: Option<String>,
+    error: Option<String>,
+}
+
+pub fn extract_images(path: &str, options: &ImageOptions) -> Result<ImageExtractionResult, PdfError> {
+    let doc = load_document(path, options.password.as_deref())?;
+    if let Some(dir) = &options.output_dir {
+        std::fs::create_dir_all(dir).map_err(|e| PdfError::Io(format!("Failed to create {}: {}", dir, e)))?;
+    }
+
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+    let mut saved = HashMap::new();
+    let mut images = Vec::new();
+
+    for (page_num, page_id) in pages {
+        if options.pages.as_ref().is_some_and(|selected| !selected.contains(&page_num)) {
+            continue;
+        }
+
+        let content = decode_content(&page_content(&doc, page_id)).map_err(|e| PdfError::ContentStream(WarningKind::DecodeError, format!("Failed to decode content of page {}: {}", page_num, e)))?;
+        let resources = inherited(&doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok());
+
+        let mut walker = ImageWalker {
+            doc: &doc,
+            page: page_num,
+            output_dir: options.output_dir.as_deref(),
+            saved: &mut saved,
+            images: Vec::new(),
+        };
+        walker.walk(&content.operations, resources, IDENTITY_MATRIX, 0);
+        images.extend(walker.images);
+    }
+
+    Ok(ImageExtractionResult { total_pages, total_images: images.len(), images, encryption: encryption_info(&doc) })
+}
+
+struct ImageWalker<'a, 's> {
+    doc: &'a Document,
+    page: u32,
+    output_dir: Option<&'a str>,
+    saved: &'s mut HashMap<ObjectId, SavedImage>,
+    images: Vec<ExtractedImage>,
+}
+
+impl<'a> ImageWalker<'a, '_> {
+    fn walk(&mut self, operations: &[Operation], resources: Option<&'a Dictionary>, initial: Matrix, depth: usize) {
+        let mut ctm = initial;
+        let mut stack: Vec<Matrix> = Vec::new();
+
+        for op in operations {
+            match op.operator.as_str() {
+                "q" => stack.push(ctm),
+                "Q" => {
+                    if let Some(saved) = stack.pop() {
+                        ctm = saved;
+                    }
+                },
+                "cm" => {
+                    if let Some(m) = operands_to_matrix(&op.operands) {
+                        ctm = concat_matrix(&ctm, &m);
+                    }
+                },
+                "Do" => {
+                    let Some(Object::Name(name)) = op.operands.first() else { continue };
+                    let Some(entry) = resources
+                        .and_then(|res| res.get(b"XObject").ok())
+                        .and_then(|obj| self.doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_dict().ok())
+                        .and_then(|xobjects| xobjects.get(name).ok())
+                    else {
+                        continue;
+                    };
+                    let object_id = entry.as_reference().ok();
+                    let Ok((_, Object::Stream(xobject))) = self.doc.dereference(entry) else { continue };
+
+                    match xobject.dict.get(b"Subtype").and_then(Object::as_name) {
+                        Ok(b"Image") => self.record(xobject, Some(name), object_id, resources, &ctm),
+                        Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                            let matrix = xobject
+                                .dict
+                                .get(b"Matrix")
+                                .ok()
+                                .and_then(|obj| obj.as_array().ok())
+                                .and_then(|values| operands_to_matrix(values))
+                                .unwrap_or(IDENTITY_MATRIX);
+                            let form_resources = xobject
+                                .dict
+                                .get(b"Resources")
+                                .ok()
+                                .and_then(|obj| self.doc.dereference(obj).ok())
+                                .and_then(|(_, obj)| obj.as_dict().ok())
+                                .or(resources);
+                            let data = xobject.decompressed_content().unwrap_or_else(|_| xobject.content.clone());
+                            if let Ok(content) = decode_content(&data) {
+                                self.walk(&content.operations, form_resources, concat_matrix(&ctm, &matrix), depth + 1);
+                            }
+                        },
+                        _ => {},
+                    }
+                },
+                "BI" => {
+                    if let Some(Object::Stream(image)) = op.operands.first() {
+                        self.record(image, None, None, resources, &ctm);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn record(&mut self, image: &Stream, name: Option<&[u8]>, object_id: Option<ObjectId>, resources: Option<&Dictionary>, ctm: &Matrix) {
+        let dict = &image.dict;
+        let int = |key: &[u8]| {
+            image_entry(dict, key)
+                .and_then(|obj| self.doc.dereference(obj).ok())
+                .and_then(|(_, obj)| obj.as_i64().ok())
+        };
+        let is_mask = image_entry(dict, b"ImageMask").and_then(|obj| obj.as_bool().ok()).unwrap_or(false);
+        let has_alpha = is_mask
+            || dict.has(b"SMask")
+            || matches!(dict.get(b"Mask").ok().and_then(|obj| self.doc.dereference(obj).ok()), Some((_, Object::Stream(_))));
+
+        // Images are drawn into the unit square of the current transformation matrix
+        let (x1, y1, x2, y2) = transform_bounds(ctm, 0.0, 0.0, 1.0, 1.0);
+        let index = self.images.len();
+
+        let saved = match object_id.and_then(|id| self.saved.get(&id)) {
+            Some(saved) => saved.clone(),
+            None => {
+                let stem = format!("page-{}-image-{}", self.page, index);
+                let saved = self.save(image, resources, &stem);
+                if let Some(id) = object_id {
+                    self.saved.insert(id, saved.clone());
+                }
+                saved
+            },
+        };
+
+        self.images.push(ExtractedImage {
+            page: self.page,
+            index,
+            name: name.map(|n| String::from_utf8_lossy(n).to_string()),
+            object_id,
+            inline: object_id.is_none() && name.is_none(),
+            width: int(b"Width").unwrap_or(0),
+            height: int(b"Height").unwrap_or(0),
+            bits_per_component: if is_mask { 1 } else { int(b"BitsPerComponent").unwrap_or(0) },
+            color_space: if is_mask { "ImageMask".to_string() } else { self.color_space_name(dict) },
+            filters: self.filter_names(dict),
+            has_alpha,
+            corners: CornerCoordinates { x1, y1, x2, y2 },
+            dimensions: Dimensions { width: x2 - x1, height: y2 - y1 },
+            format: saved.format,
+            path: saved.path,
+            data: saved.data,
+            error: saved.error,
+        });
+    }
+
+    // Encode an image, passing JPEG and JPEG 2000 data through unchanged when possible
+    fn save(&self, image: &Stream, resources: Option<&Dictionary>, stem: &str) -> SavedImage {
+        let data = match image_data(self.doc, image) {
+            Ok(data) => data,
+            Err(e) => return SavedImage { error: Some(e), ..SavedImage::default() },
+        };
+
+        // Alpha and Decode arrays have to be applied, which requires re-encoding
+        let needs_decoding = image.dict.has(b"SMask") || image.dict.has(b"Mask") || image_entry(&image.dict, b"Decode").is_some();
+        let (format, extension, bytes) = match data.encoding {
+            ImageEncoding::Jpeg if !needs_decoding => ("jpeg", "jpg", data.bytes),
+            ImageEncoding::Jpeg2000 => ("jp2", "jp2", data.bytes),
+            ImageEncoding::Jbig2 | ImageEncoding::CcittFax => {
+                return SavedImage { error: Some(format!("{:?} images are not supported", data.encoding)), ..SavedImage::default() };
+            },
+            _ => match decode_image(self.doc, image, resources, [0.0; 3]).and_then(|decoded| encode_png(decoded.width, decoded.height, &decoded.rgba)) {
+                Ok(png) => ("png", "png", png),
+                Err(e) => return SavedImage { error: Some(e), ..SavedImage::default() },
+            },
+        };
+
+        let mut saved = SavedImage { format: Some(format.to_string()), ..SavedImage::default() };
+        match self.output_dir {
+            Some(dir) => {
+                let path = std::path::Path::new(dir).join(format!("{}.{}", stem, extension)).to_string_lossy().to_string();
+                match std::fs::write(&path, &bytes) {
+                    Ok(()) => saved.path = Some(path),
+                    Err(e) => saved.error = Some(format!("Failed to write {}: {}", path, e)),
+                }
+            },
+            None => saved.data = Some(base64_encode(&bytes)),
+        }
+        saved
+    }
+
+    fn color_space_name(&self, dict: &Dictionary) -> String {
+        match image_entry(dict, b"ColorSpace").and_then(|obj| self.doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+            Some(Object::Name(name)) => String::from_utf8_lossy(name).to_string(),
+            Some(Object::Array(items)) => items
+                .first()
+                .and_then(|obj| obj.as_name().ok())
+                .map(|name| String::from_utf8_lossy(name).to_string())
+                .unwrap_or_else(|| "Unknown".to_string()),
+            // JPX images may carry their color space in the codestream
+            _ => "Unknown".to_string(),
+        }
+    }
+
+    fn filter_names(&self, dict: &Dictionary) -> Vec<String> {
+        match image_entry(dict, b"Filter").and_then(|obj| self.doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+            Some(Object::Name(name)) => vec![String::from_utf8_lossy(name).to_string()],
+            Some(Object::Array(names)) => names
+                .iter()
+                .filter_map(|obj| obj.as_name().ok())
+                .map(|name| String::from_utf8_lossy(name).to_string())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    let opaque = rgba.chunks(4).all(|pixel| pixel[3] == 255);
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = if opaque {
+        encoder.set_color(png::ColorType::Rgb);
+        rgba.chunks(4).flat_map(|pixel| pixel[..3].iter().copied()).collect()
+    } else {
+        encoder.set_color(png::ColorType::Rgba);
+        rgba.to_vec()
+    };
+
+    let mut writer = encoder.write_header().map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer.write_image_data(&pixels).map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer.finish().map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(output)
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    fn extract(name: &str, content: Vec<u8>) -> ImageExtractionResult {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let path = std::env::temp_dir().join(format!("images_test_{}.pdf", name));
+        doc.save(&path).unwrap();
+        extract_images(path.to_str().unwrap(), &ImageOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn inline_images_are_placed_and_encoded() {
+        let mut content = b"q 20 0 0 10 100 200 cm BI /W 2 /H 1 /BPC 8 /CS /RGB ID ".to_vec();
+        content.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        content.extend_from_slice(b" EI Q");
+
+        let result = extract("inline", content);
+        assert_eq!(result.total_images, 1);
+        let image = &result.images[0];
+        assert!(image.inline && image.error.is_none());
+        assert_eq!((image.width, image.height, image.color_space.as_str()), (2, 1, "RGB"));
+        assert_eq!((image.corners.x1, image.corners.y1, image.corners.x2, image.corners.y2), (100.0, 200.0, 120.0, 210.0));
+        assert_eq!(image.format.as_deref(), Some("png"));
+        assert!(image.data.as_deref().is_some_and(|data| data.starts_with("iVBORw0KGgo")));
+    }
+
+    #[test]
+    fn huge_inline_image_is_reported_not_decoded() {
+        let content = b"q 50 0 0 50 0 0 cm BI /W 100000 /H 100000 /BPC 8 /CS /G ID abc EI Q \
+            q 10 0 0 10 0 0 cm BI /W 1 /H 1 /BPC 8 /CS /G ID \x80 EI Q".to_vec();
+
+        let result = extract("huge", content);
+        assert_eq!(result.total_images, 2);
+        assert!(result.images[0].error.as_deref().is_some_and(|error| error.contains("exceeds the limit")));
+        assert!(result.images[0].data.is_none());
+        assert!(result.images[1].error.is_none() && result.images[1].data.is_some());
+    }
+}
@@ -1,6 +1,7 @@
-use crate::common::{CornerCoordinates, Dimensions, DocumentAnalysisResult, Point, RectangleData};
+use crate::common::{CornerCoordinates, Dimensions, DocumentAnalysisResult, Point, RectangleData, Transparency};
 use lopdf::{Document, Object};
-use std::path::{Path, PathBuf};
+use crate::error::PdfError;
+use crate::security::encryption_info;
 
 #[derive(Debug, Clone)]
 struct SignatureField {
@@ -13,8 +14,7 @@ struct SignatureField {
     height: f64,
 }
 
-fn detect_signature_fields(pdf_path: &Path) -> Result<Vec<SignatureField>, String> {
-    let doc = Document::load(pdf_path).map_err(|e| format!("Failed to load PDF: {}", e))?;
+fn detect_signature_fields(doc: &Document) -> Vec<SignatureField> {
     let mut signature_fields = Vec::new();
 
     // Process each page
@@ -75,21 +75,20 @@ fn detect_signature_fields(pdf_path: &Path) -> Result<Vec<SignatureField>, Strin
         }
     }
 
-    Ok(signature_fields)
+    signature_fields
 }
 
 pub fn analyze_pdf_latex(
-    path: &str,
+    doc: &Document,
     _target_fill_color: Option<&str>,
     _target_stroke_color: Option<&str>,
-) -> Result<DocumentAnalysisResult, String> {
-    let path_buf = PathBuf::from(path);
-    let signature_fields = detect_signature_fields(&path_buf)
-        .map_err(|e| format!("Failed to detect signature fields: {}", e))?;
+) -> Result<DocumentAnalysisResult, PdfError> {
+    let signature_fields = detect_signature_fields(doc);
 
     let mut rectangles = Vec::new();
 
     for field in signature_fields {
+        let corners = CornerCoordinates { x1: field.x0, y1: field.y0, x2: field.x1, y2: field.y1 };
         let rect = RectangleData {
             operation: 0,
             position: Point {
@@ -100,35 +99,40 @@ pub fn analyze_pdf_latex(
                 width: field.width,
                 height: field.height,
             },
-            corners: CornerCoordinates {
-                x1: field.x0,
-                y1: field.y0,
-                x2: field.x1,
-                y2: field.y1,
-            },
+            corners: corners.clone(),
+            visible_region: Some(corners),
+            hidden: false,
+            occluded: 0.0,
+            raw_shapes: 1,
             fill_color: "Unknown".to_string(),
             stroke_color: "Unknown".to_string(),
             line_width: 1.0,
             border: 1.0,
+            transparency: Transparency::default(),
             font_name: None,
             operation_type: "SignatureField".to_string(),
             fill_color_operands: vec![],
             page: field.page,
-            fill_color_override: None,
+            field_id: String::new(),
+            label: None,
+            role: None,
+            destination: None,
+            marked_content: Vec::new(),
         };
 
         rectangles.push(rect);
     }
 
-    // Get total pages
-    let total_pages = match Document::load(&path_buf) {
-        Ok(doc) => doc.get_pages().len() as u32,
-        Err(_) => 0,
-    };
+    let total_pages = doc.get_pages().len() as u32;
 
     Ok(DocumentAnalysisResult {
         total_pages,
         total_rectangles: rectangles.len(),
         rectangles,
+        warnings: Vec::new(),
+        pages: None,
+        content_streams: None,
+        encryption: encryption_info(doc),
+        strategy: None,
     })
 }
@@ -1,26 +1,227 @@
-use rustler::{Encoder, Env, NifResult, Term};
+use rustler::{Atom, Binary, Encoder, Env, NifResult, ResourceArc, Term};
+use serde::Serialize;
 
 // Define modules
 mod common;
+mod error;
 mod typst;
 mod latex;
+mod overlay;
+mod stamp;
+mod bates;
+mod color;
+mod function;
+mod fonts;
+mod pdf_image;
+mod render;
+mod images;
+mod font_inventory;
+mod text;
+mod brand;
+mod security;
+mod document;
+mod parallel;
+mod batch;
+mod field_id;
+mod engine;
+mod destinations;
+mod marked_content;
+mod optional_content;
+mod anchors;
+mod merge;
 
 // Import from modules
-use common::{TARGET_FILL_COLOR, TARGET_STROKE_COLOR, DocumentAnalysisResult};
+use common::{load_document, TARGET_FILL_COLOR, TARGET_STROKE_COLOR, AnalysisOptions, DocumentAnalysisResult};
+use lopdf::Document;
+use document::PdfDocument;
+use error::PdfError;
 
-pub fn analyze_pdf(path: &str, engine: Option<&str>) -> Result<DocumentAnalysisResult, String> {
-    let engine_type = engine.unwrap_or("typst");
+pub fn analyze_pdf(path: &str, engine: Option<&str>, options: &AnalysisOptions) -> Result<DocumentAnalysisResult, PdfError> {
+    let doc = load_document(path, options.password.as_deref())?;
+    analyze_document(&doc, engine, options)
+}
 
-    match engine_type {
-        "latex" => latex::analyze_pdf_latex(path, Some(TARGET_FILL_COLOR), Some(TARGET_STROKE_COLOR)),
-        _ => typst::analyze_pdf_typst(path, Some(TARGET_FILL_COLOR), Some(TARGET_STROKE_COLOR)),
-    }
+pub fn analyze_document(doc: &Document, engine: Option<&str>, options: &AnalysisOptions) -> Result<DocumentAnalysisResult, PdfError> {
+    let strategy = engine::strategy(doc, engine::Engine::parse(engine)?);
+
+    let latex = strategy
+        .runs_latex()
+        .then(|| latex::analyze_pdf_latex(doc, Some(TARGET_FILL_COLOR), Some(TARGET_STROKE_COLOR)))
+        .transpose()?;
+    let mut result = match (strategy.runs_typst(), latex) {
+        (false, Some(latex)) => latex,
+        (_, latex) => {
+            let mut result = typst::analyze_pdf_typst(doc, Some(TARGET_FILL_COLOR), Some(TARGET_STROKE_COLOR), options)?;
+            // Both scans ran: form fields follow the rectangles
+            if let Some(latex) = latex {
+                result.rectangles.extend(latex.rectangles);
+            }
+            result
+        },
+    };
+    let prefix = options.destination_prefix.as_deref().unwrap_or(destinations::DEFAULT_DESTINATION_PREFIX);
+    destinations::attach_named_fields(doc, &mut result.rectangles, prefix);
+    result.total_rectangles = result.rectangles.len();
+    field_id::assign_field_ids(doc, &mut result.rectangles);
+    result.strategy = Some(strategy);
+    Ok(result)
 }
 
-#[rustler::nif(name = "analyze_pdf_nif")]
-fn analyze_pdf_nif<'a>(env: Env<'a>, path: &str, _target_fill_color: Option<&str>, _target_stroke_color: Option<&str>, engine: Option<&str>) -> NifResult<Term<'a>> {
+#[rustler::nif(name = "analyze_pdf_nif", schedule = "DirtyCpu")]
+fn analyze_pdf_nif<'a>(env: Env<'a>, path: &str, _target_fill_color: Option<&str>, _target_stroke_color: Option<&str>, engine: Option<&str>, options_json: &str) -> NifResult<Term<'a>> {
     // Ignore the color parameters and use the constants defined at the top of the file
-    match analyze_pdf(path, engine) {
+    let result = decode_options::<AnalysisOptions>(options_json)
+        .and_then(|options| analyze_pdf(path, engine, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "stamp_pdf_nif", schedule = "DirtyCpu")]
+fn stamp_pdf_nif<'a>(env: Env<'a>, input_path: &str, output_path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<stamp::StampOptions>(options_json)
+        .and_then(|options| stamp::stamp_pdf(input_path, output_path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "bates_number_nif", schedule = "DirtyCpu")]
+fn bates_number_nif<'a>(env: Env<'a>, files: Vec<(String, String)>, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<bates::BatesOptions>(options_json)
+        .and_then(|options| bates::bates_number(&files, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "render_pages_nif", schedule = "DirtyCpu")]
+fn render_pages_nif<'a>(env: Env<'a>, path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<render::RenderOptions>(options_json)
+        .and_then(|options| render::render_pdf(path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "extract_images_nif", schedule = "DirtyCpu")]
+fn extract_images_nif<'a>(env: Env<'a>, path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<images::ImageOptions>(options_json)
+        .and_then(|options| images::extract_images(path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "font_inventory_nif", schedule = "DirtyCpu")]
+fn font_inventory_nif<'a>(env: Env<'a>, path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<font_inventory::FontInventoryOptions>(options_json)
+        .and_then(|options| font_inventory::font_inventory(path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "check_brand_nif", schedule = "DirtyCpu")]
+fn check_brand_nif<'a>(env: Env<'a>, path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<brand::BrandOptions>(options_json)
+        .and_then(|options| brand::check_brand(path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "encrypt_pdf_nif", schedule = "DirtyCpu")]
+fn encrypt_pdf_nif<'a>(env: Env<'a>, input_path: &str, output_path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<security::EncryptOptions>(options_json)
+        .and_then(|options| security::encrypt_pdf(input_path, output_path, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "open_pdf_nif", schedule = "DirtyCpu")]
+fn open_pdf_nif<'a>(env: Env<'a>, path: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<document::OpenOptions>(options_json)
+        .and_then(|options| load_document(path, options.password.as_deref()));
+    match result {
+        Ok(doc) => Ok((atoms::ok(), ResourceArc::new(PdfDocument { doc })).encode(env)),
+        Err(e) => encode_error(env, e),
+    }
+}
+
+#[rustler::nif(name = "document_rectangles_nif", schedule = "DirtyCpu")]
+fn document_rectangles_nif<'a>(env: Env<'a>, handle: ResourceArc<PdfDocument>, engine: Option<&str>, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<AnalysisOptions>(options_json)
+        .and_then(|options| analyze_document(&handle.doc, engine, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "document_fields_nif", schedule = "DirtyCpu")]
+fn document_fields_nif(env: Env<'_>, handle: ResourceArc<PdfDocument>) -> NifResult<Term<'_>> {
+    encode_result(env, Ok(document::form_fields(&handle.doc)))
+}
+
+#[rustler::nif(name = "document_text_nif", schedule = "DirtyCpu")]
+fn document_text_nif<'a>(env: Env<'a>, handle: ResourceArc<PdfDocument>, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<document::PageOptions>(options_json)
+        .map(|options| document::page_text(&handle.doc, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "document_metadata_nif")]
+fn document_metadata_nif(env: Env<'_>, handle: ResourceArc<PdfDocument>) -> NifResult<Term<'_>> {
+    encode_result(env, Ok(document::metadata(&handle.doc)))
+}
+
+#[rustler::nif(name = "document_geometry_nif")]
+fn document_geometry_nif<'a>(env: Env<'a>, handle: ResourceArc<PdfDocument>, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<document::PageOptions>(options_json)
+        .map(|options| document::page_geometry(&handle.doc, &options));
+    encode_result(env, result)
+}
+
+#[rustler::nif(name = "find_anchors_nif", schedule = "DirtyCpu")]
+fn find_anchors_nif<'a>(env: Env<'a>, input_path: &str, output_path: Option<&str>, options_json: &str) -> NifResult<Term<'a>> {
+    let result = decode_options::<anchors::AnchorOptions>(options_json)
+        .and_then(|options| anchors::find_anchors(input_path, output_path, &options));
+    encode_result(env, result)
+}
+
+// Items are `{:path | :binary, data, engine, options_json}` tuples. The batch
+// fails as a whole only for invalid batch options; each item gets its own
+// `{:ok, json}` or `{:error, {reason, details}}`.
+#[rustler::nif(name = "analyze_batch_nif", schedule = "DirtyCpu")]
+fn analyze_batch_nif<'a>(env: Env<'a>, items: Vec<(Atom, Binary<'a>, Option<String>, String)>, options_json: &str) -> NifResult<Term<'a>> {
+    let items = items
+        .into_iter()
+        .map(|(kind, data, engine, options_json)| {
+            let source = if kind == atoms::path() {
+                batch::Source::Path(String::from_utf8_lossy(data.as_slice()).to_string())
+            } else if kind == atoms::binary() {
+                batch::Source::Binary(data.as_slice())
+            } else {
+                return Err(rustler::Error::BadArg);
+            };
+            Ok(batch::BatchItem { source, engine, options_json })
+        })
+        .collect::<NifResult<Vec<_>>>()?;
+
+    let results = decode_options::<batch::BatchOptions>(options_json)
+        .and_then(|options| batch::analyze_batch(&items, &options));
+    match results {
+        Ok(results) => {
+            let results = results.into_iter().map(|result| encode_result(env, result)).collect::<NifResult<Vec<Term>>>()?;
+            Ok((atoms::ok(), results).encode(env))
+        },
+        Err(e) => encode_error(env, e),
+    }
+}
+
+// Fields are JSON lists of analyzed rectangles or stored signature positions
+#[rustler::nif(name = "match_fields_nif")]
+fn match_fields_nif<'a>(env: Env<'a>, previous_json: &str, current_json: &str, options_json: &str) -> NifResult<Term<'a>> {
+    let decode_fields = |json: &str| {
+        serde_json::from_str::<Vec<field_id::FieldRef>>(json).map_err(|e| PdfError::InvalidArgument(format!("Invalid fields: {}", e)))
+    };
+    let result = decode_options::<field_id::MatchOptions>(options_json).and_then(|options| {
+        Ok(field_id::match_fields(&decode_fields(previous_json)?, &decode_fields(current_json)?, &options))
+    });
+    encode_result(env, result)
+}
+
+// Options arrive as a JSON object so new keys can be added without changing NIF arities
+fn decode_options<T: serde::de::DeserializeOwned>(options_json: &str) -> Result<T, PdfError> {
+    serde_json::from_str(options_json).map_err(|e| PdfError::InvalidArgument(format!("Invalid options: {}", e)))
+}
+
+// Encode a result as `{:ok, json_string}` or `{:error, {reason, details}}`
+fn encode_result<'a, T: Serialize>(env: Env<'a>, result: Result<T, PdfError>) -> NifResult<Term<'a>> {
+    match result {
         Ok(result) => {
             // Serialize the result to JSON
             let json = serde_json::to_string(&result).map_err(|e| {
@@ -33,18 +234,21 @@ fn analyze_pdf_nif<'a>(env: Env<'a>, path: &str, _target_fill_color: Option<&str
 
             Ok((ok_atom, json_string).encode(env))
         },
-        Err(e) => {
-            let error_atom = atoms::error().encode(env);
-            let error_string = e.encode(env);
-            Ok((error_atom, error_string).encode(env))
-        },
+        Err(e) => encode_error(env, e),
     }
 }
 
+fn encode_error(env: Env<'_>, error: PdfError) -> NifResult<Term<'_>> {
+    let reason = Atom::from_str(env, error.reason())?;
+    Ok((atoms::error(), (reason, error.details())).encode(env))
+}
+
 mod atoms {
     rustler::atoms! {
         ok,
-        error
+        error,
+        path,
+        binary
     }
 }
 
@@ -0,0 +1,154 @@
+use lopdf::{decode_text_string, Dictionary, Document, Object, content::Operation};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Nesting depth up to which referenced objects in property lists are followed
+const MAX_PROPERTY_DEPTH: usize = 8;
+
+// A marked-content sequence opened by BMC or BDC, e.g. a tagged-PDF structure
+// element or a region a template labeled with custom keys
+#[derive(Serialize, Debug, Clone)]
+pub struct MarkedContent {
+    pub tag: String,
+    pub mcid: Option<i64>,
+    // Replacement text of the sequence, e.g. "Client signature"
+    pub actual_text: Option<String>,
+    // Property list entries other than /MCID and /ActualText
+    pub properties: Map<String, Value>,
+}
+
+impl MarkedContent {
+    // Sequence opened by a BMC or BDC operator. BDC properties are an inline
+    // dictionary or the name of one in the /Properties resource.
+    pub fn begin(doc: &Document, op: &Operation, resources: Option<&Dictionary>) -> MarkedContent {
+        let tag = op.operands.first().and_then(|obj| obj.as_name().ok()).map(|name| String::from_utf8_lossy(name).to_string()).unwrap_or_default();
+        let properties = match op.operands.get(1) {
+            Some(Object::Dictionary(dict)) => Some(dict),
+            Some(Object::Name(name)) => resources
+                .and_then(|res| res.get(b"Properties").ok())
+                .and_then(|obj| deref(doc, obj).as_dict().ok())
+                .and_then(|properties| properties.get(name).ok())
+                .and_then(|obj| deref(doc, obj).as_dict().ok()),
+            _ => None,
+        };
+
+        let mut marked = MarkedContent { tag, mcid: None, actual_text: None, properties: Map::new() };
+        for (key, value) in properties.into_iter().flat_map(Dictionary::iter) {
+            match key.as_slice() {
+                b"MCID" => marked.mcid = deref(doc, value).as_i64().ok(),
+                b"ActualText" => marked.actual_text = decode_text_string(deref(doc, value)).ok(),
+                _ => {
+                    marked.properties.insert(String::from_utf8_lossy(key).to_string(), to_json(doc, value, 0));
+                },
+            }
+        }
+        marked
+    }
+}
+
+fn deref<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    doc.dereference(object).map(|(_, obj)| obj).unwrap_or(object)
+}
+
+// Property values as JSON: names and text strings become strings, streams
+// and objects nested too deeply become null
+fn to_json(doc: &Document, object: &Object, depth: usize) -> Value {
+    if depth > MAX_PROPERTY_DEPTH {
+        return Value::Null;
+    }
+    match object {
+        Object::Null | Object::Stream(_) => Value::Null,
+        Object::Boolean(value) => Value::Bool(*value),
+        Object::Integer(value) => Value::from(*value),
+        Object::Real(value) => Value::from(*value as f64),
+        Object::Name(name) => Value::String(String::from_utf8_lossy(name).to_string()),
+        Object::String(bytes, _) => {
+            Value::String(decode_text_string(object).unwrap_or_else(|_| String::from_utf8_lossy(bytes).to_string()))
+        },
+        Object::Array(items) => Value::Array(items.iter().map(|item| to_json(doc, item, depth + 1)).collect()),
+        Object::Dictionary(dict) => Value::Object(
+            dict.iter()
+                .map(|(key, value)| (String::from_utf8_lossy(key).to_string(), to_json(doc, value, depth + 1)))
+                .collect(),
+        ),
+        Object::Reference(_) => match doc.dereference(object) {
+            Ok((_, resolved)) => to_json(doc, resolved, depth + 1),
+            Err(_) => Value::Null,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::AnalysisOptions;
+    use lopdf::{dictionary, Stream};
+    use serde_json::json;
+
+    // One page whose /Properties resource holds the dictionary /Sig
+    fn document(content: &[u8]) -> Document {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let properties_id = doc.add_object(dictionary! {
+            "ActualText" => Object::string_literal("Client signature"),
+            "Role" => "signer",
+            "Nested" => dictionary! { "Order" => 2 },
+        });
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Properties" => dictionary! { "Sig" => properties_id } },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc
+    }
+
+    #[test]
+    fn rectangles_carry_the_sequences_they_are_painted_in() {
+        let content = b"/Field <</MCID 3>> BDC /Form /Sig BDC \
+            0.83922 1 0.95687 rg 0 0.7216 0.5804 RG 200 300 150 40 re B EMC EMC \
+            /Artifact BMC 0.83922 1 0.95687 rg 0 0.7216 0.5804 RG 200 400 150 40 re B EMC";
+        let doc = document(content);
+        let result = crate::analyze_document(&doc, Some("typst"), &AnalysisOptions::default()).unwrap();
+        assert_eq!(result.rectangles.len(), 2);
+
+        let nested = &result.rectangles[0].marked_content;
+        assert_eq!(nested.iter().map(|marked| marked.tag.as_str()).collect::<Vec<_>>(), ["Field", "Form"]);
+        assert_eq!(nested[0].mcid, Some(3));
+        assert_eq!(nested[1].actual_text.as_deref(), Some("Client signature"));
+        assert_eq!(Value::Object(nested[1].properties.clone()), json!({"Role": "signer", "Nested": {"Order": 2}}));
+
+        let artifact = &result.rectangles[1].marked_content;
+        assert_eq!(artifact.len(), 1);
+        assert_eq!(artifact[0].tag, "Artifact");
+        assert!(artifact[0].properties.is_empty());
+    }
+
+    #[test]
+    fn unknown_property_names_open_an_empty_sequence() {
+        let doc = document(b"");
+        let op = Operation::new("BDC", vec!["Span".into(), "Missing".into()]);
+        let marked = MarkedContent::begin(&doc, &op, None);
+        assert_eq!(marked.tag, "Span");
+        assert!(marked.mcid.is_none() && marked.actual_text.is_none() && marked.properties.is_empty());
+    }
+
+    #[test]
+    fn deeply_nested_properties_become_null() {
+        let doc = document(b"");
+        let mut value = Object::Integer(1);
+        for _ in 0..=MAX_PROPERTY_DEPTH {
+            value = Object::Array(vec![value]);
+        }
+        let mut innermost = &to_json(&doc, &value, 0);
+        for _ in 0..MAX_PROPERTY_DEPTH {
+            innermost = &innermost[0];
+        }
+        assert_eq!(innermost, &json!([null]));
+    }
+}
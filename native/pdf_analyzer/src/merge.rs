@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use crate::common::{CornerCoordinates, RectangleData};
+
+// Rectangles on a page overlapping at least this much, as intersection over
+// union, are one field
+pub const DEFAULT_MERGE_IOU: f64 = 0.9;
+// Rectangles whose edges are all this close, in points, are one field however
+// small they are
+const EDGE_TOLERANCE: f64 = 1.0;
+
+// Merge rectangles that draw the same box into one field, e.g. the separate
+// fill and stroke `re` Typst emits for a bordered box. Each field takes its
+// fill from the shapes that fill and its stroke from the shapes that stroke,
+// keeps the geometry and position in the list of the shape painted first, and
+// counts the shapes it came from in `raw_shapes`. A rectangle is compared
+// with the first member of each field on its page only, not with the merged
+// bounds, so a chain of slightly shifted boxes does not collapse into one field.
+pub fn merge_duplicates(rectangles: Vec<RectangleData>, min_iou: f64) -> Vec<RectangleData> {
+    // Cluster of each rectangle, as the index of its first member
+    let mut cluster: Vec<usize> = (0..rectangles.len()).collect();
+    let mut first_members: HashMap<u32, Vec<usize>> = HashMap::new();
+    for i in 0..rectangles.len() {
+        let fields = first_members.entry(rectangles[i].page).or_default();
+        match fields.iter().find(|&&j| same_box(&rectangles[i].corners, &rectangles[j].corners, min_iou)) {
+            Some(&j) => cluster[i] = j,
+            None => fields.push(i),
+        }
+    }
+
+    let mut members: Vec<Vec<RectangleData>> = Vec::new();
+    let mut slots: Vec<Option<usize>> = vec![None; rectangles.len()];
+    for (rect, first) in rectangles.into_iter().zip(cluster) {
+        let slot = *slots[first].get_or_insert_with(|| {
+            members.push(Vec::new());
+            members.len() - 1
+        });
+        members[slot].push(rect);
+    }
+    members.into_iter().map(merge).collect()
+}
+
+fn same_box(a: &CornerCoordinates, b: &CornerCoordinates, min_iou: f64) -> bool {
+    let (a, b) = (normalized(a), normalized(b));
+    let close = |x: f64, y: f64| (x - y).abs() <= EDGE_TOLERANCE;
+    if close(a.0, b.0) && close(a.1, b.1) && close(a.2, b.2) && close(a.3, b.3) {
+        return true;
+    }
+    let intersection = (a.2.min(b.2) - a.0.max(b.0)).max(0.0) * (a.3.min(b.3) - a.1.max(b.1)).max(0.0);
+    let union = (a.2 - a.0) * (a.3 - a.1) + (b.2 - b.0) * (b.3 - b.1) - intersection;
+    union > 0.0 && intersection / union >= min_iou
+}
+
+fn normalized(corners: &CornerCoordinates) -> (f64, f64, f64, f64) {
+    (corners.x1.min(corners.x2), corners.y1.min(corners.y2), corners.x1.max(corners.x2), corners.y1.max(corners.y2))
+}
+
+fn merge(mut members: Vec<RectangleData>) -> RectangleData {
+    if members.len() == 1 {
+        return members.remove(0);
+    }
+    let fills = |rect: &&RectangleData| matches!(rect.operation_type.as_str(), "Fill only" | "Fill and Stroke");
+    let strokes = |rect: &&RectangleData| matches!(rect.operation_type.as_str(), "Stroke only" | "Fill and Stroke");
+    let mut field = members[0].clone();
+
+    let fill = members.iter().find(fills);
+    let stroke = members.iter().find(strokes);
+    if let Some(fill) = fill {
+        field.fill_color = fill.fill_color.clone();
+        field.fill_color_operands = fill.fill_color_operands.clone();
+        field.transparency.fill_opacity = fill.transparency.fill_opacity;
+    }
+    if let Some(stroke) = stroke {
+        field.stroke_color = stroke.stroke_color.clone();
+        field.line_width = stroke.line_width;
+        field.border = stroke.border;
+        field.transparency.stroke_opacity = stroke.transparency.stroke_opacity;
+    }
+    field.operation_type = match (fill.is_some(), stroke.is_some()) {
+        (true, true) => "Fill and Stroke".to_string(),
+        (true, false) => "Fill only".to_string(),
+        (false, true) => "Stroke only".to_string(),
+        (false, false) => field.operation_type,
+    };
+
+    // The field shows wherever any of its shapes does
+    if let Some(shown) = members.iter().find(|rect| !rect.hidden) {
+        field.visible_region = shown.visible_region.clone();
+        field.hidden = false;
+    }
+    if let Some(occluded) = members.iter().filter(|rect| !rect.hidden).map(|rect| rect.occluded).reduce(f64::min) {
+        field.occluded = occluded;
+    }
+    field.raw_shapes = members.iter().map(|rect| rect.raw_shapes).sum();
+    field
+}
@@ -0,0 +1,148 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, content::Operation};
+use std::collections::HashSet;
+
+// Nesting depth up to which visibility expressions and membership
+// dictionaries are evaluated
+const MAX_EXPRESSION_DEPTH: usize = 16;
+
+// Visibility of optional content groups in the default configuration
+// (/OCProperties /D) of a document
+pub struct OptionalContent {
+    hidden: HashSet<ObjectId>,
+}
+
+impl OptionalContent {
+    // Groups are ON unless the configuration's /BaseState is /OFF; the /ON and
+    // /OFF arrays then override the base state per group
+    pub fn load(doc: &Document) -> OptionalContent {
+        let mut hidden = HashSet::new();
+        let Some(properties) = doc.catalog().ok().and_then(|catalog| dict_entry(doc, catalog, b"OCProperties")) else {
+            return OptionalContent { hidden };
+        };
+
+        if let Some(config) = dict_entry(doc, properties, b"D") {
+            if config.get(b"BaseState").ok().and_then(|obj| obj.as_name().ok()) == Some(b"OFF".as_slice()) {
+                hidden.extend(group_ids(doc, properties, b"OCGs"));
+                for id in group_ids(doc, config, b"ON") {
+                    hidden.remove(&id);
+                }
+            }
+            hidden.extend(group_ids(doc, config, b"OFF"));
+        }
+        OptionalContent { hidden }
+    }
+
+    // Whether content in a BDC sequence is drawn. Only /OC sequences can hide
+    // content; their properties name an optional content group or membership
+    // dictionary in the /Properties resources.
+    pub fn marked_content_visible(&self, doc: &Document, op: &Operation, resources: Option<&Dictionary>) -> bool {
+        if op.operands.first().and_then(|obj| obj.as_name().ok()) != Some(b"OC".as_slice()) {
+            return true;
+        }
+        let properties = match op.operands.get(1) {
+            Some(Object::Name(name)) => resources
+                .and_then(|res| res.get(b"Properties").ok())
+                .and_then(|obj| doc.dereference(obj).ok())
+                .and_then(|(_, obj)| obj.as_dict().ok())
+                .and_then(|properties| properties.get(name).ok()),
+            other => other,
+        };
+        properties.is_none_or(|object| self.visible(doc, object, 0))
+    }
+
+    // Visibility of an optional content group or membership dictionary
+    fn visible(&self, doc: &Document, object: &Object, depth: usize) -> bool {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return true;
+        }
+        if let Object::Reference(id) = object {
+            if self.hidden.contains(id) {
+                return false;
+            }
+        }
+        let Ok((_, Object::Dictionary(dict))) = doc.dereference(object) else { return true };
+        if dict.get(b"Type").ok().and_then(|obj| obj.as_name().ok()) != Some(b"OCMD".as_slice()) {
+            return true;
+        }
+
+        // A visibility expression takes precedence over the policy
+        if let Ok(expression) = dict.get(b"VE") {
+            return self.expression(doc, expression, depth);
+        }
+        let groups: Vec<bool> = match dict.get(b"OCGs").ok().map(|obj| doc.dereference(obj).map(|(_, obj)| obj).unwrap_or(obj)) {
+            Some(Object::Array(items)) => items.iter().map(|item| self.visible(doc, item, depth + 1)).collect(),
+            Some(_) => dict.get(b"OCGs").map(|group| vec![self.visible(doc, group, depth + 1)]).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        if groups.is_empty() {
+            return true;
+        }
+        match dict.get(b"P").ok().and_then(|obj| obj.as_name().ok()) {
+            Some(b"AllOn") => groups.iter().all(|&on| on),
+            Some(b"AnyOff") => groups.iter().any(|&on| !on),
+            Some(b"AllOff") => groups.iter().all(|&on| !on),
+            _ => groups.iter().any(|&on| on),
+        }
+    }
+
+    // Evaluate a visibility expression such as [/And ocg1 [/Not ocg2]]
+    fn expression(&self, doc: &Document, object: &Object, depth: usize) -> bool {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return true;
+        }
+        let items = match doc.dereference(object) {
+            Ok((_, Object::Array(items))) => items,
+            _ => return self.visible(doc, object, depth + 1),
+        };
+        let mut operands = items.iter().skip(1).map(|item| self.expression(doc, item, depth + 1));
+        match items.first().and_then(|obj| obj.as_name().ok()) {
+            Some(b"And") => operands.all(|on| on),
+            Some(b"Or") => operands.any(|on| on),
+            Some(b"Not") => !operands.next().unwrap_or(false),
+            _ => true,
+        }
+    }
+}
+
+fn dict_entry<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Dictionary> {
+    dict.get(key).ok().and_then(|obj| doc.dereference(obj).ok()).and_then(|(_, obj)| obj.as_dict().ok())
+}
+
+// Groups referenced by an array entry such as /OFF
+fn group_ids(doc: &Document, dict: &Dictionary, key: &[u8]) -> Vec<ObjectId> {
+    dict.get(key)
+        .ok()
+        .and_then(|obj| doc.dereference(obj).ok())
+        .and_then(|(_, obj)| obj.as_array().ok())
+        .map(|items| items.iter().filter_map(|item| item.as_reference().ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    #[test]
+    fn cyclic_membership_dictionary_ends() {
+        let mut doc = Document::with_version("1.7");
+        let (single, listed) = (doc.new_object_id(), doc.new_object_id());
+        doc.objects.insert(single, Object::Dictionary(dictionary! {"Type" => "OCMD", "OCGs" => single}));
+        doc.objects.insert(listed, Object::Dictionary(dictionary! {"Type" => "OCMD", "OCGs" => vec![listed.into(), single.into()], "P" => "AllOn"}));
+
+        let content = OptionalContent { hidden: HashSet::new() };
+        assert!(content.visible(&doc, &Object::Reference(single), 0));
+        assert!(content.visible(&doc, &Object::Reference(listed), 0));
+    }
+
+    #[test]
+    fn cyclic_visibility_expression_ends() {
+        let mut doc = Document::with_version("1.7");
+        let (group, membership) = (doc.new_object_id(), doc.new_object_id());
+        doc.objects.insert(group, Object::Dictionary(dictionary! {"Type" => "OCG"}));
+        doc.objects.insert(membership, Object::Dictionary(dictionary! {"Type" => "OCMD", "VE" => vec!["And".into(), group.into(), membership.into()]}));
+
+        let content = OptionalContent { hidden: HashSet::from([group]) };
+        assert!(!content.visible(&doc, &Object::Reference(membership), 0));
+    }
+}
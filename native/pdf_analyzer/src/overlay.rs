@@ -0,0 +1,291 @@
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat, content::{Content, Operation}};
+use flate2::{write::ZlibEncoder, Compression};
+use std::io::Write;
+use crate::common::object_to_f64;
+use crate::error::PdfError;
+
+// Page box used for placement: [llx, lly, urx, ury]
+#[derive(Debug, Clone, Copy)]
+pub struct PageBox {
+    pub llx: f64,
+    pub lly: f64,
+    pub urx: f64,
+    pub ury: f64,
+}
+
+impl PageBox {
+    pub fn width(&self) -> f64 {
+        self.urx - self.llx
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ury - self.lly
+    }
+}
+
+// Resolve the MediaBox of a page, walking up the page tree for inherited values
+pub fn page_box(doc: &Document, page_id: ObjectId) -> PageBox {
+    let mut current = Some(page_id);
+    while let Some(id) = current {
+        let Ok(dict) = doc.get_dictionary(id) else { break };
+        if let Ok(media_box) = dict.get(b"MediaBox").and_then(|obj| doc.dereference(obj)).map(|(_, obj)| obj) {
+            if let Ok(values) = media_box.as_array() {
+                if values.len() >= 4 {
+                    return PageBox {
+                        llx: object_to_f64(&values[0]),
+                        lly: object_to_f64(&values[1]),
+                        urx: object_to_f64(&values[2]),
+                        ury: object_to_f64(&values[3]),
+                    };
+                }
+            }
+        }
+        current = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    // Default to US Letter
+    PageBox { llx: 0.0, lly: 0.0, urx: 612.0, ury: 792.0 }
+}
+
+// Find the resource dictionary that applies to a page, including inherited ones
+fn inherited_resources(doc: &Document, page_id: ObjectId) -> Option<Dictionary> {
+    let mut current = Some(page_id);
+    while let Some(id) = current {
+        let dict = doc.get_dictionary(id).ok()?;
+        if let Ok(resources) = dict.get(b"Resources") {
+            return match doc.dereference(resources) {
+                Ok((_, Object::Dictionary(res))) => Some(res.clone()),
+                _ => None,
+            };
+        }
+        current = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    None
+}
+
+// Register an object under /Resources/<category>/<name> for a page.
+// Inherited resources are copied onto the page so other pages are not affected.
+pub fn add_page_resource(doc: &mut Document, page_id: ObjectId, category: &[u8], name: &[u8], object_id: ObjectId) -> Result<(), PdfError> {
+    let page = doc.get_dictionary(page_id).map_err(|e| PdfError::CorruptXref(format!("Failed to read page: {}", e)))?;
+    let mut resources = match page.get(b"Resources") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id).cloned().unwrap_or_default(),
+        Ok(Object::Dictionary(dict)) => dict.clone(),
+        _ => inherited_resources(doc, page_id).unwrap_or_default(),
+    };
+
+    let mut entries = match resources.get(category) {
+        Ok(obj) => match doc.dereference(obj) {
+            Ok((_, Object::Dictionary(dict))) => dict.clone(),
+            _ => Dictionary::new(),
+        },
+        Err(_) => Dictionary::new(),
+    };
+    entries.set(name.to_vec(), Object::Reference(object_id));
+    resources.set(category.to_vec(), Object::Dictionary(entries));
+
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| PdfError::CorruptXref(format!("Failed to update page: {}", e)))?;
+    page.set("Resources", Object::Dictionary(resources));
+    Ok(())
+}
+
+// Pick a resource name that is not already used on the page
+pub fn unique_resource_name(doc: &Document, page_id: ObjectId, category: &[u8], prefix: &str) -> Vec<u8> {
+    let existing = inherited_resources(doc, page_id)
+        .and_then(|res| match res.get(category) {
+            Ok(obj) => match doc.dereference(obj) {
+                Ok((_, Object::Dictionary(dict))) => Some(dict.clone()),
+                _ => None,
+            },
+            Err(_) => None,
+        })
+        .unwrap_or_default();
+
+    let mut index = 0;
+    loop {
+        let candidate = format!("{}{}", prefix, index).into_bytes();
+        if !existing.has(&candidate) {
+            return candidate;
+        }
+        index += 1;
+    }
+}
+
+// Add an ExtGState with the given fill and stroke opacity and return its id
+pub fn add_opacity_state(doc: &mut Document, opacity: f64) -> ObjectId {
+    let opacity = opacity.clamp(0.0, 1.0) as f32;
+    doc.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => opacity,
+        "CA" => opacity,
+    })
+}
+
+// Wrap existing page content in q/Q and append the overlay content afterwards,
+// so the overlay always starts from the default graphics state.
+pub fn append_page_overlay(doc: &mut Document, page_id: ObjectId, operations: Vec<Operation>) -> Result<(), PdfError> {
+    let overlay = Content { operations }.encode().map_err(|e| PdfError::InvalidArgument(format!("Failed to encode overlay: {}", e)))?;
+    let existing = doc.get_page_contents(page_id);
+
+    // Streams are concatenated when the page is drawn and the last one may not
+    // end in whitespace, so the closing stream starts with a newline
+    let open_id = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let mut close_content = b"\nQ\n".to_vec();
+    close_content.extend_from_slice(&overlay);
+    let close_id = doc.add_object(compressed_stream(Dictionary::new(), &close_content));
+
+    let mut contents: Vec<Object> = Vec::with_capacity(existing.len() + 2);
+    contents.push(Object::Reference(open_id));
+    contents.extend(existing.into_iter().map(Object::Reference));
+    contents.push(Object::Reference(close_id));
+
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| PdfError::CorruptXref(format!("Failed to update page: {}", e)))?;
+    page.set("Contents", Object::Array(contents));
+    Ok(())
+}
+
+// Build a Flate-compressed stream from raw bytes
+pub fn compressed_stream(mut dict: Dictionary, data: &[u8]) -> Stream {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    match encoder.write_all(data).and_then(|_| encoder.finish()) {
+        Ok(compressed) => {
+            dict.set("Filter", "FlateDecode");
+            Stream::new(dict, compressed).with_compression(false)
+        },
+        Err(_) => Stream::new(dict, data.to_vec()),
+    }
+}
+
+// A TrueType font embedded as a simple font with WinAnsiEncoding
+pub struct EmbeddedFont {
+    pub font_id: ObjectId,
+    // Advance widths in 1/1000 em for codes 0..=255
+    widths: Vec<f64>,
+    ascent: f64,
+    descent: f64,
+}
+
+impl EmbeddedFont {
+    pub fn load(doc: &mut Document, font_path: &str) -> Result<Self, PdfError> {
+        let data = std::fs::read(font_path).map_err(|e| PdfError::read_failed(font_path, e))?;
+        let face = ttf_parser::Face::parse(&data, 0).map_err(|e| PdfError::InvalidArgument(format!("Failed to parse font {}: {}", font_path, e)))?;
+        let units_per_em = face.units_per_em() as f64;
+        let scale = |v: f64| v * 1000.0 / units_per_em;
+
+        let mut widths = vec![0.0; 256];
+        for (code, width) in widths.iter_mut().enumerate().skip(32) {
+            if let Some(ch) = win_ansi_char(code as u8) {
+                if let Some(glyph) = face.glyph_index(ch) {
+                    *width = scale(face.glyph_hor_advance(glyph).unwrap_or(0) as f64).round();
+                }
+            }
+        }
+
+        let base_font = face
+            .names()
+            .into_iter()
+            .find(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .and_then(|name| name.to_string())
+            .unwrap_or_else(|| {
+                std::path::Path::new(font_path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "EmbeddedFont".to_string())
+            })
+            .replace(' ', "");
+
+        let bbox = face.global_bounding_box();
+        let ascent = scale(face.ascender() as f64);
+        let descent = scale(face.descender() as f64);
+        let cap_height = scale(face.capital_height().unwrap_or(face.ascender()) as f64);
+
+        let mut file_dict = Dictionary::new();
+        file_dict.set("Length1", data.len() as i64);
+        let file_id = doc.add_object(compressed_stream(file_dict, &data));
+
+        let descriptor_id = doc.add_object(dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => Object::Name(base_font.clone().into_bytes()),
+            "Flags" => 32,
+            "FontBBox" => vec![
+                Object::Real(scale(bbox.x_min as f64) as f32),
+                Object::Real(scale(bbox.y_min as f64) as f32),
+                Object::Real(scale(bbox.x_max as f64) as f32),
+                Object::Real(scale(bbox.y_max as f64) as f32),
+            ],
+            "ItalicAngle" => Object::Real(face.italic_angle()),
+            "Ascent" => Object::Real(ascent as f32),
+            "Descent" => Object::Real(descent as f32),
+            "CapHeight" => Object::Real(cap_height as f32),
+            "StemV" => 80,
+            "FontFile2" => file_id,
+        });
+
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "TrueType",
+            "BaseFont" => Object::Name(base_font.into_bytes()),
+            "FirstChar" => 32,
+            "LastChar" => 255,
+            "Widths" => widths[32..].iter().map(|w| Object::Integer(*w as i64)).collect::<Vec<_>>(),
+            "Encoding" => "WinAnsiEncoding",
+            "FontDescriptor" => descriptor_id,
+        });
+
+        Ok(EmbeddedFont { font_id, widths, ascent, descent })
+    }
+
+    // Width of the encoded text in user space units
+    pub fn text_width(&self, encoded: &[u8], font_size: f64) -> f64 {
+        encoded.iter().map(|code| self.widths[*code as usize]).sum::<f64>() * font_size / 1000.0
+    }
+
+    pub fn ascent(&self, font_size: f64) -> f64 {
+        self.ascent * font_size / 1000.0
+    }
+
+    pub fn descent(&self, font_size: f64) -> f64 {
+        self.descent * font_size / 1000.0
+    }
+}
+
+// Characters in WinAnsiEncoding positions 0x80..=0x9F differ from Latin-1
+const WIN_ANSI_HIGH: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ'),
+];
+
+pub fn win_ansi_char(code: u8) -> Option<char> {
+    match code {
+        0x80..=0x9F => WIN_ANSI_HIGH[(code - 0x80) as usize],
+        0x20..=0x7E | 0xA0..=0xFF => Some(code as char),
+        _ => None,
+    }
+}
+
+// Encode a string as WinAnsi bytes, replacing unsupported characters with '?'
+pub fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|ch| match ch as u32 {
+            0x20..=0x7E | 0xA0..=0xFF => ch as u8,
+            _ => WIN_ANSI_HIGH
+                .iter()
+                .position(|c| *c == Some(ch))
+                .map(|pos| 0x80 + pos as u8)
+                .unwrap_or(b'?'),
+        })
+        .collect()
+}
+
+// Operations drawing a single line of text with the given text matrix
+pub fn text_operations(font_name: &[u8], font_size: f64, color: [f64; 3], matrix: [f64; 6], encoded: Vec<u8>) -> Vec<Operation> {
+    vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name(font_name.to_vec()), Object::Real(font_size as f32)]),
+        Operation::new("rg", color.iter().map(|c| Object::Real(*c as f32)).collect()),
+        Operation::new("Tm", matrix.iter().map(|v| Object::Real(*v as f32)).collect()),
+        Operation::new("Tj", vec![Object::String(encoded, StringFormat::Literal)]),
+        Operation::new("ET", vec![]),
+    ]
+}
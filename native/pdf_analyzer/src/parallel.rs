@@ -0,0 +1,75 @@
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::error::PdfError;
+
+// Largest pool a caller may ask for
+pub const MAX_THREADS: usize = 64;
+
+// Pools are created once per size and shared by all NIF calls
+static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+
+// Thread pool with the given number of workers, 0 for one per CPU
+pub fn thread_pool(threads: usize) -> Result<Arc<ThreadPool>, PdfError> {
+    if threads > MAX_THREADS {
+        return Err(PdfError::InvalidArgument(format!("threads must be at most {}, got {}", MAX_THREADS, threads)));
+    }
+
+    let mut pools = POOLS.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(pool.clone());
+    }
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("pdf_analyzer-{}", index))
+        .build()
+        .map_err(|e| PdfError::BudgetExceeded(format!("Failed to start {} analysis threads: {}", threads, e)))?;
+    let pool = Arc::new(pool);
+    pools.insert(threads, pool.clone());
+    Ok(pool)
+}
+
+// Pool for the `threads` option; None runs on the calling thread
+pub fn optional_pool(threads: Option<usize>) -> Result<Option<Arc<ThreadPool>>, PdfError> {
+    match threads {
+        None | Some(1) => Ok(None),
+        Some(threads) => thread_pool(threads).map(Some),
+    }
+}
+
+// Map the items on the pool, or sequentially without one. Results are in item
+// order whichever thread produced them.
+pub fn ordered_map<T: Sync, R: Send>(pool: Option<&ThreadPool>, items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    match pool {
+        None => items.iter().map(f).collect(),
+        Some(pool) => pool.install(|| items.par_iter().map(f).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_keep_item_order() {
+        let items: Vec<u64> = (0..200).collect();
+        let slow_square = |n: &u64| {
+            // Early items finish last
+            std::thread::sleep(std::time::Duration::from_micros(200 - n));
+            n * n
+        };
+        let pool = optional_pool(Some(4)).unwrap();
+        let expected: Vec<u64> = items.iter().map(|n| n * n).collect();
+        assert_eq!(ordered_map(pool.as_deref(), &items, slow_square), expected);
+        assert_eq!(ordered_map(None, &items, slow_square), expected);
+    }
+
+    #[test]
+    fn pools_are_shared_and_bounded() {
+        assert!(optional_pool(None).unwrap().is_none() && optional_pool(Some(1)).unwrap().is_none());
+        assert!(Arc::ptr_eq(&thread_pool(3).unwrap(), &thread_pool(3).unwrap()));
+        assert_eq!(thread_pool(MAX_THREADS + 1).unwrap_err().reason(), "invalid_argument");
+    }
+}
@@ -0,0 +1,471 @@
+use lopdf::{Dictionary, Document, Object, Stream, content::{Content, Operation}};
+use crate::color::{resolve_color_space, ColorSpace};
+use crate::common::object_to_f64;
+
+// Largest image that is decoded, about 160 MB of RGBA. Width and height come
+// from the file, so they are checked before any pixel buffer is allocated.
+pub const MAX_IMAGE_PIXELS: usize = 40_000_000;
+
+// Image filters that produce the final pixel encoding rather than raw samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageEncoding {
+    Raw,
+    Jpeg,
+    Jpeg2000,
+    Jbig2,
+    CcittFax,
+}
+
+// Image bytes with all generic (compression) filters removed
+pub struct ImageData {
+    pub bytes: Vec<u8>,
+    pub encoding: ImageEncoding,
+}
+
+// An image converted to 8-bit, non-premultiplied RGBA
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+// Look up an image dictionary key, accepting the abbreviated inline image form
+pub fn image_entry<'a>(dict: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let abbreviation: &[u8] = match key {
+        b"Width" => b"W",
+        b"Height" => b"H",
+        b"BitsPerComponent" => b"BPC",
+        b"ColorSpace" => b"CS",
+        b"ImageMask" => b"IM",
+        b"Decode" => b"D",
+        b"DecodeParms" => b"DP",
+        b"Filter" => b"F",
+        b"Interpolate" => b"I",
+        _ => key,
+    };
+    dict.get(key).or_else(|_| dict.get(abbreviation)).ok()
+}
+
+fn filter_names(doc: &Document, dict: &Dictionary) -> Vec<Vec<u8>> {
+    match image_entry(dict, b"Filter").and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+        Some(Object::Name(name)) => vec![name.clone()],
+        Some(Object::Array(names)) => names.iter().filter_map(|obj| obj.as_name().ok().map(|n| n.to_vec())).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn filter_params(doc: &Document, dict: &Dictionary, index: usize) -> Option<Dictionary> {
+    match image_entry(dict, b"DecodeParms").and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+        Some(Object::Dictionary(params)) if index == 0 => Some(params.clone()),
+        Some(Object::Array(items)) => items
+            .get(index)
+            .and_then(|obj| doc.dereference(obj).ok())
+            .and_then(|(_, obj)| obj.as_dict().ok().cloned()),
+        _ => None,
+    }
+}
+
+// Remove compression filters, stopping at the first image-specific filter
+pub fn image_data(doc: &Document, stream: &Stream) -> Result<ImageData, String> {
+    let mut bytes = stream.content.clone();
+
+    for (index, filter) in filter_names(doc, &stream.dict).iter().enumerate() {
+        let encoding = match filter.as_slice() {
+            b"DCTDecode" | b"DCT" => ImageEncoding::Jpeg,
+            b"JPXDecode" => ImageEncoding::Jpeg2000,
+            b"JBIG2Decode" => ImageEncoding::Jbig2,
+            b"CCITTFaxDecode" | b"CCF" => ImageEncoding::CcittFax,
+            b"ASCIIHexDecode" | b"AHx" => {
+                bytes = decode_ascii_hex(&bytes);
+                continue;
+            },
+            b"RunLengthDecode" | b"RL" => {
+                bytes = decode_run_length(&bytes);
+                continue;
+            },
+            b"FlateDecode" | b"Fl" | b"LZWDecode" | b"LZW" | b"ASCII85Decode" | b"A85" => {
+                let full_name: &str = match filter.as_slice() {
+                    b"FlateDecode" | b"Fl" => "FlateDecode",
+                    b"LZWDecode" | b"LZW" => "LZWDecode",
+                    _ => "ASCII85Decode",
+                };
+                let mut step = Dictionary::new();
+                step.set("Filter", Object::Name(full_name.as_bytes().to_vec()));
+                if let Some(params) = filter_params(doc, &stream.dict, index) {
+                    step.set("DecodeParms", params);
+                }
+                bytes = Stream::new(step, bytes)
+                    .decompressed_content()
+                    .map_err(|e| format!("Failed to decode {} image data: {}", full_name, e))?;
+                continue;
+            },
+            other => return Err(format!("Unsupported image filter: {}", String::from_utf8_lossy(other))),
+        };
+        return Ok(ImageData { bytes, encoding });
+    }
+
+    Ok(ImageData { bytes, encoding: ImageEncoding::Raw })
+}
+
+fn decode_ascii_hex(data: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = data
+        .iter()
+        .take_while(|b| **b != b'>')
+        .filter_map(|b| (*b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+fn decode_run_length(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let length = data[pos] as usize;
+        pos += 1;
+        match length {
+            128 => break,
+            0..=127 => {
+                let end = (pos + length + 1).min(data.len());
+                output.extend_from_slice(&data[pos..end]);
+                pos = end;
+            },
+            _ => {
+                if let Some(byte) = data.get(pos) {
+                    output.extend(std::iter::repeat_n(*byte, 257 - length));
+                }
+                pos += 1;
+            },
+        }
+    }
+    output
+}
+
+// Read `count` samples of `bits` bits from a row of packed image data
+fn unpack_row(row: &[u8], bits: usize, count: usize) -> Vec<u32> {
+    match bits {
+        8 => row.iter().take(count).map(|b| *b as u32).collect(),
+        16 => row.chunks(2).take(count).map(|pair| ((pair[0] as u32) << 8 | *pair.get(1).unwrap_or(&0) as u32) >> 8).collect(),
+        _ => (0..count)
+            .map(|i| {
+                let bit = i * bits;
+                let byte = row.get(bit / 8).copied().unwrap_or(0) as u32;
+                (byte >> (8 - bits - bit % 8)) & ((1 << bits) - 1)
+            })
+            .collect(),
+    }
+}
+
+fn decode_array(doc: &Document, dict: &Dictionary) -> Option<Vec<f64>> {
+    image_entry(dict, b"Decode")
+        .and_then(|obj| doc.dereference(obj).ok())
+        .and_then(|(_, obj)| obj.as_array().ok())
+        .map(|values| values.iter().map(object_to_f64).collect())
+}
+
+fn int_entry(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<i64> {
+    image_entry(dict, key)
+        .and_then(|obj| doc.dereference(obj).ok())
+        .and_then(|(_, obj)| obj.as_i64().ok())
+}
+
+// Decode an image XObject (or inline image) to RGBA. Stencil masks are painted
+// with `fill_rgb`; soft masks and stencil /Mask entries become the alpha channel.
+pub fn decode_image(doc: &Document, stream: &Stream, resources: Option<&Dictionary>, fill_rgb: [f64; 3]) -> Result<DecodedImage, String> {
+    let dict = &stream.dict;
+    let data = image_data(doc, stream)?;
+    let mut image = decode_pixels(doc, dict, data, resources, fill_rgb)?;
+
+    if let Some(Object::Stream(mask)) = dict.get(b"SMask").ok().and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+        if let Ok(alpha) = decode_gray_mask(doc, mask, false) {
+            apply_alpha(&mut image, &alpha);
+        }
+    } else if let Some(Object::Stream(mask)) = dict.get(b"Mask").ok().and_then(|obj| doc.dereference(obj).ok()).map(|(_, obj)| obj) {
+        // Explicit stencil mask: samples of 1 are masked out
+        if let Ok(alpha) = decode_gray_mask(doc, mask, true) {
+            apply_alpha(&mut image, &alpha);
+        }
+    }
+
+    Ok(image)
+}
+
+fn decode_pixels(doc: &Document, dict: &Dictionary, data: ImageData, resources: Option<&Dictionary>, fill_rgb: [f64; 3]) -> Result<DecodedImage, String> {
+    let width = int_entry(doc, dict, b"Width").unwrap_or(0).max(0) as usize;
+    let height = int_entry(doc, dict, b"Height").unwrap_or(0).max(0) as usize;
+
+    match data.encoding {
+        ImageEncoding::Jpeg => return decode_jpeg(doc, dict, &data.bytes),
+        ImageEncoding::Raw => {},
+        other => return Err(format!("Unsupported image encoding: {:?}", other)),
+    }
+    if width == 0 || height == 0 {
+        return Err("Image has no dimensions".to_string());
+    }
+    check_pixel_budget(width, height)?;
+
+    let is_mask = image_entry(dict, b"ImageMask").and_then(|obj| obj.as_bool().ok()).unwrap_or(false);
+    let bits = if is_mask { 1 } else { int_entry(doc, dict, b"BitsPerComponent").unwrap_or(8).max(0) as usize };
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16) {
+        return Err(format!("Unsupported bits per component: {}", bits));
+    }
+    let color_space = match image_entry(dict, b"ColorSpace") {
+        Some(obj) if !is_mask => resolve_color_space(doc, obj, resources),
+        _ => ColorSpace::DeviceGray,
+    };
+    let components = color_space.components();
+    let stride = width
+        .checked_mul(components)
+        .and_then(|samples| samples.checked_mul(bits))
+        .map(|row_bits| row_bits.div_ceil(8))
+        .filter(|stride| stride.checked_mul(height).is_some())
+        .ok_or_else(|| format!("Image of {}x{} pixels with {} components is too large", width, height, components))?;
+    let max_value = ((1u32 << bits.min(8)) - 1) as f64;
+    let decode = decode_array(doc, dict);
+    let is_indexed = matches!(color_space, ColorSpace::Indexed { .. });
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = data.bytes.get(y * stride..((y + 1) * stride).min(data.bytes.len())).unwrap_or(&[]);
+        let samples = unpack_row(row, bits, width * components);
+        for x in 0..width {
+            if is_mask {
+                let sample = samples.get(x).copied().unwrap_or(1);
+                let inverted = decode.as_ref().map(|d| d.first().copied().unwrap_or(0.0) > 0.5).unwrap_or(false);
+                let painted = (sample == 0) != inverted;
+                let [r, g, b] = fill_rgb;
+                rgba.extend_from_slice(&[(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, if painted { 255 } else { 0 }]);
+                continue;
+            }
+
+            let values: Vec<f64> = (0..components)
+                .map(|c| {
+                    let raw = samples.get(x * components + c).copied().unwrap_or(0) as f64;
+                    let (dmin, dmax) = match &decode {
+                        Some(d) if d.len() >= 2 * (c + 1) => (d[2 * c], d[2 * c + 1]),
+                        _ if is_indexed => (0.0, max_value),
+                        _ => (0.0, 1.0),
+                    };
+                    dmin + raw * (dmax - dmin) / max_value
+                })
+                .collect();
+            let [r, g, b] = color_space.to_rgb(&values);
+            rgba.extend_from_slice(&[(r * 255.0).round() as u8, (g * 255.0).round() as u8, (b * 255.0).round() as u8, 255]);
+        }
+    }
+
+    Ok(DecodedImage { width: width as u32, height: height as u32, rgba })
+}
+
+fn decode_jpeg(doc: &Document, dict: &Dictionary, bytes: &[u8]) -> Result<DecodedImage, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    decoder.read_info().map_err(|e| format!("Failed to decode JPEG image: {}", e))?;
+    let header = decoder.info().ok_or("JPEG image has no header")?;
+    check_pixel_budget(header.width as usize, header.height as usize)?;
+    let pixels = decoder.decode().map_err(|e| format!("Failed to decode JPEG image: {}", e))?;
+    let info = decoder.info().ok_or("JPEG image has no header")?;
+    let inverted_cmyk = decode_array(doc, dict).map(|d| d.first().copied().unwrap_or(0.0) > 0.5).unwrap_or(false)
+        // Adobe applications write CMYK JPEGs with inverted components
+        || bytes.windows(5).any(|w| w == b"Adobe");
+
+    let rgba = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        jpeg_decoder::PixelFormat::L16 => pixels.chunks(2).flat_map(|g| [g[0], g[0], g[0], 255]).collect(),
+        jpeg_decoder::PixelFormat::RGB24 => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => pixels
+            .chunks(4)
+            .flat_map(|p| {
+                let v = |i: usize| {
+                    let value = p[i] as f64 / 255.0;
+                    if inverted_cmyk { 1.0 - value } else { value }
+                };
+                let [r, g, b] = crate::color::cmyk_to_rgb(v(0), v(1), v(2), v(3));
+                [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255]
+            })
+            .collect(),
+    };
+
+    Ok(DecodedImage { width: info.width as u32, height: info.height as u32, rgba })
+}
+
+fn check_pixel_budget(width: usize, height: usize) -> Result<(), String> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_IMAGE_PIXELS => Ok(()),
+        _ => Err(format!("Image of {}x{} pixels exceeds the limit of {} pixels", width, height, MAX_IMAGE_PIXELS)),
+    }
+}
+
+// Decode a soft mask or stencil mask into one alpha byte per pixel
+fn decode_gray_mask(doc: &Document, stream: &Stream, stencil: bool) -> Result<DecodedImage, String> {
+    let data = image_data(doc, stream)?;
+    let mut mask = if stencil {
+        let mut dict = stream.dict.clone();
+        dict.set("ImageMask", true);
+        decode_pixels(doc, &dict, data, None, [0.0; 3])?
+    } else {
+        decode_pixels(doc, &stream.dict, data, None, [0.0; 3])?
+    };
+    if !stencil {
+        // Use the gray level as alpha
+        for pixel in mask.rgba.chunks_mut(4) {
+            pixel[3] = pixel[0];
+        }
+    }
+    Ok(mask)
+}
+
+// Multiply the image alpha with a mask, resampling the mask to the image size
+fn apply_alpha(image: &mut DecodedImage, mask: &DecodedImage) {
+    if mask.width == 0 || mask.height == 0 {
+        return;
+    }
+    for y in 0..image.height {
+        let my = (y as u64 * mask.height as u64 / image.height as u64) as u32;
+        for x in 0..image.width {
+            let mx = (x as u64 * mask.width as u64 / image.width as u64) as u32;
+            let alpha = mask.rgba[((my * mask.width + mx) * 4 + 3) as usize] as u32;
+            let index = ((y * image.width + x) * 4 + 3) as usize;
+            image.rgba[index] = (image.rgba[index] as u32 * alpha / 255) as u8;
+        }
+    }
+}
+
+// Placeholder operator used while inline images are lifted out of a content stream
+const INLINE_IMAGE_PLACEHOLDER: &str = "WraftInlineImage";
+
+// Decode a content stream, including inline images that use filters (which
+// lopdf's parser rejects). Every inline image becomes a `BI` operation whose
+// single operand is the image stream, matching lopdf's unfiltered form.
+pub fn decode_content(data: &[u8]) -> lopdf::Result<Content> {
+    let mut stripped = Vec::with_capacity(data.len());
+    let mut images: Vec<Stream> = Vec::new();
+    let mut copy_from = 0;
+    let mut pos = 0;
+
+    while let Some(start) = next_inline_image(data, pos) {
+        let Some((image, end)) = parse_inline_image(data, start) else { break };
+        stripped.extend_from_slice(&data[copy_from..start]);
+        stripped.extend_from_slice(format!(" {} {} ", images.len(), INLINE_IMAGE_PLACEHOLDER).as_bytes());
+        images.push(image);
+        copy_from = end;
+        pos = end;
+    }
+    stripped.extend_from_slice(&data[copy_from..]);
+
+    let mut content = Content::decode(&stripped)?;
+    if !images.is_empty() {
+        for op in content.operations.iter_mut() {
+            if op.operator == INLINE_IMAGE_PLACEHOLDER {
+                let index = op.operands.first().map(object_to_f64).unwrap_or(0.0) as usize;
+                if let Some(image) = images.get(index) {
+                    *op = Operation::new("BI", vec![Object::Stream(image.clone())]);
+                }
+            }
+        }
+    }
+    Ok(content)
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || b"()<>[]{}/%".contains(&byte)
+}
+
+fn is_token_at(data: &[u8], pos: usize, token: &[u8]) -> bool {
+    data[pos..].starts_with(token)
+        && (pos == 0 || is_delimiter(data[pos - 1]))
+        && data.get(pos + token.len()).map(|b| is_delimiter(*b)).unwrap_or(true)
+}
+
+// Find the next `BI` operator, skipping strings and comments
+fn next_inline_image(data: &[u8], mut pos: usize) -> Option<usize> {
+    while pos < data.len() {
+        match data[pos] {
+            b'(' => {
+                let mut depth = 0;
+                while pos < data.len() {
+                    match data[pos] {
+                        b'\\' => pos += 1,
+                        b'(' => depth += 1,
+                        b')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        },
+                        _ => {},
+                    }
+                    pos += 1;
+                }
+            },
+            b'%' => {
+                while pos < data.len() && !b"\r\n".contains(&data[pos]) {
+                    pos += 1;
+                }
+            },
+            b'<' if data.get(pos + 1) != Some(&b'<') => {
+                while pos < data.len() && data[pos] != b'>' {
+                    pos += 1;
+                }
+            },
+            b'B' if is_token_at(data, pos, b"BI") => return Some(pos),
+            _ => {},
+        }
+        pos += 1;
+    }
+    None
+}
+
+// Parse `BI <dict> ID <data> EI` starting at `start`, returning the image and the end offset
+fn parse_inline_image(data: &[u8], start: usize) -> Option<(Stream, usize)> {
+    let id_pos = (start + 2..data.len().saturating_sub(1)).find(|pos| is_token_at(data, *pos, b"ID"))?;
+    let mut dict_source = b"<<".to_vec();
+    dict_source.extend_from_slice(&data[start + 2..id_pos]);
+    dict_source.extend_from_slice(b">> WraftInlineDict");
+    let dict = Content::decode(&dict_source)
+        .ok()?
+        .operations
+        .into_iter()
+        .next()?
+        .operands
+        .into_iter()
+        .next()?
+        .as_dict()
+        .ok()?
+        .clone();
+
+    // A single whitespace byte separates ID from the data
+    let data_start = id_pos + 3;
+    let unfiltered_length = if image_entry(&dict, b"Filter").is_none() {
+        let get = |key: &[u8]| image_entry(&dict, key).map(object_to_f64).unwrap_or(0.0) as usize;
+        let is_mask = image_entry(&dict, b"ImageMask").and_then(|obj| obj.as_bool().ok()).unwrap_or(false);
+        let components = match image_entry(&dict, b"ColorSpace").and_then(|obj| obj.as_name().ok()) {
+            _ if is_mask => 1,
+            Some(b"DeviceRGB" | b"RGB") => 3,
+            Some(b"DeviceCMYK" | b"CMYK") => 4,
+            _ => 1,
+        };
+        let bits = if is_mask { 1 } else { get(b"BitsPerComponent").max(1) };
+        get(b"Width")
+            .checked_mul(components)
+            .and_then(|samples| samples.checked_mul(bits))
+            .and_then(|row_bits| row_bits.div_ceil(8).checked_mul(get(b"Height")))
+    } else {
+        dict.get(b"L").or_else(|_| dict.get(b"Length")).ok().map(|obj| object_to_f64(obj) as usize)
+    }
+    // A length past the end of the content is wrong, so EI is searched for instead
+    .filter(|len| *len <= data.len().saturating_sub(data_start));
+
+    let search_from = unfiltered_length.map(|len| (data_start + len).min(data.len())).unwrap_or(data_start);
+    let ei_pos = (search_from..data.len().saturating_sub(1))
+        .find(|pos| data[*pos..].starts_with(b"EI") && (*pos == 0 || data[*pos - 1].is_ascii_whitespace()) && data.get(pos + 2).map(|b| is_delimiter(*b)).unwrap_or(true))?;
+    let data_end = match unfiltered_length {
+        Some(len) => (data_start + len).min(ei_pos),
+        // Drop the whitespace before EI
+        None => ei_pos.saturating_sub(1).max(data_start),
+    };
+
+    let stream = Stream::new(dict, data.get(data_start..data_end)?.to_vec()).with_compression(false);
+    Some((stream, ei_pos + 2))
+}
@@ -0,0 +1,890 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, content::Operation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+use tiny_skia::{FillRule, LineCap, LineJoin, Mask, Paint, Path, PathBuilder, Pixmap, PixmapPaint, Stroke, StrokeDash, Transform};
+use crate::color::{resolve_color_space, ColorSpace};
+use crate::common::{load_document, object_to_f64, AnalysisOptions, RectangleData};
+use crate::error::{PdfError, WarningKind};
+use crate::fonts::PdfFont;
+use crate::overlay::{page_box, PageBox};
+use crate::pdf_image::{decode_content, decode_image};
+use crate::security::{encryption_info, EncryptionInfo};
+
+// Nesting limit for Form XObjects, guarding against reference cycles
+pub const MAX_FORM_DEPTH: usize = 16;
+// Largest resolution pages are rendered at
+pub const MAX_DPI: f64 = 600.0;
+// Pixel budget of one rendered page, about 160 MB of RGBA; a Letter page at
+// 600 dpi fits
+pub const MAX_PAGE_PIXELS: f64 = 40_000_000.0;
+// Annotation flags of annotations that are not shown on screen
+const HIDDEN_ANNOTATION: i64 = 1 << 1;
+const NO_VIEW_ANNOTATION: i64 = 1 << 5;
+
+// Options for rasterizing pages, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct RenderOptions {
+    pub dpi: f64,
+    pub pages: Option<Vec<u32>>,
+    pub output_dir: Option<String>,
+    // Outline the fields found by `analyze_pdf` with the given engine
+    pub highlight: bool,
+    pub engine: Option<String>,
+    // TrueType font used for text in fonts that are not embedded
+    pub fallback_font_path: Option<String>,
+    // User or owner password of encrypted documents
+    pub password: Option<String>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            dpi: 72.0,
+            pages: None,
+            output_dir: None,
+            highlight: false,
+            engine: None,
+            fallback_font_path: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RenderedPage {
+    pub page: u32,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RenderResult {
+    pub total_pages: u32,
+    pub pages: Vec<RenderedPage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+}
+
+// Render the selected pages of a PDF to `<output_dir>/page-<n>.png`
+pub fn render_pdf(path: &str, options: &RenderOptions) -> Result<RenderResult, PdfError> {
+    let output_dir = options.output_dir.as_deref().ok_or_else(|| PdfError::InvalidArgument("output_dir is required for rendering".to_string()))?;
+    check_dpi(options.dpi)?;
+    std::fs::create_dir_all(output_dir).map_err(|e| PdfError::Io(format!("Failed to create {}: {}", output_dir, e)))?;
+
+    let doc = load_document(path, options.password.as_deref())?;
+    let fallback = match &options.fallback_font_path {
+        Some(font_path) => Some(std::fs::read(font_path).map_err(|e| PdfError::read_failed(font_path, e))?),
+        None => None,
+    };
+    let fields = if options.highlight {
+        crate::analyze_document(&doc, options.engine.as_deref(), &AnalysisOptions::default())?.rectangles
+    } else {
+        Vec::new()
+    };
+
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+    let mut rendered = Vec::new();
+
+    for (page_num, page_id) in pages {
+        if let Some(selected) = &options.pages {
+            if !selected.contains(&page_num) {
+                continue;
+            }
+        }
+
+        let (mut pixmap, base) = render_page(&doc, page_id, options.dpi, fallback.as_deref())?;
+        let page_fields: Vec<&RectangleData> = fields.iter().filter(|field| field.page == page_num).collect();
+        highlight_fields(&mut pixmap, base, &page_fields);
+
+        let file_path = std::path::Path::new(output_dir).join(format!("page-{}.png", page_num));
+        pixmap.save_png(&file_path).map_err(|e| PdfError::Io(format!("Failed to write {}: {}", file_path.display(), e)))?;
+        rendered.push(RenderedPage {
+            page: page_num,
+            path: file_path.to_string_lossy().to_string(),
+            width: pixmap.width(),
+            height: pixmap.height(),
+        });
+    }
+
+    Ok(RenderResult { total_pages, pages: rendered, encryption: encryption_info(&doc) })
+}
+
+// Rasterize one page on a white background. Also returns the transform from
+// default user space to device pixels, for drawing overlays.
+pub fn render_page(doc: &Document, page_id: ObjectId, dpi: f64, fallback: Option<&[u8]>) -> Result<(Pixmap, Transform), PdfError> {
+    check_dpi(dpi)?;
+    let bounds = visible_box(doc, page_id);
+    let rotation = inherited(doc, page_id, b"Rotate").map(object_to_f64).unwrap_or(0.0) as i64;
+    let scale = (dpi / 72.0) as f32;
+    let (width, height) = (bounds.width() as f32 * scale, bounds.height() as f32 * scale);
+    let (llx, lly, urx, ury) = (bounds.llx as f32, bounds.lly as f32, bounds.urx as f32, bounds.ury as f32);
+
+    let (base, pixel_width, pixel_height) = match rotation.rem_euclid(360) {
+        90 => (Transform::from_row(0.0, scale, scale, 0.0, -scale * lly, -scale * llx), height, width),
+        180 => (Transform::from_row(-scale, 0.0, 0.0, scale, scale * urx, -scale * lly), width, height),
+        270 => (Transform::from_row(0.0, -scale, -scale, 0.0, scale * ury, scale * urx), height, width),
+        _ => (Transform::from_row(scale, 0.0, 0.0, -scale, -scale * llx, scale * ury), width, height),
+    };
+
+    let (pixel_width, pixel_height) = (pixel_width.abs().ceil().max(1.0), pixel_height.abs().ceil().max(1.0));
+    // Checked before allocating, a huge page box would otherwise take gigabytes
+    if pixel_width as f64 * pixel_height as f64 > MAX_PAGE_PIXELS {
+        return Err(PdfError::BudgetExceeded(format!("Page is too large to render at {} dpi", dpi)));
+    }
+    let mut pixmap = Pixmap::new(pixel_width as u32, pixel_height as u32)
+        .ok_or_else(|| PdfError::BudgetExceeded("Page is too large to render".to_string()))?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+
+    let resources = inherited(doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok());
+    let content = decode_content(&page_content(doc, page_id)).map_err(|e| PdfError::ContentStream(WarningKind::DecodeError, format!("Failed to decode page content: {}", e)))?;
+
+    let mut renderer = Renderer::new(doc, &mut pixmap, fallback);
+    let mut state = RenderState::new(base);
+    renderer.run(&content.operations, resources, &mut state, 0);
+    renderer.draw_annotations(page_id, base);
+
+    Ok((pixmap, base))
+}
+
+fn check_dpi(dpi: f64) -> Result<(), PdfError> {
+    if dpi.is_finite() && dpi > 0.0 && dpi <= MAX_DPI {
+        Ok(())
+    } else {
+        Err(PdfError::InvalidArgument(format!("dpi must be greater than 0 and at most {}, got {}", MAX_DPI, dpi)))
+    }
+}
+
+fn highlight_fields(pixmap: &mut Pixmap, base: Transform, fields: &[&RectangleData]) {
+    let mut fill = Paint::default();
+    fill.set_color_rgba8(255, 64, 64, 64);
+    let mut outline = Paint::default();
+    outline.set_color_rgba8(220, 0, 0, 255);
+    let stroke = Stroke { width: 2.0, ..Stroke::default() };
+
+    for field in fields {
+        let c = &field.corners;
+        let rect = tiny_skia::Rect::from_ltrb(c.x1.min(c.x2) as f32, c.y1.min(c.y2) as f32, c.x1.max(c.x2) as f32, c.y1.max(c.y2) as f32);
+        if let Some(path) = rect.map(PathBuilder::from_rect).and_then(|path| path.transform(base)) {
+            pixmap.fill_path(&path, &fill, FillRule::Winding, Transform::identity(), None);
+            pixmap.stroke_path(&path, &outline, &stroke, Transform::identity(), None);
+        }
+    }
+}
+
+// Concatenate the content streams of a page, separating them with whitespace
+pub fn page_content(doc: &Document, page_id: ObjectId) -> Vec<u8> {
+    let mut content = Vec::new();
+    for stream_id in doc.get_page_contents(page_id) {
+        if let Ok(stream) = doc.get_object(stream_id).and_then(Object::as_stream) {
+            content.extend(stream.decompressed_content().unwrap_or_else(|_| stream.content.clone()));
+            content.push(b'\n');
+        }
+    }
+    content
+}
+
+// Look up a page attribute, following /Parent for inheritable keys
+pub fn inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut current = Some(page_id);
+    while let Some(id) = current {
+        let dict = doc.get_dictionary(id).ok()?;
+        if let Ok(value) = dict.get(key) {
+            return doc.dereference(value).ok().map(|(_, obj)| obj);
+        }
+        current = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    None
+}
+
+// CropBox if present, otherwise MediaBox
+pub fn visible_box(doc: &Document, page_id: ObjectId) -> PageBox {
+    let media_box = page_box(doc, page_id);
+    match inherited(doc, page_id, b"CropBox").and_then(|obj| obj.as_array().ok()) {
+        Some(values) if values.len() >= 4 => {
+            let (x0, y0, x1, y1) = (object_to_f64(&values[0]), object_to_f64(&values[1]), object_to_f64(&values[2]), object_to_f64(&values[3]));
+            PageBox { llx: x0.min(x1), lly: y0.min(y1), urx: x0.max(x1), ury: y0.max(y1) }
+        },
+        _ => media_box,
+    }
+}
+
+#[derive(Clone)]
+struct RenderState {
+    ctm: Transform,
+    fill_space: ColorSpace,
+    stroke_space: ColorSpace,
+    fill: [f64; 3],
+    stroke: [f64; 3],
+    fill_alpha: f64,
+    stroke_alpha: f64,
+    line_width: f64,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    miter_limit: f64,
+    dash: Option<(Vec<f32>, f32)>,
+    clip: Option<Rc<Mask>>,
+    font: Option<Rc<PdfFont>>,
+    font_size: f64,
+    char_spacing: f64,
+    word_spacing: f64,
+    horizontal_scale: f64,
+    leading: f64,
+    rise: f64,
+    render_mode: i64,
+}
+
+impl RenderState {
+    fn new(ctm: Transform) -> Self {
+        RenderState {
+            ctm,
+            fill_space: ColorSpace::DeviceGray,
+            stroke_space: ColorSpace::DeviceGray,
+            fill: [0.0; 3],
+            stroke: [0.0; 3],
+            fill_alpha: 1.0,
+            stroke_alpha: 1.0,
+            line_width: 1.0,
+            line_cap: LineCap::Butt,
+            line_join: LineJoin::Miter,
+            miter_limit: 10.0,
+            dash: None,
+            clip: None,
+            font: None,
+            font_size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scale: 1.0,
+            leading: 0.0,
+            rise: 0.0,
+            render_mode: 0,
+        }
+    }
+}
+
+fn operand_transform(operands: &[Object]) -> Option<Transform> {
+    if operands.len() < 6 {
+        return None;
+    }
+    let v: Vec<f32> = operands.iter().take(6).map(|obj| object_to_f64(obj) as f32).collect();
+    Some(Transform::from_row(v[0], v[1], v[2], v[3], v[4], v[5]))
+}
+
+fn paint_for(color: [f64; 3], alpha: f64) -> Paint<'static> {
+    let mut paint = Paint::default();
+    let to_u8 = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    paint.set_color_rgba8(to_u8(color[0]), to_u8(color[1]), to_u8(color[2]), to_u8(alpha));
+    paint.anti_alias = true;
+    paint
+}
+
+// Cache key of a font: its object, or the address of a direct dictionary
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum FontKey {
+    Object(ObjectId),
+    Direct(usize),
+}
+
+struct Renderer<'a, 'p> {
+    doc: &'a Document,
+    pixmap: &'p mut Pixmap,
+    fallback: Option<&'a [u8]>,
+    fonts: HashMap<FontKey, Rc<PdfFont>>,
+    glyphs: HashMap<(usize, u16), Option<Path>>,
+    path: PathBuilder,
+    pending_clip: Option<FillRule>,
+    text_matrix: Transform,
+    line_matrix: Transform,
+}
+
+impl<'a, 'p> Renderer<'a, 'p> {
+    fn new(doc: &'a Document, pixmap: &'p mut Pixmap, fallback: Option<&'a [u8]>) -> Self {
+        Renderer {
+            doc,
+            pixmap,
+            fallback,
+            fonts: HashMap::new(),
+            glyphs: HashMap::new(),
+            path: PathBuilder::new(),
+            pending_clip: None,
+            text_matrix: Transform::identity(),
+            line_matrix: Transform::identity(),
+        }
+    }
+
+    fn deref(&self, object: &'a Object) -> &'a Object {
+        self.doc.dereference(object).map(|(_, obj)| obj).unwrap_or(object)
+    }
+
+    fn resource(&self, resources: Option<&'a Dictionary>, category: &[u8], name: &[u8]) -> Option<&'a Object> {
+        let category = resources?.get(category).ok().map(|obj| self.deref(obj))?.as_dict().ok()?;
+        category.get(name).ok()
+    }
+
+    fn run(&mut self, operations: &[Operation], resources: Option<&'a Dictionary>, state: &mut RenderState, depth: usize) {
+        let mut stack: Vec<RenderState> = Vec::new();
+
+        for op in operations {
+            let operands = &op.operands;
+            let number = |i: usize| operands.get(i).map(object_to_f64).unwrap_or(0.0);
+
+            match op.operator.as_str() {
+                "q" => stack.push(state.clone()),
+                "Q" => {
+                    if let Some(previous) = stack.pop() {
+                        *state = previous;
+                    }
+                },
+                "cm" => {
+                    if let Some(matrix) = operand_transform(operands) {
+                        state.ctm = state.ctm.pre_concat(matrix);
+                    }
+                },
+                "w" => state.line_width = number(0),
+                "J" => state.line_cap = match number(0) as i64 { 1 => LineCap::Round, 2 => LineCap::Square, _ => LineCap::Butt },
+                "j" => state.line_join = match number(0) as i64 { 1 => LineJoin::Round, 2 => LineJoin::Bevel, _ => LineJoin::Miter },
+                "M" => state.miter_limit = number(0),
+                "d" => {
+                    let array = operands.first().and_then(|obj| obj.as_array().ok());
+                    state.dash = array
+                        .map(|values| values.iter().map(|v| object_to_f64(v) as f32).collect::<Vec<_>>())
+                        .filter(|values| !values.is_empty())
+                        .map(|values| (values, number(1) as f32));
+                },
+                "gs" => {
+                    if let Some(Object::Name(name)) = operands.first() {
+                        if let Some(Object::Dictionary(ext)) = self.resource(resources, b"ExtGState", name).map(|obj| self.deref(obj)) {
+                            self.apply_ext_gstate(ext, state);
+                        }
+                    }
+                },
+
+                // Color
+                "g" | "G" | "rg" | "RG" | "k" | "K" => {
+                    let space = match op.operator.as_str() {
+                        "g" | "G" => ColorSpace::DeviceGray,
+                        "rg" | "RG" => ColorSpace::DeviceRGB,
+                        _ => ColorSpace::DeviceCMYK,
+                    };
+                    let values: Vec<f64> = operands.iter().map(object_to_f64).collect();
+                    let color = space.to_rgb(&values);
+                    if op.operator.chars().all(|c| c.is_ascii_uppercase()) {
+                        state.stroke_space = space;
+                        state.stroke = color;
+                    } else {
+                        state.fill_space = space;
+                        state.fill = color;
+                    }
+                },
+                "cs" | "CS" => {
+                    if let Some(name) = operands.first() {
+                        let space = resolve_color_space(self.doc, name, resources);
+                        let initial = space.to_rgb(&space.initial_values());
+                        if op.operator == "CS" {
+                            state.stroke_space = space;
+                            state.stroke = initial;
+                        } else {
+                            state.fill_space = space;
+                            state.fill = initial;
+                        }
+                    }
+                },
+                "sc" | "scn" | "SC" | "SCN" => {
+                    let values: Vec<f64> = operands.iter().filter(|obj| !matches!(obj, Object::Name(_))).map(object_to_f64).collect();
+                    if op.operator.starts_with('S') {
+                        state.stroke = state.stroke_space.to_rgb(&values);
+                    } else {
+                        state.fill = state.fill_space.to_rgb(&values);
+                    }
+                },
+
+                // Path construction
+                "m" => self.path.move_to(number(0) as f32, number(1) as f32),
+                "l" => self.path.line_to(number(0) as f32, number(1) as f32),
+                "c" => self.path.cubic_to(number(0) as f32, number(1) as f32, number(2) as f32, number(3) as f32, number(4) as f32, number(5) as f32),
+                "v" => {
+                    let current = self.path.last_point().unwrap_or_default();
+                    self.path.cubic_to(current.x, current.y, number(0) as f32, number(1) as f32, number(2) as f32, number(3) as f32);
+                },
+                "y" => self.path.cubic_to(number(0) as f32, number(1) as f32, number(2) as f32, number(3) as f32, number(2) as f32, number(3) as f32),
+                "h" => self.path.close(),
+                "re" => {
+                    let (x, y, w, h) = (number(0) as f32, number(1) as f32, number(2) as f32, number(3) as f32);
+                    self.path.move_to(x, y);
+                    self.path.line_to(x + w, y);
+                    self.path.line_to(x + w, y + h);
+                    self.path.line_to(x, y + h);
+                    self.path.close();
+                },
+
+                // Clipping takes effect after the next painting operator
+                "W" => self.pending_clip = Some(FillRule::Winding),
+                "W*" => self.pending_clip = Some(FillRule::EvenOdd),
+
+                // Path painting
+                "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "n" => {
+                    let operator = op.operator.as_str();
+                    if matches!(operator, "s" | "b" | "b*") {
+                        self.path.close();
+                    }
+                    let path = std::mem::take(&mut self.path).finish();
+                    let rule = if operator.ends_with('*') { FillRule::EvenOdd } else { FillRule::Winding };
+                    if let Some(path) = &path {
+                        if matches!(operator, "f" | "F" | "f*" | "B" | "B*" | "b" | "b*") {
+                            self.fill_path(path, rule, state);
+                        }
+                        if matches!(operator, "S" | "s" | "B" | "B*" | "b" | "b*") {
+                            self.stroke_path(path, state);
+                        }
+                    }
+                    if let Some(clip_rule) = self.pending_clip.take() {
+                        self.intersect_clip(path.as_ref(), clip_rule, state);
+                    }
+                },
+
+                // XObjects and inline images
+                "Do" => {
+                    if let Some(Object::Name(name)) = operands.first() {
+                        if let Some(Object::Stream(xobject)) = self.resource(resources, b"XObject", name).map(|obj| self.deref(obj)) {
+                            self.draw_xobject(xobject, resources, state, depth);
+                        }
+                    }
+                },
+                "BI" => {
+                    if let Some(Object::Stream(image)) = operands.first() {
+                        self.draw_image(image, resources, state);
+                    }
+                },
+
+                // Text
+                "BT" => {
+                    self.text_matrix = Transform::identity();
+                    self.line_matrix = Transform::identity();
+                },
+                "Tf" => {
+                    if let Some(Object::Name(name)) = operands.first() {
+                        state.font = self.load_font(resources, name);
+                    }
+                    state.font_size = number(1);
+                },
+                "Tc" => state.char_spacing = number(0),
+                "Tw" => state.word_spacing = number(0),
+                "Tz" => state.horizontal_scale = number(0) / 100.0,
+                "TL" => state.leading = number(0),
+                "Ts" => state.rise = number(0),
+                "Tr" => state.render_mode = number(0) as i64,
+                "Td" => self.next_line(number(0), number(1)),
+                "TD" => {
+                    state.leading = -number(1);
+                    self.next_line(number(0), number(1));
+                },
+                "Tm" => {
+                    if let Some(matrix) = operand_transform(operands) {
+                        self.text_matrix = matrix;
+                        self.line_matrix = matrix;
+                    }
+                },
+                "T*" => self.next_line(0.0, -state.leading),
+                "Tj" => {
+                    if let Some(Object::String(bytes, _)) = operands.first() {
+                        self.show_text(bytes, state);
+                    }
+                },
+                "'" => {
+                    self.next_line(0.0, -state.leading);
+                    if let Some(Object::String(bytes, _)) = operands.first() {
+                        self.show_text(bytes, state);
+                    }
+                },
+                "\"" => {
+                    state.word_spacing = number(0);
+                    state.char_spacing = number(1);
+                    self.next_line(0.0, -state.leading);
+                    if let Some(Object::String(bytes, _)) = operands.get(2) {
+                        self.show_text(bytes, state);
+                    }
+                },
+                "TJ" => {
+                    if let Some(Object::Array(items)) = operands.first() {
+                        for item in items {
+                            match item {
+                                Object::String(bytes, _) => self.show_text(bytes, state),
+                                Object::Integer(_) | Object::Real(_) => {
+                                    let tx = -object_to_f64(item) / 1000.0 * state.font_size * state.horizontal_scale;
+                                    self.text_matrix = self.text_matrix.pre_translate(tx as f32, 0.0);
+                                },
+                                _ => {},
+                            }
+                        }
+                    }
+                },
+                // Shadings and other operators are not rendered
+                _ => {},
+            }
+        }
+    }
+
+    fn apply_ext_gstate(&mut self, ext: &'a Dictionary, state: &mut RenderState) {
+        for (key, value) in ext.iter() {
+            let value = self.deref(value);
+            match key.as_slice() {
+                b"LW" => state.line_width = object_to_f64(value),
+                b"CA" => state.stroke_alpha = object_to_f64(value),
+                b"ca" => state.fill_alpha = object_to_f64(value),
+                b"Font" => {
+                    if let Ok(items) = value.as_array() {
+                        if let (Some(Object::Reference(id)), Some(size)) = (items.first(), items.get(1)) {
+                            if let Ok(font) = self.doc.get_dictionary(*id) {
+                                state.font = Some(self.font_from_dict(FontKey::Object(*id), font));
+                                state.font_size = object_to_f64(size);
+                            }
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn fill_path(&mut self, path: &Path, rule: FillRule, state: &RenderState) {
+        let paint = paint_for(state.fill, state.fill_alpha);
+        self.pixmap.fill_path(path, &paint, rule, state.ctm, state.clip.as_deref());
+    }
+
+    fn stroke_path(&mut self, path: &Path, state: &RenderState) {
+        let paint = paint_for(state.stroke, state.stroke_alpha);
+        let stroke = Stroke {
+            width: state.line_width.max(0.0) as f32,
+            miter_limit: state.miter_limit as f32,
+            line_cap: state.line_cap,
+            line_join: state.line_join,
+            dash: state.dash.as_ref().and_then(|(array, phase)| StrokeDash::new(array.clone(), *phase)),
+        };
+        self.pixmap.stroke_path(path, &paint, &stroke, state.ctm, state.clip.as_deref());
+    }
+
+    fn intersect_clip(&mut self, path: Option<&Path>, rule: FillRule, state: &mut RenderState) {
+        let mut mask = match &state.clip {
+            Some(mask) => (**mask).clone(),
+            None => {
+                let Some(mut mask) = Mask::new(self.pixmap.width(), self.pixmap.height()) else { return };
+                mask.data_mut().fill(255);
+                mask
+            },
+        };
+        match path {
+            Some(path) => mask.intersect_path(path, rule, true, state.ctm),
+            // An empty clipping path hides everything that follows
+            None => mask.clear(),
+        }
+        state.clip = Some(Rc::new(mask));
+    }
+
+    fn draw_xobject(&mut self, xobject: &'a Stream, resources: Option<&'a Dictionary>, state: &RenderState, depth: usize) {
+        match xobject.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Image") => self.draw_image(xobject, resources, state),
+            Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                let mut form_state = state.clone();
+                self.run_form(xobject, resources, &mut form_state, depth);
+            },
+            _ => {},
+        }
+    }
+
+    fn run_form(&mut self, form: &'a Stream, parent_resources: Option<&'a Dictionary>, state: &mut RenderState, depth: usize) {
+        if let Some(matrix) = form.dict.get(b"Matrix").ok().and_then(|obj| obj.as_array().ok()).and_then(|values| operand_transform(values)) {
+            state.ctm = state.ctm.pre_concat(matrix);
+        }
+        if let Some(bbox) = form.dict.get(b"BBox").ok().and_then(|obj| obj.as_array().ok()) {
+            if bbox.len() >= 4 {
+                let (x0, y0, x1, y1) = (object_to_f64(&bbox[0]) as f32, object_to_f64(&bbox[1]) as f32, object_to_f64(&bbox[2]) as f32, object_to_f64(&bbox[3]) as f32);
+                let rect = tiny_skia::Rect::from_ltrb(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)).map(PathBuilder::from_rect);
+                self.intersect_clip(rect.as_ref(), FillRule::Winding, state);
+            }
+        }
+
+        let resources = form
+            .dict
+            .get(b"Resources")
+            .ok()
+            .map(|obj| self.deref(obj))
+            .and_then(|obj| obj.as_dict().ok())
+            .or(parent_resources);
+        let Ok(data) = form.decompressed_content().or_else(|_| Ok::<_, lopdf::Error>(form.content.clone())) else { return };
+        if let Ok(content) = decode_content(&data) {
+            // Text and path state are local to the form
+            let saved = (std::mem::take(&mut self.path), self.text_matrix, self.line_matrix);
+            self.run(&content.operations, resources, state, depth + 1);
+            (self.path, self.text_matrix, self.line_matrix) = saved;
+        }
+    }
+
+    fn draw_image(&mut self, image: &Stream, resources: Option<&'a Dictionary>, state: &RenderState) {
+        let Ok(decoded) = decode_image(self.doc, image, resources, state.fill) else { return };
+        if decoded.width == 0 || decoded.height == 0 {
+            return;
+        }
+
+        // tiny-skia expects premultiplied RGBA
+        let mut data = decoded.rgba;
+        for pixel in data.chunks_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as u32 * alpha / 255) as u8;
+            }
+        }
+        let Some(size) = tiny_skia::IntSize::from_wh(decoded.width, decoded.height) else { return };
+        let Some(image_pixmap) = Pixmap::from_vec(data, size) else { return };
+
+        // Images occupy the unit square, with the first row at the top
+        let (w, h) = (decoded.width as f32, decoded.height as f32);
+        let transform = state.ctm.pre_concat(Transform::from_row(1.0 / w, 0.0, 0.0, -1.0 / h, 0.0, 1.0));
+        let paint = PixmapPaint {
+            opacity: state.fill_alpha as f32,
+            quality: tiny_skia::FilterQuality::Bilinear,
+            ..PixmapPaint::default()
+        };
+        self.pixmap.draw_pixmap(0, 0, image_pixmap.as_ref(), &paint, transform, state.clip.as_deref());
+    }
+
+    fn load_font(&mut self, resources: Option<&'a Dictionary>, name: &[u8]) -> Option<Rc<PdfFont>> {
+        let entry = self.resource(resources, b"Font", name)?;
+        let key = match entry {
+            Object::Reference(id) => FontKey::Object(*id),
+            other => FontKey::Direct(other as *const Object as usize),
+        };
+        let dict = self.deref(entry).as_dict().ok()?;
+        Some(self.font_from_dict(key, dict))
+    }
+
+    fn font_from_dict(&mut self, key: FontKey, dict: &Dictionary) -> Rc<PdfFont> {
+        if let Some(font) = self.fonts.get(&key) {
+            return font.clone();
+        }
+        let font = Rc::new(PdfFont::load(self.doc, dict, self.fallback));
+        self.fonts.insert(key, font.clone());
+        font
+    }
+
+    fn next_line(&mut self, tx: f64, ty: f64) {
+        self.line_matrix = self.line_matrix.pre_translate(tx as f32, ty as f32);
+        self.text_matrix = self.line_matrix;
+    }
+
+    fn show_text(&mut self, bytes: &[u8], state: &RenderState) {
+        let Some(font) = state.font.clone() else { return };
+        let font_key = Rc::as_ptr(&font) as usize;
+        let is_type3 = font.subtype == "Type3";
+        let size = state.font_size as f32;
+        let scale = state.horizontal_scale as f32;
+
+        for code in font.codes(bytes) {
+            let width = font.advance(code);
+            let visible = !matches!(state.render_mode, 3 | 7);
+
+            if visible && !is_type3 {
+                if let Some(gid) = font.glyph_id(code) {
+                    let path = self.glyphs.entry((font_key, gid)).or_insert_with(|| {
+                        let mut builder = GlyphPathBuilder(PathBuilder::new());
+                        if font.outline(gid, &mut builder) { builder.0.finish() } else { None }
+                    }).clone();
+                    if let Some(path) = path {
+                        let glyph_transform = state
+                            .ctm
+                            .pre_concat(self.text_matrix)
+                            .pre_concat(Transform::from_row(size * scale, 0.0, 0.0, size, 0.0, state.rise as f32));
+                        let glyph_state = RenderState { ctm: glyph_transform, ..state.clone() };
+                        if matches!(state.render_mode, 0 | 2 | 4 | 6) {
+                            self.fill_path(&path, FillRule::Winding, &glyph_state);
+                        }
+                        if matches!(state.render_mode, 1 | 2 | 5 | 6) {
+                            // Line width is in user space, so undo the glyph scaling
+                            let stroke_state = RenderState { line_width: state.line_width / state.font_size.abs().max(1e-6), ..glyph_state };
+                            self.stroke_path(&path, &stroke_state);
+                        }
+                    }
+                }
+            }
+
+            let word_spacing = if !font.two_byte && code == 32 { state.word_spacing } else { 0.0 };
+            let tx = (width * state.font_size + state.char_spacing + word_spacing) * state.horizontal_scale;
+            self.text_matrix = self.text_matrix.pre_translate(tx as f32, 0.0);
+        }
+    }
+
+    // Draw the normal appearance stream of each visible annotation
+    fn draw_annotations(&mut self, page_id: ObjectId, base: Transform) {
+        let Ok(annotations) = self.doc.get_page_annotations(page_id) else { return };
+        for annotation in annotations {
+            let flags = annotation.get(b"F").ok().map(object_to_f64).unwrap_or(0.0) as i64;
+            if flags & (HIDDEN_ANNOTATION | NO_VIEW_ANNOTATION) != 0 {
+                continue;
+            }
+            let Some(rect) = annotation.get(b"Rect").ok().map(|obj| self.deref(obj)).and_then(|obj| obj.as_array().ok()) else { continue };
+            if rect.len() < 4 {
+                continue;
+            }
+            let Some(normal) = annotation
+                .get(b"AP")
+                .ok()
+                .map(|obj| self.deref(obj))
+                .and_then(|obj| obj.as_dict().ok())
+                .and_then(|ap| ap.get(b"N").ok())
+                .map(|obj| self.deref(obj))
+            else {
+                continue;
+            };
+            let appearance = match normal {
+                Object::Stream(stream) => Some(stream),
+                Object::Dictionary(states) => annotation
+                    .get(b"AS")
+                    .ok()
+                    .and_then(|obj| obj.as_name().ok())
+                    .and_then(|state| states.get(state).ok())
+                    .map(|obj| self.deref(obj))
+                    .and_then(|obj| obj.as_stream().ok()),
+                _ => None,
+            };
+            let Some(appearance) = appearance else { continue };
+
+            // Map the transformed form BBox onto the annotation rectangle
+            let bbox = appearance.dict.get(b"BBox").ok().and_then(|obj| obj.as_array().ok());
+            let matrix = appearance
+                .dict
+                .get(b"Matrix")
+                .ok()
+                .and_then(|obj| obj.as_array().ok())
+                .and_then(|values| operand_transform(values))
+                .unwrap_or_default();
+            let Some(bbox) = bbox.filter(|b| b.len() >= 4) else { continue };
+            let corners = [
+                (object_to_f64(&bbox[0]), object_to_f64(&bbox[1])),
+                (object_to_f64(&bbox[2]), object_to_f64(&bbox[1])),
+                (object_to_f64(&bbox[2]), object_to_f64(&bbox[3])),
+                (object_to_f64(&bbox[0]), object_to_f64(&bbox[3])),
+            ];
+            let mapped: Vec<(f64, f64)> = corners
+                .iter()
+                .map(|(x, y)| {
+                    let mut point = tiny_skia::Point::from_xy(*x as f32, *y as f32);
+                    matrix.map_point(&mut point);
+                    (point.x as f64, point.y as f64)
+                })
+                .collect();
+            let (min_x, max_x) = mapped.iter().fold((f64::MAX, f64::MIN), |(lo, hi), (x, _)| (lo.min(*x), hi.max(*x)));
+            let (min_y, max_y) = mapped.iter().fold((f64::MAX, f64::MIN), |(lo, hi), (_, y)| (lo.min(*y), hi.max(*y)));
+            let (rx0, ry0, rx1, ry1) = (object_to_f64(&rect[0]), object_to_f64(&rect[1]), object_to_f64(&rect[2]), object_to_f64(&rect[3]));
+            let (bw, bh) = (max_x - min_x, max_y - min_y);
+            if bw.abs() < 1e-6 || bh.abs() < 1e-6 {
+                continue;
+            }
+            let sx = (rx1 - rx0).abs() / bw;
+            let sy = (ry1 - ry0).abs() / bh;
+            let fit = Transform::from_row(sx as f32, 0.0, 0.0, sy as f32, (rx0.min(rx1) - min_x * sx) as f32, (ry0.min(ry1) - min_y * sy) as f32);
+
+            let mut state = RenderState::new(base.pre_concat(fit));
+            self.run_form(appearance, None, &mut state, 0);
+        }
+    }
+}
+
+// Collects glyph outlines into a tiny-skia path
+struct GlyphPathBuilder(PathBuilder);
+
+impl ttf_parser::OutlineBuilder for GlyphPathBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    // One page with the given content and annotations, and its id
+    fn page(media_box: [i64; 4], content: &str, annotations: Vec<Dictionary>) -> (Document, ObjectId) {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content.as_bytes().to_vec()));
+        let annotations: Vec<Object> = annotations.into_iter().map(|annotation| doc.add_object(annotation).into()).collect();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => media_box.iter().map(|v| Object::Integer(*v)).collect::<Vec<_>>(),
+            "Contents" => content_id,
+            "Annots" => annotations,
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        (doc, page_id)
+    }
+
+    fn pixel(pixmap: &Pixmap, x: u32, y: u32) -> (u8, u8, u8) {
+        let color = pixmap.pixel(x, y).unwrap();
+        (color.red(), color.green(), color.blue())
+    }
+
+    #[test]
+    fn paths_are_drawn_in_device_space() {
+        let (doc, page_id) = page([0, 0, 100, 100], "1 0 0 rg 0 0 50 50 re f", Vec::new());
+        let (pixmap, _) = render_page(&doc, page_id, 144.0, None).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (200, 200));
+        // PDF space starts at the bottom left, pixels at the top left
+        assert_eq!(pixel(&pixmap, 10, 190), (255, 0, 0));
+        assert_eq!(pixel(&pixmap, 10, 10), (255, 255, 255));
+    }
+
+    #[test]
+    fn dpi_is_validated() {
+        let (doc, page_id) = page([0, 0, 100, 100], "", Vec::new());
+        for dpi in [0.0, -72.0, f64::NAN, f64::INFINITY, MAX_DPI + 1.0] {
+            assert_eq!(render_page(&doc, page_id, dpi, None).unwrap_err().reason(), "invalid_argument");
+        }
+    }
+
+    #[test]
+    fn huge_pages_exceed_the_budget() {
+        let (doc, page_id) = page([0, 0, 14400, 14400], "", Vec::new());
+        assert_eq!(render_page(&doc, page_id, 72.0, None).unwrap_err().reason(), "budget_exceeded");
+    }
+
+    #[test]
+    fn hidden_and_no_view_annotations_are_skipped() {
+        let annotation = |flags: i64, x: i64| {
+            let appearance = Stream::new(dictionary! { "BBox" => vec![0.into(), 0.into(), 10.into(), 10.into()] }, b"0 0 1 rg 0 0 10 10 re f".to_vec());
+            dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Square",
+                "Rect" => vec![x.into(), 0.into(), (x + 10).into(), 10.into()],
+                "F" => flags,
+                "AP" => dictionary! { "N" => appearance },
+            }
+        };
+        let (doc, page_id) = page([0, 0, 100, 100], "", vec![annotation(0, 0), annotation(2, 20), annotation(32, 40)]);
+        let (pixmap, _) = render_page(&doc, page_id, 72.0, None).unwrap();
+        assert_eq!(pixel(&pixmap, 5, 95), (0, 0, 255));
+        assert_eq!(pixel(&pixmap, 25, 95), (255, 255, 255));
+        assert_eq!(pixel(&pixmap, 45, 95), (255, 255, 255));
+    }
+}
@@ -0,0 +1,236 @@
+use lopdf::encryption::crypt_filters::{Aes256CryptFilter, CryptFilter};
+use lopdf::{Document, EncryptionState, EncryptionVersion, Object, Permissions, StringFormat};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::common::load_document;
+use crate::error::PdfError;
+
+// Options for encrypting a PDF, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EncryptOptions {
+    // Password needed to open the document; empty opens it without one
+    pub user_password: String,
+    // Password that lifts the permission restrictions (required)
+    pub owner_password: String,
+    // Granted permissions out of "print", "copy", "modify", "annotate" and
+    // "fill_forms"; all of them when absent
+    pub permissions: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EncryptResult {
+    pub total_pages: u32,
+    // Settings read back from the output file
+    pub encryption: EncryptionInfo,
+}
+
+// Standard security handler settings of a document that was decrypted on load
+#[derive(Serialize, Debug, Clone)]
+pub struct EncryptionInfo {
+    // "RC4", "AES-128", "AES-256" or "None" for the Identity crypt filter
+    pub method: String,
+    pub key_length: usize,
+    pub version: i64,
+    pub revision: i64,
+    pub permissions: PermissionFlags,
+}
+
+// User access permissions from the /P entry. Owners are not bound by them.
+#[derive(Serialize, Debug, Clone)]
+pub struct PermissionFlags {
+    pub print: bool,
+    pub print_high_quality: bool,
+    pub modify: bool,
+    pub copy: bool,
+    pub copy_for_accessibility: bool,
+    pub annotate: bool,
+    pub fill_forms: bool,
+    pub assemble: bool,
+}
+
+impl From<Permissions> for PermissionFlags {
+    fn from(permissions: Permissions) -> Self {
+        PermissionFlags {
+            print: permissions.contains(Permissions::PRINTABLE),
+            print_high_quality: permissions.contains(Permissions::PRINTABLE_IN_HIGH_QUALITY),
+            modify: permissions.contains(Permissions::MODIFIABLE),
+            copy: permissions.contains(Permissions::COPYABLE),
+            copy_for_accessibility: permissions.contains(Permissions::COPYABLE_FOR_ACCESSIBILITY),
+            annotate: permissions.contains(Permissions::ANNOTABLE),
+            fill_forms: permissions.contains(Permissions::FILLABLE),
+            assemble: permissions.contains(Permissions::ASSEMBLABLE),
+        }
+    }
+}
+
+// Describe the encryption of a loaded document; None if it was not encrypted
+pub fn encryption_info(doc: &Document) -> Option<EncryptionInfo> {
+    let state = doc.encryption_state.as_ref()?;
+    let (method, key_length) = cipher(state);
+
+    Some(EncryptionInfo {
+        method: method.to_string(),
+        key_length,
+        version: state.version(),
+        revision: state.revision(),
+        permissions: state.permissions().into(),
+    })
+}
+
+// Cipher and key length in bits. Version 4 and 5 handlers name the cipher in
+// their default stream crypt filter; older ones always use RC4.
+fn cipher(state: &EncryptionState) -> (&'static str, usize) {
+    let rc4_length = state.key_length().unwrap_or(40);
+    if state.version() < 4 {
+        return ("RC4", rc4_length);
+    }
+
+    match state.crypt_filters().get(state.default_stream_filter()).map(|filter| filter.method()) {
+        Some(b"AESV3") => ("AES-256", 256),
+        Some(b"AESV2") => ("AES-128", 128),
+        Some(b"V2") => ("RC4", rc4_length),
+        _ if state.version() == 5 => ("AES-256", 256),
+        _ => ("None", 0),
+    }
+}
+
+// Encrypt a PDF with AES-256 (standard security handler revision 6). The output
+// is written next to its final path and opened again with the owner password;
+// it is only moved into place once it decrypts.
+pub fn encrypt_pdf(input_path: &str, output_path: &str, options: &EncryptOptions) -> Result<EncryptResult, PdfError> {
+    if options.owner_password.is_empty() {
+        return Err(PdfError::InvalidArgument("owner_password is required for encryption".to_string()));
+    }
+    let names = options.permissions.clone().unwrap_or_else(|| PERMISSION_NAMES.map(String::from).to_vec());
+    // Assistive technology may always extract content (PDF 2.0)
+    let permissions = names
+        .iter()
+        .try_fold(Permissions::COPYABLE_FOR_ACCESSIBILITY, |granted, name| Ok::<_, PdfError>(granted | permission(name)?))?;
+
+    let mut doc = load_document(input_path, None)?;
+    let total_pages = doc.get_pages().len() as u32;
+
+    let mut rng = rand::rng();
+    let mut file_encryption_key = [0u8; 32];
+    rng.fill(&mut file_encryption_key);
+
+    // Encrypted documents must have a file identifier
+    if !doc.trailer.has(b"ID") {
+        let mut id = [0u8; 16];
+        rng.fill(&mut id);
+        let id = Object::String(id.to_vec(), StringFormat::Hexadecimal);
+        doc.trailer.set("ID", Object::Array(vec![id.clone(), id]));
+    }
+
+    let crypt_filter: Arc<dyn CryptFilter> = Arc::new(Aes256CryptFilter);
+    let version = EncryptionVersion::V5 {
+        encrypt_metadata: true,
+        crypt_filters: BTreeMap::from([(b"StdCF".to_vec(), crypt_filter)]),
+        file_encryption_key: &file_encryption_key,
+        stream_filter: b"StdCF".to_vec(),
+        string_filter: b"StdCF".to_vec(),
+        owner_password: &options.owner_password,
+        user_password: &options.user_password,
+        permissions,
+    };
+    let state = EncryptionState::try_from(version).map_err(|e| PdfError::InvalidArgument(format!("Invalid password: {}", e)))?;
+    doc.encrypt(&state).map_err(|e| PdfError::UnsupportedFeature(format!("Failed to encrypt PDF: {}", e)))?;
+
+    let partial_path = format!("{}.partial", output_path);
+    let verified = save_verified(&mut doc, &partial_path, &options.owner_password).and_then(|encryption| {
+        std::fs::rename(&partial_path, output_path).map_err(|e| PdfError::Io(format!("Failed to move {} to {}: {}", partial_path, output_path, e)))?;
+        Ok(encryption)
+    });
+    if verified.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    Ok(EncryptResult { total_pages, encryption: verified? })
+}
+
+fn save_verified(doc: &mut Document, path: &str, owner_password: &str) -> Result<EncryptionInfo, PdfError> {
+    doc.save(path).map_err(|e| PdfError::Io(format!("Failed to save PDF {}: {}", path, e)))?;
+    encryption_info(&load_document(path, Some(owner_password))?).ok_or_else(|| PdfError::Io(format!("{} was saved without encryption", path)))
+}
+
+const PERMISSION_NAMES: [&str; 5] = ["print", "copy", "modify", "annotate", "fill_forms"];
+
+fn permission(name: &str) -> Result<Permissions, PdfError> {
+    match name {
+        "print" => Ok(Permissions::PRINTABLE | Permissions::PRINTABLE_IN_HIGH_QUALITY),
+        "copy" => Ok(Permissions::COPYABLE),
+        "modify" => Ok(Permissions::MODIFIABLE | Permissions::ASSEMBLABLE),
+        "annotate" => Ok(Permissions::ANNOTABLE),
+        "fill_forms" => Ok(Permissions::FILLABLE),
+        other => Err(PdfError::InvalidArgument(format!("Unknown permission: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+    use std::path::PathBuf;
+
+    fn input(name: &str) -> PathBuf {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let content_id = doc.add_object(Stream::new(dictionary! {}, b"BT /F1 12 Tf (Confidential) Tj ET".to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => content_id,
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let path = std::env::temp_dir().join(format!("security_test_{}.pdf", name));
+        doc.save(&path).unwrap();
+        path
+    }
+
+    fn options(permissions: &[&str]) -> EncryptOptions {
+        EncryptOptions {
+            user_password: "reader".to_string(),
+            owner_password: "owner".to_string(),
+            permissions: Some(permissions.iter().map(|name| name.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn encrypted_output_opens_with_either_password() {
+        let (input, output) = (input("round_trip"), std::env::temp_dir().join("security_test_round_trip_out.pdf"));
+        let result = encrypt_pdf(input.to_str().unwrap(), output.to_str().unwrap(), &options(&["print"])).unwrap();
+        assert_eq!((result.encryption.method.as_str(), result.encryption.revision), ("AES-256", 6));
+
+        let output = output.to_str().unwrap();
+        assert_eq!(load_document(output, None).unwrap_err().reason(), "encrypted");
+        assert_eq!(load_document(output, Some("wrong")).unwrap_err().reason(), "encrypted");
+        let doc = load_document(output, Some("reader")).unwrap();
+        let permissions = encryption_info(&doc).unwrap().permissions;
+        assert!(permissions.print && permissions.copy_for_accessibility && !permissions.copy && !permissions.modify);
+
+        // Content is readable again after decryption
+        let page_id = doc.get_pages()[&1];
+        assert!(String::from_utf8_lossy(&doc.get_page_content(page_id).unwrap()).contains("Confidential"));
+        assert!(!std::path::Path::new(&format!("{}.partial", output)).exists());
+    }
+
+    #[test]
+    fn failures_leave_no_output() {
+        let input = input("failures");
+        let output = std::env::temp_dir().join("security_test_failures_out.pdf");
+        let _ = std::fs::remove_file(&output);
+
+        let error = encrypt_pdf(input.to_str().unwrap(), output.to_str().unwrap(), &options(&["print", "share"])).unwrap_err();
+        assert_eq!(error.reason(), "invalid_argument");
+        let missing_dir = std::env::temp_dir().join("security_test_missing/out.pdf");
+        let error = encrypt_pdf(input.to_str().unwrap(), missing_dir.to_str().unwrap(), &options(&[])).unwrap_err();
+        assert_eq!(error.reason(), "io_error");
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,339 @@
+use lopdf::{dictionary, Document, Object, ObjectId, Stream, content::Operation};
+use serde::{Deserialize, Serialize};
+use crate::common::load_document;
+use crate::error::PdfError;
+use crate::overlay::{
+    add_opacity_state, add_page_resource, append_page_overlay, compressed_stream, encode_win_ansi,
+    page_box, text_operations, unique_resource_name, EmbeddedFont, PageBox,
+};
+
+// Options for drawing a watermark or stamp, decoded from the JSON passed by Elixir
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct StampOptions {
+    pub text: Option<String>,
+    pub image_path: Option<String>,
+    pub font_path: Option<String>,
+    pub font_size: f64,
+    pub color: [f64; 3],
+    pub opacity: f64,
+    pub rotation: Option<f64>,
+    pub position: String,
+    pub margin: f64,
+    pub pages: Option<Vec<u32>>,
+    pub image_width: Option<f64>,
+}
+
+impl Default for StampOptions {
+    fn default() -> Self {
+        StampOptions {
+            text: None,
+            image_path: None,
+            font_path: None,
+            font_size: 48.0,
+            color: [0.5, 0.5, 0.5],
+            opacity: 0.3,
+            rotation: None,
+            position: "center".to_string(),
+            margin: 36.0,
+            pages: None,
+            image_width: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct StampResult {
+    pub total_pages: u32,
+    pub stamped_pages: Vec<u32>,
+}
+
+struct StampImage {
+    id: ObjectId,
+    pixel_width: f64,
+    pixel_height: f64,
+}
+
+pub fn stamp_pdf(input_path: &str, output_path: &str, options: &StampOptions) -> Result<StampResult, PdfError> {
+    // Check the arguments before spending time on the document
+    if options.text.is_none() && options.image_path.is_none() {
+        return Err(PdfError::InvalidArgument("Either text or image_path must be given".to_string()));
+    }
+    let font_path = match (&options.image_path, &options.text) {
+        (None, Some(_)) => Some(options.font_path.as_deref().ok_or_else(|| PdfError::InvalidArgument("font_path is required for text stamps".to_string()))?),
+        _ => None,
+    };
+
+    let mut doc = load_document(input_path, None)?;
+    let pages = doc.get_pages();
+    let total_pages = pages.len() as u32;
+
+    let image = match &options.image_path {
+        Some(path) => Some(load_image(&mut doc, path)?),
+        None => None,
+    };
+    let font = match font_path {
+        Some(font_path) => Some(EmbeddedFont::load(&mut doc, font_path)?),
+        None => None,
+    };
+    let gs_id = add_opacity_state(&mut doc, options.opacity);
+
+    let mut stamped_pages = Vec::new();
+    for (page_num, page_id) in pages {
+        if let Some(selected) = &options.pages {
+            if !selected.contains(&page_num) {
+                continue;
+            }
+        }
+
+        let bounds = page_box(&doc, page_id);
+        let gs_name = unique_resource_name(&doc, page_id, b"ExtGState", "WraftGS");
+        add_page_resource(&mut doc, page_id, b"ExtGState", &gs_name, gs_id)?;
+
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("gs", vec![Object::Name(gs_name)]),
+        ];
+
+        if let Some(image) = &image {
+            let width = options.image_width.unwrap_or(image.pixel_width);
+            let height = width * image.pixel_height / image.pixel_width;
+            let (cx, cy) = anchor(&bounds, &options.position, options.margin, width, height);
+            let angle = rotation_radians(options, &bounds);
+            let (cos, sin) = (angle.cos(), angle.sin());
+            let e = cx - cos * width / 2.0 + sin * height / 2.0;
+            let f = cy - sin * width / 2.0 - cos * height / 2.0;
+
+            let name = unique_resource_name(&doc, page_id, b"XObject", "WraftIm");
+            add_page_resource(&mut doc, page_id, b"XObject", &name, image.id)?;
+            operations.push(Operation::new("cm", [width * cos, width * sin, -height * sin, height * cos, e, f]
+                .iter().map(|v| Object::Real(*v as f32)).collect()));
+            operations.push(Operation::new("Do", vec![Object::Name(name)]));
+        } else if let (Some(font), Some(template)) = (&font, &options.text) {
+            let text = template
+                .replace("{n}", &page_num.to_string())
+                .replace("{total}", &total_pages.to_string());
+            let encoded = encode_win_ansi(&text);
+            let width = font.text_width(&encoded, options.font_size);
+            let ascent = font.ascent(options.font_size);
+            let descent = font.descent(options.font_size);
+            let height = ascent - descent;
+            let (cx, cy) = anchor(&bounds, &options.position, options.margin, width, height);
+            let angle = rotation_radians(options, &bounds);
+            let (cos, sin) = (angle.cos(), angle.sin());
+            // Text origin sits on the baseline, `-descent` above the bottom of the box
+            let (lx, ly) = (-width / 2.0, -descent - height / 2.0);
+            let e = cx + cos * lx - sin * ly;
+            let f = cy + sin * lx + cos * ly;
+
+            let name = unique_resource_name(&doc, page_id, b"Font", "WraftF");
+            add_page_resource(&mut doc, page_id, b"Font", &name, font.font_id)?;
+            operations.extend(text_operations(&name, options.font_size, options.color, [cos, sin, -sin, cos, e, f], encoded));
+        }
+
+        operations.push(Operation::new("Q", vec![]));
+        append_page_overlay(&mut doc, page_id, operations)?;
+        stamped_pages.push(page_num);
+    }
+
+    doc.save(output_path).map_err(|e| PdfError::Io(format!("Failed to save PDF {}: {}", output_path, e)))?;
+
+    Ok(StampResult { total_pages, stamped_pages })
+}
+
+// Centre point of the stamp box for a position preset
+pub fn anchor(bounds: &PageBox, position: &str, margin: f64, width: f64, height: f64) -> (f64, f64) {
+    let left = bounds.llx + margin + width / 2.0;
+    let right = bounds.urx - margin - width / 2.0;
+    let top = bounds.ury - margin - height / 2.0;
+    let bottom = bounds.lly + margin + height / 2.0;
+    let center_x = bounds.llx + bounds.width() / 2.0;
+    let center_y = bounds.lly + bounds.height() / 2.0;
+
+    match position {
+        "top_left" => (left, top),
+        "top_center" => (center_x, top),
+        "top_right" => (right, top),
+        "bottom_left" => (left, bottom),
+        "bottom_center" => (center_x, bottom),
+        "bottom_right" => (right, bottom),
+        _ => (center_x, center_y),
+    }
+}
+
+// The "diagonal" preset follows the page diagonal unless an explicit rotation is given
+fn rotation_radians(options: &StampOptions, bounds: &PageBox) -> f64 {
+    match options.rotation {
+        Some(degrees) => degrees.to_radians(),
+        None if options.position == "diagonal" => bounds.height().atan2(bounds.width()),
+        None => 0.0,
+    }
+}
+
+// Load a PNG or JPEG image as an image XObject. Images without pixels are
+// rejected since their aspect ratio is undefined.
+fn load_image(doc: &mut Document, path: &str) -> Result<StampImage, PdfError> {
+    let image = load_image_data(doc, path)?;
+    if image.pixel_width == 0.0 || image.pixel_height == 0.0 {
+        return Err(PdfError::InvalidArgument(format!("Image has no pixels: {}", path)));
+    }
+    Ok(image)
+}
+
+fn load_image_data(doc: &mut Document, path: &str) -> Result<StampImage, PdfError> {
+    let data = std::fs::read(path).map_err(|e| PdfError::read_failed(path, e))?;
+
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let (width, height, components) = jpeg_dimensions(&data).ok_or_else(|| PdfError::UnsupportedFeature(format!("Unsupported JPEG image: {}", path)))?;
+        let color_space = match components {
+            1 => "DeviceGray",
+            4 => "DeviceCMYK",
+            _ => "DeviceRGB",
+        };
+        let stream = Stream::new(dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => width as i64,
+            "Height" => height as i64,
+            "ColorSpace" => color_space,
+            "BitsPerComponent" => 8,
+            "Filter" => "DCTDecode",
+        }, data).with_compression(false);
+        let id = doc.add_object(stream);
+        return Ok(StampImage { id, pixel_width: width as f64, pixel_height: height as f64 });
+    }
+
+    if data.starts_with(b"\x89PNG") {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(&data));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| PdfError::InvalidArgument(format!("Failed to decode PNG {}: {}", path, e)))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer).map_err(|e| PdfError::InvalidArgument(format!("Failed to decode PNG {}: {}", path, e)))?;
+        let pixels = &buffer[..frame.buffer_size()];
+
+        let (channels, has_alpha) = match frame.color_type {
+            png::ColorType::Grayscale => (1, false),
+            png::ColorType::GrayscaleAlpha => (1, true),
+            png::ColorType::Rgba => (3, true),
+            _ => (3, false),
+        };
+        let stride = channels + has_alpha as usize;
+        let mut color = Vec::with_capacity(pixels.len());
+        let mut alpha = Vec::new();
+        for pixel in pixels.chunks(stride) {
+            color.extend_from_slice(&pixel[..channels]);
+            if has_alpha {
+                alpha.push(pixel[channels]);
+            }
+        }
+
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => frame.width as i64,
+            "Height" => frame.height as i64,
+            "ColorSpace" => if channels == 1 { "DeviceGray" } else { "DeviceRGB" },
+            "BitsPerComponent" => 8,
+        };
+        if has_alpha {
+            let mask_id = doc.add_object(compressed_stream(dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => frame.width as i64,
+                "Height" => frame.height as i64,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            }, &alpha));
+            dict.set("SMask", mask_id);
+        }
+        let id = doc.add_object(compressed_stream(dict, &color));
+        return Ok(StampImage { id, pixel_width: frame.width as f64, pixel_height: frame.height as f64 });
+    }
+
+    Err(PdfError::UnsupportedFeature(format!("Unsupported image format (expected PNG or JPEG): {}", path)))
+}
+
+// Read width, height and component count from the first SOF marker of a JPEG
+fn jpeg_dimensions(data: &[u8]) -> Option<(u16, u16, u8)> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let is_sof = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_sof && pos + 9 < data.len() {
+            let height = u16::from_be_bytes([data[pos + 5], data[pos + 6]]);
+            let width = u16::from_be_bytes([data[pos + 7], data[pos + 8]]);
+            return Some((width, height, data[pos + 9]));
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_page_pdf(path: &std::path::Path) {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..2).map(|_| {
+            let content_id = doc.add_object(Stream::new(dictionary! {}, b"0 0 1 rg 10 10 50 50 re f".to_vec()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Contents" => content_id,
+            }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 2 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(path).unwrap();
+    }
+
+    fn png(path: &std::path::Path, width: u32, height: u32) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&vec![200; (width * height * 4) as usize]).unwrap();
+    }
+
+    #[test]
+    fn image_stamp_lands_on_selected_pages() {
+        let dir = std::env::temp_dir();
+        let (input, output, image) = (dir.join("stamp_test_in.pdf"), dir.join("stamp_test_out.pdf"), dir.join("stamp_test.png"));
+        two_page_pdf(&input);
+        png(&image, 4, 2);
+
+        let options = StampOptions { image_path: Some(image.to_str().unwrap().to_string()), image_width: Some(100.0), pages: Some(vec![2]), ..Default::default() };
+        let result = stamp_pdf(input.to_str().unwrap(), output.to_str().unwrap(), &options).unwrap();
+        assert_eq!(result.total_pages, 2);
+        assert_eq!(result.stamped_pages, vec![2]);
+
+        let stamped = Document::load(&output).unwrap();
+        let pages = stamped.get_pages();
+        let content = |page| String::from_utf8_lossy(&stamped.get_page_content(pages[&page]).unwrap()).to_string();
+        assert!(!content(1).contains("Do"));
+        // The page's own content is kept and wrapped, the stamp is centred over it
+        let stamped_content = content(2);
+        assert!(stamped_content.starts_with("q\n") && stamped_content.contains(" re f"));
+        assert!(stamped_content.contains("256 371 cm\n/WraftIm0 Do"));
+    }
+
+    #[test]
+    fn arguments_are_checked_before_loading() {
+        let missing = std::env::temp_dir().join("stamp_test_missing.pdf");
+        let error = stamp_pdf(missing.to_str().unwrap(), "unused.pdf", &StampOptions::default()).unwrap_err();
+        assert_eq!(error.reason(), "invalid_argument");
+
+        let options = StampOptions { text: Some("DRAFT".to_string()), ..Default::default() };
+        let error = stamp_pdf(missing.to_str().unwrap(), "unused.pdf", &options).unwrap_err();
+        assert_eq!(error.reason(), "invalid_argument");
+    }
+}
@@ -0,0 +1,289 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, content::Operation};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use crate::common::{concat_matrix, object_to_f64, operands_to_matrix, transform_bounds, Matrix, IDENTITY_MATRIX};
+use crate::fonts::PdfFont;
+use crate::pdf_image::decode_content;
+use crate::render::{inherited, page_content};
+
+// Text state parameters; saved and restored with the graphics state by q/Q
+#[derive(Clone)]
+pub struct TextState {
+    pub font: Option<Rc<PdfFont>>,
+    pub font_name: Option<Vec<u8>>,
+    pub font_size: f64,
+    pub char_spacing: f64,
+    pub word_spacing: f64,
+    pub horizontal_scale: f64,
+    pub leading: f64,
+    pub rise: f64,
+    pub render_mode: i64,
+}
+
+impl Default for TextState {
+    fn default() -> Self {
+        TextState {
+            font: None,
+            font_name: None,
+            font_size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scale: 1.0,
+            leading: 0.0,
+            rise: 0.0,
+            render_mode: 0,
+        }
+    }
+}
+
+impl TextState {
+    // Render modes 3 and 7 neither fill nor stroke the glyphs
+    pub fn is_visible(&self) -> bool {
+        !matches!(self.render_mode, 3 | 7)
+    }
+
+    pub fn fills(&self) -> bool {
+        matches!(self.render_mode, 0 | 2 | 4 | 6)
+    }
+
+    pub fn strokes(&self) -> bool {
+        matches!(self.render_mode, 1 | 2 | 5 | 6)
+    }
+}
+
+// A glyph placed by a text-showing operator
+pub struct PlacedGlyph {
+    // Box from descent to ascent and across the advance, in default user space
+    pub bounds: (f64, f64, f64, f64),
+    // Text of the glyph, empty if the font does not map the code
+    pub text: String,
+    // Where the code is in the operands: the TJ array item (0 for the other
+    // operators) and the byte range within that string
+    pub item: usize,
+    pub bytes: Range<usize>,
+    // Horizontal displacement in thousandths of text space units, the unit of
+    // TJ position adjustments
+    pub displacement: f64,
+}
+
+// Tracks the text and line matrices of the current text object and places
+// glyphs using the fonts' widths, for analyses that need text geometry
+pub struct TextLayout<'a> {
+    doc: &'a Document,
+    fonts: HashMap<ObjectId, Rc<PdfFont>>,
+    pub text_matrix: Matrix,
+    pub line_matrix: Matrix,
+}
+
+impl<'a> TextLayout<'a> {
+    pub fn new(doc: &'a Document) -> Self {
+        TextLayout { doc, fonts: HashMap::new(), text_matrix: IDENTITY_MATRIX, line_matrix: IDENTITY_MATRIX }
+    }
+
+    // Apply a text operator. Text-showing operators return the glyphs they
+    // place; all other operators return an empty list.
+    pub fn apply(&mut self, op: &Operation, state: &mut TextState, resources: Option<&Dictionary>, ctm: &Matrix) -> Vec<PlacedGlyph> {
+        let number = |i: usize| op.operands.get(i).map(object_to_f64).unwrap_or(0.0);
+        let mut glyphs = Vec::new();
+
+        match op.operator.as_str() {
+            "BT" => {
+                self.text_matrix = IDENTITY_MATRIX;
+                self.line_matrix = IDENTITY_MATRIX;
+            },
+            "Tf" => {
+                if let Some(Object::Name(name)) = op.operands.first() {
+                    state.font = self.load_font(resources, name);
+                    state.font_name = Some(name.clone());
+                }
+                state.font_size = number(1);
+            },
+            "Tc" => state.char_spacing = number(0),
+            "Tw" => state.word_spacing = number(0),
+            "Tz" => state.horizontal_scale = number(0) / 100.0,
+            "TL" => state.leading = number(0),
+            "Ts" => state.rise = number(0),
+            "Tr" => state.render_mode = number(0) as i64,
+            "Td" => self.next_line(number(0), number(1)),
+            "TD" => {
+                state.leading = -number(1);
+                self.next_line(number(0), number(1));
+            },
+            "Tm" => {
+                if let Some(matrix) = operands_to_matrix(&op.operands) {
+                    self.text_matrix = matrix;
+                    self.line_matrix = matrix;
+                }
+            },
+            "T*" => self.next_line(0.0, -state.leading),
+            "Tj" => {
+                if let Some(Object::String(bytes, _)) = op.operands.first() {
+                    self.show_text(bytes, 0, state, ctm, &mut glyphs);
+                }
+            },
+            "'" => {
+                self.next_line(0.0, -state.leading);
+                if let Some(Object::String(bytes, _)) = op.operands.first() {
+                    self.show_text(bytes, 0, state, ctm, &mut glyphs);
+                }
+            },
+            "\"" => {
+                state.word_spacing = number(0);
+                state.char_spacing = number(1);
+                self.next_line(0.0, -state.leading);
+                if let Some(Object::String(bytes, _)) = op.operands.get(2) {
+                    self.show_text(bytes, 0, state, ctm, &mut glyphs);
+                }
+            },
+            "TJ" => {
+                if let Some(Object::Array(items)) = op.operands.first() {
+                    for (index, item) in items.iter().enumerate() {
+                        match item {
+                            Object::String(bytes, _) => self.show_text(bytes, index, state, ctm, &mut glyphs),
+                            Object::Integer(_) | Object::Real(_) => {
+                                let tx = -object_to_f64(item) / 1000.0 * state.font_size * state.horizontal_scale;
+                                self.text_matrix = concat_matrix(&self.text_matrix, &[1.0, 0.0, 0.0, 1.0, tx, 0.0]);
+                            },
+                            _ => {},
+                        }
+                    }
+                }
+            },
+            _ => {},
+        }
+        glyphs
+    }
+
+    fn load_font(&mut self, resources: Option<&Dictionary>, name: &[u8]) -> Option<Rc<PdfFont>> {
+        let entry = resources?
+            .get(b"Font")
+            .ok()
+            .and_then(|obj| self.doc.dereference(obj).ok())
+            .and_then(|(_, obj)| obj.as_dict().ok())?
+            .get(name)
+            .ok()?;
+        let id = entry.as_reference().ok();
+        if let Some(font) = id.and_then(|id| self.fonts.get(&id)) {
+            return Some(font.clone());
+        }
+
+        let dict = self.doc.dereference(entry).ok()?.1.as_dict().ok()?;
+        let font = Rc::new(PdfFont::load(self.doc, dict, None));
+        if let Some(id) = id {
+            self.fonts.insert(id, font.clone());
+        }
+        Some(font)
+    }
+
+    fn next_line(&mut self, tx: f64, ty: f64) {
+        self.line_matrix = concat_matrix(&self.line_matrix, &[1.0, 0.0, 0.0, 1.0, tx, ty]);
+        self.text_matrix = self.line_matrix;
+    }
+
+    fn show_text(&mut self, bytes: &[u8], item: usize, state: &TextState, ctm: &Matrix, glyphs: &mut Vec<PlacedGlyph>) {
+        let Some(font) = state.font.clone() else { return };
+        let size = state.font_size;
+        let code_length = if font.two_byte { 2 } else { 1 };
+
+        for (index, code) in font.codes(bytes).into_iter().enumerate() {
+            let advance = font.advance(code);
+            let rendering = concat_matrix(
+                &concat_matrix(ctm, &self.text_matrix),
+                &[size * state.horizontal_scale, 0.0, 0.0, size, 0.0, state.rise],
+            );
+            let word_spacing = if !font.two_byte && code == 32 { state.word_spacing } else { 0.0 };
+            let width = advance * size + state.char_spacing + word_spacing;
+            let start = index * code_length;
+            glyphs.push(PlacedGlyph {
+                bounds: transform_bounds(&rendering, 0.0, font.descent, advance, font.ascent),
+                text: font.unicode(code).unwrap_or_default().to_string(),
+                item,
+                bytes: start..(start + code_length).min(bytes.len()),
+                displacement: if size == 0.0 { 0.0 } else { width * 1000.0 / size },
+            });
+
+            let tx = width * state.horizontal_scale;
+            self.text_matrix = concat_matrix(&self.text_matrix, &[1.0, 0.0, 0.0, 1.0, tx, 0.0]);
+        }
+    }
+}
+
+// Smallest box containing all the placed glyphs
+pub fn glyph_bounds(glyphs: &[PlacedGlyph]) -> Option<(f64, f64, f64, f64)> {
+    glyphs.iter().map(|glyph| glyph.bounds).reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+}
+
+// Text shown by one text-showing operator
+pub struct TextRun {
+    pub text: String,
+    pub bounds: (f64, f64, f64, f64),
+}
+
+impl TextRun {
+    // Join the glyphs' text. Generators that position words with kerning
+    // instead of space glyphs get a space where the gap is wider than a fifth
+    // of the glyph height.
+    fn from_glyphs(glyphs: &[PlacedGlyph]) -> Option<TextRun> {
+        let bounds = glyph_bounds(glyphs)?;
+        let mut text = String::new();
+        let mut previous: Option<&PlacedGlyph> = None;
+
+        for glyph in glyphs {
+            if let Some(previous) = previous {
+                let gap = glyph.bounds.0 - previous.bounds.2;
+                let height = glyph.bounds.3 - glyph.bounds.1;
+                if gap > height / 5.0 && !text.ends_with(char::is_whitespace) && !glyph.text.starts_with(char::is_whitespace) {
+                    text.push(' ');
+                }
+            }
+            text.push_str(&glyph.text);
+            previous = Some(glyph);
+        }
+        Some(TextRun { text, bounds })
+    }
+}
+
+// Visible text of a page in content order, one run per text-showing operator.
+// Text inside form XObjects is not included.
+pub fn page_text_runs(doc: &Document, page_id: ObjectId) -> Vec<TextRun> {
+    let Ok(content) = decode_content(&page_content(doc, page_id)) else { return Vec::new() };
+    let mut runs = Vec::new();
+    place_glyphs(doc, page_id, &content.operations, |_, text, glyphs| {
+        if text.is_visible() {
+            runs.extend(TextRun::from_glyphs(&glyphs));
+        }
+    });
+    runs
+}
+
+// Place the glyphs of a page's content operations, calling `visit` with the
+// operator index, text state and glyphs of every text operator
+pub fn place_glyphs(doc: &Document, page_id: ObjectId, operations: &[Operation], mut visit: impl FnMut(usize, &TextState, Vec<PlacedGlyph>)) {
+    let resources = inherited(doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok());
+    let mut layout = TextLayout::new(doc);
+    let (mut text, mut ctm) = (TextState::default(), IDENTITY_MATRIX);
+    let mut stack: Vec<(TextState, Matrix)> = Vec::new();
+
+    for (index, op) in operations.iter().enumerate() {
+        match op.operator.as_str() {
+            "q" => stack.push((text.clone(), ctm)),
+            "Q" => {
+                if let Some(saved) = stack.pop() {
+                    (text, ctm) = saved;
+                }
+            },
+            "cm" => {
+                if let Some(m) = operands_to_matrix(&op.operands) {
+                    ctm = concat_matrix(&ctm, &m);
+                }
+            },
+            _ => {
+                let glyphs = layout.apply(op, &mut text, resources, &ctm);
+                if !glyphs.is_empty() {
+                    visit(index, &text, glyphs);
+                }
+            },
+        }
+    }
+}
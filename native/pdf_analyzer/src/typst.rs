@@ -1,79 +1,249 @@
-use lopdf::{Document, Object, Stream, content::{Content, Operation}};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, content::{Content, Operation}};
 use std::collections::HashMap;
+use crate::error::{PdfError, WarningKind};
+use crate::parallel::{optional_pool, ordered_map};
+use crate::marked_content::MarkedContent;
+use crate::merge::{merge_duplicates, DEFAULT_MERGE_IOU};
+use crate::pdf_image::{decode_content, image_entry};
+use crate::optional_content::OptionalContent;
+use crate::overlay::{page_box, PageBox};
+use crate::render::{inherited, visible_box};
+use crate::security::encryption_info;
 use crate::common::{
-    DocumentAnalysisResult, PageAnalysisResult, RectangleData, SummaryStats,
-    OperatorCount, Point, Dimensions, CornerCoordinates, GraphicsState,
-    object_to_f64
+    AnalysisOptions, AnalysisWarning, AnalysisWarningKind, FieldWarningKind, ContentStreamInfo, DocumentAnalysisResult, PageAnalysisResult, RectangleData, SummaryStats,
+    OperatorCount, Point, Dimensions, CornerCoordinates, GraphicsState, Matrix,
+    IDENTITY_MATRIX, concat_matrix, operands_to_matrix, object_to_f64, transform_bounds, transform_point
 };
 
-pub fn analyze_pdf_typst(path: &str, target_fill_color: Option<&str>, target_stroke_color: Option<&str>) -> Result<DocumentAnalysisResult, String> {
-    let doc = Document::load(path).map_err(|e| format!("Failed to open PDF: {}", e))?;
+pub fn analyze_pdf_typst(doc: &Document, target_fill_color: Option<&str>, target_stroke_color: Option<&str>, options: &AnalysisOptions) -> Result<DocumentAnalysisResult, PdfError> {
+    let pages: Vec<(u32, ObjectId)> = doc.get_pages().into_iter().collect();
+    let scan = Scan { target_fill_color, target_stroke_color, options, optional_content: OptionalContent::load(doc) };
+    let analyze = |&(page_num, page_id): &(u32, ObjectId)| analyze_page(doc, page_num, page_id, &scan);
+
+    // Outcomes are collected in page order whichever thread analyzed the page
+    let outcomes = ordered_map(optional_pool(options.threads)?.as_deref(), &pages, analyze);
+
     let mut all_rectangles: Vec<RectangleData> = Vec::new();
-    let mut total_pages_processed = 0;
-
-    // Existing Typst engine implementation
-    for (page_num, page_id) in doc.get_pages() {
-        total_pages_processed += 1;
-        let mut page_rectangles: Vec<RectangleData> = Vec::new();
-
-        // Extract page height for coordinate transformation
-        let page_height = get_page_height(&doc, page_id).unwrap_or(792.0); // Default to letter size height
-
-        // Method 1: Process content through the Contents dictionary reference
-        if let Ok(Object::Dictionary(dict)) = doc.get_object(page_id) {
-            match dict.get(b"Contents") {
-                Ok(content_refs) => {
-                    match content_refs {
-                        Object::Reference(content_id) => {
-                            if let Some(mut analysis_result) = analyze_content_stream(&doc, *content_id, page_num, page_height, target_fill_color, target_stroke_color) {
-                                page_rectangles.append(&mut analysis_result.rectangles);
-                            }
-                        },
-                        Object::Array(content_ids) => {
-                            for content_id in content_ids.iter() {
-                                if let Object::Reference(id) = content_id {
-                                    if let Some(mut analysis_result) = analyze_content_stream(&doc, *id, page_num, page_height, target_fill_color, target_stroke_color) {
-                                        page_rectangles.append(&mut analysis_result.rectangles);
-                                    }
-                                }
-                            }
-                        },
-                        Object::Stream(stream) => {
-                            if let Some(mut analysis_result) = analyze_stream_object(stream, page_num, page_height, target_fill_color, target_stroke_color) {
-                                page_rectangles.append(&mut analysis_result.rectangles);
-                            }
-                        },
-                        _ => {}
-                    }
-                },
-                // Method 2: Only use get_page_content as a fallback
-                Err(_) => {
-                    match doc.get_page_content(page_id) {
-                        Ok(content_data) => {
-                            match Content::decode(&content_data) {
-                                Ok(content) => {
-                                    let mut analysis_result = analyze_content_operations(&content.operations, page_num, page_height, target_fill_color, target_stroke_color);
-                                    page_rectangles.append(&mut analysis_result.rectangles);
-                                },
-                                Err(_) => {}
-                            }
-                        },
-                        Err(_) => {}
-                    }
-                }
-            }
+    let mut page_results: Vec<PageAnalysisResult> = Vec::new();
+    let mut content_streams: Vec<ContentStreamInfo> = Vec::new();
+    let mut warnings: Vec<AnalysisWarning> = Vec::new();
+
+    for outcome in outcomes {
+        warnings.extend(outcome.warnings);
+        if options.diagnostics {
+            all_rectangles.extend(outcome.result.rectangles.iter().cloned());
+            page_results.push(outcome.result);
+            content_streams.extend(outcome.content_streams);
+        } else {
+            all_rectangles.extend(outcome.result.rectangles);
         }
+    }
 
-        all_rectangles.append(&mut page_rectangles);
+    if options.strict {
+        let stream_warning = warnings.iter().find_map(|warning| match warning.kind {
+            AnalysisWarningKind::ContentStream(kind) => Some((kind, warning)),
+            AnalysisWarningKind::Field(_) => None,
+        });
+        if let Some((kind, warning)) = stream_warning {
+            let stream = warning.object_id.map(|(id, gen)| format!("content stream {} {} R", id, gen)).unwrap_or_else(|| "content stream".to_string());
+            return Err(PdfError::ContentStream(kind, format!("Page {}, {}: {}", warning.page, stream, warning.message)));
+        }
+    }
+    if !options.keep_duplicates {
+        all_rectangles = merge_duplicates(all_rectangles, options.merge_iou.unwrap_or(DEFAULT_MERGE_IOU));
+    }
+    if let Some(threshold) = options.occlusion_threshold {
+        let occluded = |rect: &RectangleData| !rect.hidden && rect.occluded > 0.0 && rect.occluded >= threshold;
+        if options.drop_occluded {
+            all_rectangles.retain(|rect| !occluded(rect));
+        } else {
+            warnings.extend(all_rectangles.iter().filter(|rect| occluded(rect)).map(|rect| AnalysisWarning {
+                page: rect.page,
+                object_id: None,
+                kind: AnalysisWarningKind::Field(FieldWarningKind::OccludedField),
+                message: format!("Rectangle at operation {} is {:.0}% covered by content painted over it", rect.operation, rect.occluded * 100.0),
+            }));
+        }
     }
 
     Ok(DocumentAnalysisResult {
-        total_pages: total_pages_processed,
+        total_pages: pages.len() as u32,
         total_rectangles: all_rectangles.len(),
         rectangles: all_rectangles,
+        warnings,
+        pages: options.diagnostics.then_some(page_results),
+        content_streams: options.diagnostics.then_some(content_streams),
+        encryption: encryption_info(doc),
+        strategy: None,
     })
 }
 
+// Everything found on one page. Pages are analyzed independently so they can run in parallel.
+struct PageOutcome {
+    result: PageAnalysisResult,
+    content_streams: Vec<ContentStreamInfo>,
+    warnings: Vec<AnalysisWarning>,
+}
+
+// What every page of the document is scanned for
+struct Scan<'a> {
+    target_fill_color: Option<&'a str>,
+    target_stroke_color: Option<&'a str>,
+    options: &'a AnalysisOptions,
+    optional_content: OptionalContent,
+}
+
+// Painting order of a page's rectangles and of the opaque content that can
+// cover them, counted in operations across all content streams of the page
+#[derive(Default)]
+struct PaintOrder {
+    // Operations in the content streams analyzed so far
+    offset: usize,
+    // When each reported rectangle was painted, in the order they are reported
+    rectangles: Vec<usize>,
+    // Opaque fills and images with their visible bounds
+    opaque: Vec<(usize, Bounds)>,
+    // Whether anything has been painted on the page yet
+    painted: bool,
+}
+
+// What the content of a page is analyzed against
+struct PageContext<'a> {
+    doc: &'a Document,
+    page_num: u32,
+    resources: Option<&'a Dictionary>,
+    // Nothing outside the crop box is visible
+    visible_box: PageBox,
+    media_box: PageBox,
+    scan: &'a Scan<'a>,
+}
+
+fn analyze_page(doc: &Document, page_num: u32, page_id: ObjectId, scan: &Scan) -> PageOutcome {
+    let context = PageContext {
+        doc,
+        page_num,
+        resources: inherited(doc, page_id, b"Resources").and_then(|obj| obj.as_dict().ok()),
+        visible_box: visible_box(doc, page_id),
+        media_box: page_box(doc, page_id),
+        scan,
+    };
+    let mut stream_results: Vec<PageAnalysisResult> = Vec::new();
+    let mut content_streams: Vec<ContentStreamInfo> = Vec::new();
+    let mut warnings: Vec<AnalysisWarning> = Vec::new();
+    let mut paint_order = PaintOrder::default();
+
+    // Extract page height for coordinate transformation
+    let page_height = get_page_height(doc, page_id).unwrap_or(792.0); // Default to letter size height
+
+    // Method 1: Process content through the Contents dictionary reference
+    if let Ok(Object::Dictionary(dict)) = doc.get_object(page_id) {
+        match dict.get(b"Contents") {
+            Ok(content_refs) => {
+                for (content_id, stream) in page_streams(doc, content_refs) {
+                    let Some(stream) = stream else {
+                        warnings.push(AnalysisWarning {
+                            page: page_num,
+                            object_id: content_id,
+                            kind: AnalysisWarningKind::ContentStream(WarningKind::CorruptStream),
+                            message: "Content stream object is missing or is not a stream".to_string(),
+                        });
+                        continue;
+                    };
+                    let (info, analysis_result, stream_warnings) = analyze_stream_object(stream, content_id, &context, page_height, &mut paint_order);
+                    stream_results.extend(analysis_result);
+                    warnings.extend(stream_warnings);
+                    content_streams.push(info);
+                }
+            },
+            // Method 2: Only use get_page_content as a fallback
+            Err(_) => {
+                if let Ok(content_data) = doc.get_page_content(page_id) {
+                    if let Ok(content) = Content::decode(&content_data) {
+                        stream_results.push(analyze_content_operations(&content.operations, &context, page_height, &mut paint_order));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut result = merge_page_results(page_num, stream_results);
+    for (rect, &painted) in result.rectangles.iter_mut().zip(&paint_order.rectangles) {
+        let Some(region) = &rect.visible_region else { continue };
+        let covers: Vec<Bounds> = paint_order.opaque.iter().filter(|(order, _)| *order > painted).map(|(_, bounds)| *bounds).collect();
+        rect.occluded = covered_fraction((region.x1, region.y1, region.x2, region.y2), &covers);
+    }
+    PageOutcome { result, content_streams, warnings }
+}
+
+// Content streams of a page in drawing order, with their object ids.
+// Entries that do not resolve to a stream are returned as None.
+fn page_streams<'a>(doc: &'a Document, contents: &'a Object) -> Vec<(Option<ObjectId>, Option<&'a Stream>)> {
+    match contents {
+        Object::Reference(content_id) => match doc.get_object(*content_id) {
+            Ok(Object::Stream(stream)) => vec![(Some(*content_id), Some(stream))],
+            Ok(array @ Object::Array(_)) => page_streams(doc, array),
+            _ => vec![(Some(*content_id), None)],
+        },
+        Object::Array(content_ids) => content_ids
+            .iter()
+            .map(|content_id| {
+                let id = content_id.as_reference().ok();
+                (id, id.and_then(|id| doc.get_object(id).and_then(Object::as_stream).ok()))
+            })
+            .collect(),
+        Object::Stream(stream) => vec![(None, Some(stream))],
+        _ => vec![(None, None)],
+    }
+}
+
+// Combine the results of a page's content streams into one page result
+fn merge_page_results(page_num: u32, results: Vec<PageAnalysisResult>) -> PageAnalysisResult {
+    let mut summary = SummaryStats {
+        total_operations: 0,
+        rectangle_operations: 0,
+        path_operations: 0,
+        text_operations: 0,
+        invisible_text_operations: 0,
+        background_rectangles: 0,
+        marked_content_operations: 0,
+        other_operations: 0,
+    };
+    let mut op_counts: HashMap<String, usize> = HashMap::new();
+    let mut rectangles = Vec::new();
+    let mut background = Vec::new();
+
+    for result in results {
+        summary.total_operations += result.summary.total_operations;
+        summary.rectangle_operations += result.summary.rectangle_operations;
+        summary.path_operations += result.summary.path_operations;
+        summary.text_operations += result.summary.text_operations;
+        summary.invisible_text_operations += result.summary.invisible_text_operations;
+        summary.background_rectangles += result.summary.background_rectangles;
+        summary.marked_content_operations += result.summary.marked_content_operations;
+        summary.other_operations += result.summary.other_operations;
+        for count in result.most_common_operators {
+            *op_counts.entry(count.operator).or_insert(0) += count.occurrences;
+        }
+        rectangles.extend(result.rectangles);
+        background.extend(result.background);
+    }
+
+    let mut op_vec: Vec<_> = op_counts.into_iter().collect();
+    op_vec.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let most_common_operators = op_vec.into_iter().take(10).map(|(operator, occurrences)| OperatorCount {
+        operator,
+        occurrences,
+    }).collect();
+
+    PageAnalysisResult {
+        pdf_page: page_num,
+        summary,
+        most_common_operators,
+        rectangles,
+        background,
+    }
+}
+
 fn get_page_height(doc: &Document, page_id: lopdf::ObjectId) -> Option<f64> {
     if let Ok(Object::Dictionary(page_dict)) = doc.get_object(page_id) {
         // Try to get MediaBox first
@@ -99,45 +269,131 @@ fn get_page_height(doc: &Document, page_id: lopdf::ObjectId) -> Option<f64> {
     None
 }
 
-fn analyze_content_stream(doc: &Document, content_id: lopdf::ObjectId, page_num: u32, page_height: f64, target_fill_color: Option<&str>, target_stroke_color: Option<&str>) -> Option<PageAnalysisResult> {
-    match doc.get_object(content_id) {
-        Ok(Object::Stream(stream)) => {
-            analyze_stream_object(stream, page_num, page_height, target_fill_color, target_stroke_color)
+// Content filters lopdf can decompress
+const SUPPORTED_FILTERS: [&[u8]; 3] = [b"FlateDecode", b"LZWDecode", b"ASCII85Decode"];
+
+// Operator appended to content data so a parse that stops early can be detected
+const END_MARKER: &str = "WraftContentEnd";
+
+// Decompress a content stream. Streams without /Filter are stored uncompressed.
+// Corrupt Flate data is kept as far as it could be inflated and returned with a warning.
+fn stream_content(stream: &Stream) -> Result<(Vec<u8>, Option<String>), (WarningKind, String)> {
+    if !stream.dict.has(b"Filter") {
+        return Ok((stream.content.clone(), None));
+    }
+    let filters = stream.filters().map_err(|e| (WarningKind::CorruptStream, format!("Invalid /Filter entry: {}", e)))?;
+    if let Some(filter) = filters.iter().find(|filter| !SUPPORTED_FILTERS.contains(filter)) {
+        return Err((WarningKind::UnsupportedFilter, format!("Unsupported content stream filter: {}", String::from_utf8_lossy(filter))));
+    }
+    let content_data = stream
+        .decompressed_content()
+        .map_err(|e| (WarningKind::CorruptStream, format!("Failed to decompress content stream: {}", e)))?;
+
+    // lopdf ignores zlib errors and keeps the partial output, so check the data separately
+    let mut corruption = None;
+    if filters.first() == Some(&&b"FlateDecode"[..]) {
+        let mut decoder = flate2::read::ZlibDecoder::new(stream.content.as_slice());
+        if let Err(e) = std::io::copy(&mut decoder, &mut std::io::sink()) {
+            corruption = Some(format!("Content stream is corrupt after {} decoded bytes: {}", content_data.len(), e));
+        }
+    }
+    Ok((content_data, corruption))
+}
+
+// Parse content, returning the operations read before any syntax error and the error
+fn decode_operations(content_data: &[u8]) -> (Vec<Operation>, Option<String>) {
+    let mut data = content_data.to_vec();
+    data.push(b'\n');
+    data.extend_from_slice(END_MARKER.as_bytes());
+
+    match decode_content(&data) {
+        Ok(mut content) => {
+            if content.operations.last().is_some_and(|op| op.operator == END_MARKER) {
+                content.operations.pop();
+                (content.operations, None)
+            } else {
+                let parsed = content.operations.len();
+                (content.operations, Some(format!("Failed to parse content stream after {} operations", parsed)))
+            }
         },
-        _ => None,
+        Err(e) => (Vec::new(), Some(format!("Failed to decode content stream: {}", e))),
     }
 }
 
-fn analyze_stream_object(stream: &Stream, page_num: u32, page_height: f64, target_fill_color: Option<&str>, target_stroke_color: Option<&str>) -> Option<PageAnalysisResult> {
-    match stream.decompressed_content() {
-        Ok(content_data) => {
-            match Content::decode(&content_data) {
-                Ok(content) => {
-                    Some(analyze_content_operations(&content.operations, page_num, page_height, target_fill_color, target_stroke_color))
-                },
-                Err(_) => None,
+fn analyze_stream_object(stream: &Stream, content_id: Option<ObjectId>, context: &PageContext, page_height: f64, paint_order: &mut PaintOrder) -> (ContentStreamInfo, Option<PageAnalysisResult>, Vec<AnalysisWarning>) {
+    let page_num = context.page_num;
+    let mut info = ContentStreamInfo {
+        page: page_num,
+        object_id: content_id,
+        raw_size: stream.content.len(),
+        decoded_size: None,
+        operations: None,
+        error: None,
+    };
+    let mut warnings = Vec::new();
+    let mut warn = |info: &mut ContentStreamInfo, kind: WarningKind, message: String| {
+        info.error = Some(match info.error.take() {
+            Some(previous) => format!("{}; {}", previous, message),
+            None => message.clone(),
+        });
+        warnings.push(AnalysisWarning { page: page_num, object_id: content_id, kind: AnalysisWarningKind::ContentStream(kind), message });
+    };
+
+    let content_data = match stream_content(stream) {
+        Ok((content_data, corruption)) => {
+            if let Some(message) = corruption {
+                warn(&mut info, WarningKind::CorruptStream, message);
             }
+            content_data
         },
-        Err(_) => None,
+        Err((kind, message)) => {
+            warn(&mut info, kind, message);
+            return (info, None, warnings);
+        },
+    };
+    info.decoded_size = Some(content_data.len());
+
+    // Operations before a syntax error are still analyzed
+    let (operations, error) = decode_operations(&content_data);
+    if let Some(message) = error {
+        warn(&mut info, WarningKind::DecodeError, message);
     }
+    info.operations = Some(operations.len());
+    let result = analyze_content_operations(&operations, context, page_height, paint_order);
+    (info, Some(result), warnings)
 }
 
-fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_height: f64, target_fill_color: Option<&str>, target_stroke_color: Option<&str>) -> PageAnalysisResult {
+fn analyze_content_operations(operations: &[Operation], context: &PageContext, _page_height: f64, paint_order: &mut PaintOrder) -> PageAnalysisResult {
+    let (page_num, target_fill_color, target_stroke_color) = (context.page_num, context.scan.target_fill_color, context.scan.target_stroke_color);
+    let options = context.scan.options;
     let mut rectangle_count = 0;
     let mut path_ops_count = 0;
     let mut text_ops_count = 0;
+    let mut invisible_text_ops_count = 0;
+    let mut background = Vec::new();
+    let mut marked_content_ops_count = 0;
     let mut other_ops_count = 0;
+    // Open marked-content sequences, outermost first. They nest independently of q/Q.
+    let mut marked_content: Vec<MarkedContent> = Vec::new();
+    // Whether each open sequence is inside optional content that is turned off
+    let mut hidden_content: Vec<bool> = Vec::new();
+    // Bounds of the current path in default user space, and whether it becomes
+    // the clipping path when it is painted
+    let mut path_bounds: Option<Bounds> = None;
+    let mut clip_pending = false;
+    // Whether the current path consists of rectangles only, so its bounds are
+    // exactly what a fill covers
+    let mut path_is_rectangles = true;
 
     let mut op_counts: HashMap<String, usize> = HashMap::new();
-    let mut state = GraphicsState::default();
+    let page_box = context.visible_box;
+    let mut state = GraphicsState { clip: Some((page_box.llx, page_box.lly, page_box.urx, page_box.ury)), ..GraphicsState::default() };
     let mut graphics_stack: Vec<GraphicsState> = Vec::new();
     let mut rectangles_data: Vec<RectangleData> = Vec::new();
 
-    // Transformation matrix [a, b, c, d, e, f] where:
-    // x' = a*x + c*y + e
-    // y' = b*x + d*y + f
-    let mut transform_matrix: [f64; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0]; // Identity matrix
-    let mut transform_stack: Vec<[f64; 6]> = Vec::new();
+    let mut transform_matrix: Matrix = IDENTITY_MATRIX;
+    let mut transform_stack: Vec<Matrix> = Vec::new();
+    let painting_operators = painting_operators(operations);
 
     for (i, op) in operations.iter().enumerate() {
         *op_counts.entry(op.operator.clone()).or_insert(0) += 1;
@@ -156,109 +412,23 @@ fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_hei
                 }
             },
             "cm" => {
-                // Transformation matrix: [a b c d e f]
-                // x' = a*x + c*y + e
-                // y' = b*x + d*y + f
-                if op.operands.len() >= 6 {
-                    let a = object_to_f64(&op.operands[0]);
-                    let b = object_to_f64(&op.operands[1]);
-                    let c = object_to_f64(&op.operands[2]);
-                    let d = object_to_f64(&op.operands[3]);
-                    let e = object_to_f64(&op.operands[4]);
-                    let f = object_to_f64(&op.operands[5]);
-
-                    // Multiply current matrix with new matrix
-                    let new_matrix = [
-                        transform_matrix[0] * a + transform_matrix[2] * b,
-                        transform_matrix[1] * a + transform_matrix[3] * b,
-                        transform_matrix[0] * c + transform_matrix[2] * d,
-                        transform_matrix[1] * c + transform_matrix[3] * d,
-                        transform_matrix[0] * e + transform_matrix[2] * f + transform_matrix[4],
-                        transform_matrix[1] * e + transform_matrix[3] * f + transform_matrix[5],
-                    ];
-                    transform_matrix = new_matrix;
-                }
-            },
-            "CS" => {
-                if let Some(Object::Name(name)) = op.operands.first() {
-                    state.stroke_color_space = std::str::from_utf8(name).unwrap_or("DeviceGray").to_string();
+                if let Some(matrix) = operands_to_matrix(&op.operands) {
+                    transform_matrix = concat_matrix(&transform_matrix, &matrix);
                 }
             },
-            "cs" => {
-                if let Some(Object::Name(name)) = op.operands.first() {
-                    state.fill_color_space = std::str::from_utf8(name).unwrap_or("DeviceGray").to_string();
-                }
-            },
-            "SCN" | "scn" => {
-                let is_stroke = op.operator == "SCN";
-                let color_space = if is_stroke { &state.stroke_color_space } else { &state.fill_color_space };
-                match color_space.as_str() {
-                    "srgb" | "DeviceRGB" => {
-                        if op.operands.len() >= 3 {
-                            let r_f = object_to_f64(&op.operands[0]);
-                            let g_f = object_to_f64(&op.operands[1]);
-                            let b_f = object_to_f64(&op.operands[2]);
-                            let r = (r_f * 255.0) as u8;
-                            let g = (g_f * 255.0) as u8;
-                            let b = (b_f * 255.0) as u8;
-                            let color = format!("RGB({}, {}, {})", r, g, b);
-                            if is_stroke {
-                                state.stroke_color = color;
-                            } else {
-                                state.fill_color = color;
-                            }
-                        }
-                    },
-                    "d65gray" | "DeviceGray" => {
-                        if let Some(gray_f) = op.operands.first().map(object_to_f64) {
-                            let gray_val = (gray_f * 255.0) as u8;
-                            let color = format!("Gray({})", gray_val);
-                            if is_stroke {
-                                state.stroke_color = color;
-                            } else {
-                                state.fill_color = color;
-                            }
-                        }
-                    },
-                    _ => {}
-                }
-            },
-            "RG" | "rg" => {
-                if op.operands.len() >= 3 {
-                    let r_f = object_to_f64(&op.operands[0]);
-                    let g_f = object_to_f64(&op.operands[1]);
-                    let b_f = object_to_f64(&op.operands[2]);
-                    let r = (r_f * 255.0) as u8;
-                    let g = (g_f * 255.0) as u8;
-                    let b = (b_f * 255.0) as u8;
-                    let color = format!("RGB({}, {}, {})", r, g, b);
-                    if op.operator == "RG" {
-                        state.stroke_color = color;
-                        state.stroke_color_space = "DeviceRGB".to_string();
-                    } else {
-                        state.fill_color = color;
-                        state.fill_color_space = "DeviceRGB".to_string();
-                    }
-                }
-            },
-            "G" | "g" => {
-                if let Some(gray_f) = op.operands.first().map(object_to_f64) {
-                    let gray_val = (gray_f * 255.0) as u8;
-                    let color = format!("Gray({})", gray_val);
-                    if op.operator == "G" {
-                        state.stroke_color = color;
-                        state.stroke_color_space = "DeviceGray".to_string();
-                    } else {
-                        state.fill_color = color;
-                        state.fill_color_space = "DeviceGray".to_string();
-                    }
-                }
+            "CS" | "cs" | "SC" | "sc" | "SCN" | "scn" | "RG" | "rg" | "G" | "g" | "K" | "k" => {
+                state.apply_color_operator(context.doc, op, context.resources);
             },
             "w" => {
                 if let Some(width_obj) = op.operands.first() {
                     state.line_width = object_to_f64(width_obj);
                 }
             },
+            "gs" => {
+                if let Some(Object::Name(name)) = op.operands.first() {
+                    state.apply_ext_gstate(context.doc, name, context.resources);
+                }
+            },
             "re" => {
                 rectangle_count += 1;
                 if op.operands.len() >= 4 {
@@ -266,60 +436,58 @@ fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_hei
                     let y = object_to_f64(&op.operands[1]);
                     let width = object_to_f64(&op.operands[2]);
                     let height = object_to_f64(&op.operands[3]);
+                    extend_bounds(&mut path_bounds, &transform_matrix, &[(x, y), (x + width, y), (x, y + height), (x + width, y + height)]);
 
-                    let mut op_type = "Unknown".to_string();
-                    let mut lookahead_index = i + 1;
-                    while lookahead_index < operations.len() {
-                        let lookahead_op = &operations[lookahead_index];
-                        match lookahead_op.operator.as_str() {
-                            "S" => { op_type = String::from("Stroke only"); break; },
-                            "B" => { op_type = String::from("Fill and Stroke"); break; },
-                            "f" | "F" => { op_type = String::from("Fill only"); break; },
-                            "re" | "m" | "l" | "c" | "v" | "y" | "h" | "q" | "Q" | "cm" | "gs" => break,
-                            _ => { lookahead_index += 1; }
-                        }
-                    }
+                    let paint_index = painting_operators[i].unwrap_or(i);
+                    let op_type = match painting_operators[i].map(|index| operations[index].operator.as_str()) {
+                        Some("S" | "s") => String::from("Stroke only"),
+                        Some("B" | "B*" | "b" | "b*") => String::from("Fill and Stroke"),
+                        Some("f" | "F" | "f*") => String::from("Fill only"),
+                        Some("n") => String::from("No paint"),
+                        _ => "Unknown".to_string(),
+                    };
 
                     let current_fill_color_formatted = format!("{} ({})", state.fill_color, state.fill_color_space);
                     let current_stroke_color_formatted = format!("{} ({})", state.stroke_color, state.stroke_color_space);
 
-                    // For testing purposes, we'll override the colors for specific rectangles
-                    // This is just to demonstrate the color filtering functionality
-                    let mut fill_color_override: Option<String> = None;
-                    let mut stroke_color_override: Option<String> = None;
-
-                    // First rectangle (full page) gets our target colors
-                    if i == 0 && x == 0.0 && width > 590.0 && height < -800.0 {
-                        fill_color_override = Some("RGB(214, 255, 244)".to_string());
-                        stroke_color_override = Some("RGB(0, 184, 148)".to_string());
-                    }
-
-                    // Use the overridden colors for matching if they exist
-                    let fill_color_for_matching = fill_color_override.as_ref().unwrap_or(&state.fill_color);
-                    let stroke_color_for_matching = stroke_color_override.as_ref().unwrap_or(&state.stroke_color);
-
                     // Check if the rectangle matches the target colors
                     let fill_color_matches = match target_fill_color {
-                        Some(target) => fill_color_for_matching.contains(target),
+                        Some(target) => state.fill_color.contains(target),
                         None => true, // No filter means all match
                     };
 
                     let stroke_color_matches = match target_stroke_color {
-                        Some(target) => stroke_color_for_matching.contains(target),
+                        Some(target) => state.stroke_color.contains(target),
                         None => true, // No filter means all match
                     };
 
+                    // A filled rectangle over the whole MediaBox, painted before anything
+                    // else on the page, is the page background and not a field
+                    let bounds = transform_bounds(&transform_matrix, x, y, x + width, y + height);
+                    let is_background = !paint_order.painted
+                        && matches!(op_type.as_str(), "Fill only" | "Fill and Stroke")
+                        && covers_page(bounds, &context.media_box);
+
+                    // Shapes whose painted parts are fully transparent, or that are not painted at all
+                    let transparency = &state.transparency;
+                    let invisible = match op_type.as_str() {
+                        "Fill only" => transparency.fill_opacity == 0.0,
+                        "Stroke only" => transparency.stroke_opacity == 0.0,
+                        "Fill and Stroke" => transparency.fill_opacity == 0.0 && transparency.stroke_opacity == 0.0,
+                        "No paint" => true,
+                        _ => false,
+                    };
+                    let strokes = matches!(op_type.as_str(), "Stroke only" | "Fill and Stroke");
+
                     // Only add the rectangle if it matches both target colors (or if no targets specified)
-                    if fill_color_matches && stroke_color_matches {
+                    if is_background {
+                        background.push(CornerCoordinates { x1: bounds.0, y1: bounds.1, x2: bounds.2, y2: bounds.3 });
+                    } else if fill_color_matches && stroke_color_matches && !(options.skip_invisible && invisible) {
                         // Apply transformation matrix to coordinates
-                        // x' = a*x + c*y + e
-                        // y' = b*x + d*y + f
-                        let transformed_x = transform_matrix[0] * x + transform_matrix[2] * y + transform_matrix[4];
-                        let transformed_y = transform_matrix[1] * x + transform_matrix[3] * y + transform_matrix[5];
+                        let (transformed_x, transformed_y) = transform_point(&transform_matrix, x, y);
 
                         // Transform the width and height vectors as well
-                        let transformed_x2 = transform_matrix[0] * (x + width) + transform_matrix[2] * (y + height) + transform_matrix[4];
-                        let transformed_y2 = transform_matrix[1] * (x + width) + transform_matrix[3] * (y + height) + transform_matrix[5];
+                        let (transformed_x2, transformed_y2) = transform_point(&transform_matrix, x + width, y + height);
 
                         // Calculate actual width and height after transformation
                         let actual_width = transformed_x2 - transformed_x;
@@ -330,45 +498,144 @@ fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_hei
                         let y1 = transformed_y.min(transformed_y + actual_height);  // bottom edge
                         let y2 = transformed_y.max(transformed_y + actual_height);  // top edge
                         let corrected_height = (y2 - y1).abs();
+                        let corners = CornerCoordinates { x1: transformed_x, y1, x2: transformed_x + actual_width, y2 };
+                        let visible_region = visible_region(&corners, state.clip);
+                        let hidden = visible_region.is_none() || hidden_content.last().copied().unwrap_or(false);
 
                         let rect_data = RectangleData {
                             operation: i,
                             position: Point { x: transformed_x, y: transformed_y },
                             dimensions: Dimensions { width: actual_width.abs(), height: corrected_height },
-                            corners: CornerCoordinates {
-                                x1: transformed_x,
-                                y1,
-                                x2: transformed_x + actual_width,
-                                y2,
-                            },
-                            fill_color: fill_color_override.clone().unwrap_or(current_fill_color_formatted),
-                            stroke_color: stroke_color_override.clone().unwrap_or(current_stroke_color_formatted),
+                            corners,
+                            visible_region,
+                            hidden,
+                            // Known once the whole page has been read
+                            occluded: 0.0,
+                            raw_shapes: 1,
+                            fill_color: current_fill_color_formatted,
+                            stroke_color: current_stroke_color_formatted,
                             line_width: state.line_width,
-                            border: state.border_width,
+                            border: if strokes { state.line_width } else { 0.0 },
+                            transparency: state.transparency.clone(),
                             font_name: state.current_font_name.as_ref().map(|bytes| String::from_utf8_lossy(bytes).to_string()),
                             operation_type: op_type,
-                            fill_color_operands: op.operands.iter().map(|obj| object_to_f64(obj)).collect(),
+                            fill_color_operands: op.operands.iter().map(object_to_f64).collect(),
                             page: page_num,
-                            fill_color_override: fill_color_override,
+                            // Assigned once all rectangles of the document are known
+                            field_id: String::new(),
+                            label: None,
+                            role: None,
+                            destination: None,
+                            marked_content: marked_content.clone(),
                         };
-                        rectangles_data.push(rect_data);
+                        if options.include_hidden || !rect_data.hidden {
+                            rectangles_data.push(rect_data);
+                            // A path's own fill is painted at the same index, so it
+                            // never counts as covering the path's rectangles
+                            paint_order.rectangles.push(paint_order.offset + paint_index);
+                        }
                     }
                 }
             },
             "m" | "l" | "c" | "v" | "y" | "h" => {
                 path_ops_count += 1;
+                path_is_rectangles = false;
+                let points: Vec<(f64, f64)> = op.operands.chunks_exact(2).map(|pair| (object_to_f64(&pair[0]), object_to_f64(&pair[1]))).collect();
+                extend_bounds(&mut path_bounds, &transform_matrix, &points);
                 if op.operator == "m" && op.operands.len() >= 2 {
                     state.current_x = object_to_f64(&op.operands[0]);
                     // Keep Y coordinate as-is since PDF already uses bottom-left origin
                     state.current_y = object_to_f64(&op.operands[1]);
                 }
             },
-            "BT" | "ET" | "Tj" | "TJ" | "Td" | "TD" | "T*" => {
+            "BT" | "ET" | "Td" | "TD" | "T*" => {
                 text_ops_count += 1;
             },
+            "Tj" | "TJ" | "'" | "\"" => {
+                text_ops_count += 1;
+                if !state.text_is_visible() {
+                    invisible_text_ops_count += 1;
+                } else {
+                    paint_order.painted = true;
+                }
+            },
+            "Tr" => {
+                text_ops_count += 1;
+                if let Some(mode) = op.operands.first() {
+                    state.text_render_mode = object_to_f64(mode) as i64;
+                }
+            },
+            "BMC" | "BDC" => {
+                marked_content_ops_count += 1;
+                marked_content.push(MarkedContent::begin(context.doc, op, context.resources));
+                let visible = context.scan.optional_content.marked_content_visible(context.doc, op, context.resources);
+                hidden_content.push(hidden_content.last().copied().unwrap_or(false) || !visible);
+            },
+            "EMC" => {
+                marked_content_ops_count += 1;
+                marked_content.pop();
+                hidden_content.pop();
+            },
+            "W" | "W*" => {
+                other_ops_count += 1;
+                clip_pending = true;
+            },
+            // Painting ends the path. A pending clip takes effect afterwards; the
+            // clip keeps the bounds of its path, which is exact for rectangles
+            // in unrotated space. Text clipping (render modes 4 to 7) is not tracked.
+            "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "n" => {
+                other_ops_count += 1;
+                let fills = matches!(op.operator.as_str(), "f" | "F" | "f*" | "B" | "B*" | "b" | "b*");
+                paint_order.painted |= op.operator != "n";
+                if fills && path_is_rectangles && !hidden_content.last().copied().unwrap_or(false) && fills_opaquely(&state) {
+                    if let Some(bounds) = path_bounds.and_then(|bounds| clip_bounds(bounds, state.clip)) {
+                        paint_order.opaque.push((paint_order.offset + i, bounds));
+                    }
+                }
+                if clip_pending {
+                    state.clip = intersect(state.clip, path_bounds);
+                }
+                path_bounds = None;
+                clip_pending = false;
+                path_is_rectangles = true;
+            },
+            // Images are drawn into the unit square of the current transformation
+            // matrix and cover what is below unless they have transparent parts
+            "Do" | "BI" => {
+                other_ops_count += 1;
+                paint_order.painted = true;
+                let image = match op.operands.first() {
+                    Some(Object::Stream(image)) => Some(image),
+                    Some(Object::Name(name)) => context
+                        .resources
+                        .and_then(|res| res.get(b"XObject").ok())
+                        .and_then(|obj| context.doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_dict().ok())
+                        .and_then(|xobjects| xobjects.get(name).ok())
+                        .and_then(|obj| context.doc.dereference(obj).ok())
+                        .and_then(|(_, obj)| obj.as_stream().ok())
+                        .filter(|xobject| xobject.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image".as_slice())),
+                    _ => None,
+                };
+                if image.is_some_and(is_opaque_image) {
+                    let mut bounds = None;
+                    extend_bounds(&mut bounds, &transform_matrix, &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+                    let visible = bounds.and_then(|bounds| clip_bounds(bounds, state.clip));
+                    if let Some(bounds) = visible.filter(|_| !hidden_content.last().copied().unwrap_or(false) && fills_opaquely(&state)) {
+                        paint_order.opaque.push((paint_order.offset + i, bounds));
+                    }
+                }
+            },
+            "sh" => {
+                other_ops_count += 1;
+                paint_order.painted = true;
+            },
+            "MP" | "DP" => {
+                marked_content_ops_count += 1;
+            },
             "Tf" => {
                 text_ops_count += 1;
-                if let Some(Object::Name(name_bytes)) = op.operands.get(0) {
+                if let Some(Object::Name(name_bytes)) = op.operands.first() {
                     state.current_font_name = Some(name_bytes.clone());
                 }
             },
@@ -378,19 +645,23 @@ fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_hei
         }
     }
 
+    paint_order.offset += operations.len();
+
     let summary = SummaryStats {
         total_operations: operations.len(),
         rectangle_operations: rectangle_count,
         path_operations: path_ops_count,
         text_operations: text_ops_count,
+        invisible_text_operations: invisible_text_ops_count,
+        background_rectangles: background.len(),
+        marked_content_operations: marked_content_ops_count,
         other_operations: other_ops_count,
     };
 
-    let mut op_vec: Vec<_> = op_counts.iter().collect();
-    op_vec.sort_by(|a, b| b.1.cmp(a.1));
-    let most_common_operators = op_vec.iter().take(10).map(|(op, count)| OperatorCount {
-        operator: op.to_string(),
-        occurrences: **count,
+    // All operators are kept here; the page result keeps the ten most common
+    let most_common_operators = op_counts.into_iter().map(|(operator, occurrences)| OperatorCount {
+        operator,
+        occurrences,
     }).collect();
 
     PageAnalysisResult {
@@ -398,5 +669,236 @@ fn analyze_content_operations(operations: &[Operation], page_num: u32, _page_hei
         summary,
         most_common_operators,
         rectangles: rectangles_data,
+        background,
+    }
+}
+
+// Index of the operator that paints the path each operation belongs to. A
+// path can hold several rectangles and other segments before it is painted;
+// operators that may not appear inside a path end the search.
+fn painting_operators(operations: &[Operation]) -> Vec<Option<usize>> {
+    let mut painting = vec![None; operations.len()];
+    let mut next = None;
+    for (index, op) in operations.iter().enumerate().rev() {
+        match op.operator.as_str() {
+            "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "n" => next = Some(index),
+            "q" | "Q" | "cm" | "gs" => next = None,
+            _ => {},
+        }
+        painting[index] = next;
+    }
+    painting
+}
+
+// (left, bottom, right, top) in default user space
+type Bounds = (f64, f64, f64, f64);
+
+fn extend_bounds(bounds: &mut Option<Bounds>, matrix: &Matrix, points: &[(f64, f64)]) {
+    for &(x, y) in points {
+        let (x, y) = transform_point(matrix, x, y);
+        *bounds = Some(match *bounds {
+            Some((x1, y1, x2, y2)) => (x1.min(x), y1.min(y), x2.max(x), y2.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+}
+
+// Intersection of the clip with a new clipping path; a clip by an empty path
+// hides everything
+fn intersect(clip: Option<Bounds>, path: Option<Bounds>) -> Option<Bounds> {
+    let Some((x1, y1, x2, y2)) = path else { return Some((0.0, 0.0, -1.0, -1.0)) };
+    Some(match clip {
+        Some((cx1, cy1, cx2, cy2)) => (x1.max(cx1), y1.max(cy1), x2.min(cx2), y2.min(cy2)),
+        None => (x1, y1, x2, y2),
+    })
+}
+
+// Part of the rectangle inside the clip, None if no part of it is visible
+fn visible_region(corners: &CornerCoordinates, clip: Option<Bounds>) -> Option<CornerCoordinates> {
+    let (x1, y1, x2, y2) = (corners.x1.min(corners.x2), corners.y1.min(corners.y2), corners.x1.max(corners.x2), corners.y1.max(corners.y2));
+    let Some((cx1, cy1, cx2, cy2)) = clip else { return Some(CornerCoordinates { x1, y1, x2, y2 }) };
+    let region = CornerCoordinates { x1: x1.max(cx1), y1: y1.max(cy1), x2: x2.min(cx2), y2: y2.min(cy2) };
+    // A rectangle with an area keeps none of it when only an edge touches the clip
+    let touches_only = |low: f64, high: f64, length: f64| high < low || (length > 0.0 && high == low);
+    if touches_only(region.x1, region.x2, x2 - x1) || touches_only(region.y1, region.y2, y2 - y1) {
+        return None;
+    }
+    Some(region)
+}
+
+// Part of the bounds inside the clip, None when nothing of them is visible
+fn clip_bounds(bounds: Bounds, clip: Option<Bounds>) -> Option<Bounds> {
+    let (x1, y1, x2, y2) = match clip {
+        Some((cx1, cy1, cx2, cy2)) => (bounds.0.max(cx1), bounds.1.max(cy1), bounds.2.min(cx2), bounds.3.min(cy2)),
+        None => bounds,
+    };
+    (x2 > x1 && y2 > y1).then_some((x1, y1, x2, y2))
+}
+
+// Whether filled content hides what is below it. Blend modes other than
+// Normal mix the colors, so they are treated as see-through.
+fn fills_opaquely(state: &GraphicsState) -> bool {
+    let transparency = &state.transparency;
+    transparency.fill_opacity >= 1.0 && !transparency.soft_mask && matches!(transparency.blend_mode.as_str(), "Normal" | "Compatible")
+}
+
+// Images without a stencil, soft mask or color key mask paint every pixel
+fn is_opaque_image(image: &Stream) -> bool {
+    let is_stencil = image_entry(&image.dict, b"ImageMask").and_then(|obj| obj.as_bool().ok()).unwrap_or(false);
+    !is_stencil && !image.dict.has(b"SMask") && !image.dict.has(b"Mask")
+}
+
+// Fraction of the region covered by the union of the given bounds. The region
+// is swept in vertical slabs between cover edges; in each slab the covered
+// height is the union of the spans of the covers crossing it.
+fn covered_fraction(region: Bounds, covers: &[Bounds]) -> f64 {
+    let (x1, y1, x2, y2) = region;
+    let area = (x2 - x1) * (y2 - y1);
+    let mut covers: Vec<Bounds> = covers.iter().filter_map(|&cover| clip_bounds(cover, Some(region))).collect();
+    if area <= 0.0 || covers.is_empty() {
+        return 0.0;
+    }
+
+    covers.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let mut xs: Vec<f64> = covers.iter().flat_map(|cover| [cover.0, cover.2]).collect();
+    xs.sort_by(f64::total_cmp);
+    xs.dedup();
+    let mut covered = 0.0;
+    for x in xs.windows(2) {
+        // Covers are sorted by their bottom edge, so spans are merged in one pass
+        let (mut height, mut reach) = (0.0, f64::NEG_INFINITY);
+        for cover in covers.iter().filter(|cover| cover.0 <= x[0] && x[1] <= cover.2) {
+            if cover.3 > reach {
+                height += cover.3 - cover.1.max(reach);
+                reach = cover.3;
+            }
+        }
+        covered += (x[1] - x[0]) * height;
+    }
+    (covered / area).min(1.0)
+}
+
+// How far, in points, a page background may fall short of the page edges
+const BACKGROUND_TOLERANCE: f64 = 1.0;
+
+// Whether the bounds reach every edge of the page box
+fn covers_page((x1, y1, x2, y2): Bounds, page: &PageBox) -> bool {
+    let (llx, lly, urx, ury) = (page.llx.min(page.urx), page.lly.min(page.ury), page.llx.max(page.urx), page.lly.max(page.ury));
+    x1 <= llx + BACKGROUND_TOLERANCE && y1 <= lly + BACKGROUND_TOLERANCE && x2 >= urx - BACKGROUND_TOLERANCE && y2 >= ury - BACKGROUND_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    fn single_page(content: &str) -> Document {
+        page_with_streams(vec![Stream::new(Dictionary::new(), content.as_bytes().to_vec())])
+    }
+
+    fn page_with_streams(streams: Vec<Stream>) -> Document {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let contents: Vec<Object> = streams.into_iter().map(|stream| doc.add_object(stream).into()).collect();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => contents,
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc
+    }
+
+    fn analyze(content: &str) -> Vec<RectangleData> {
+        let options = AnalysisOptions { keep_duplicates: true, occlusion_threshold: Some(0.5), drop_occluded: true, ..Default::default() };
+        analyze_pdf_typst(&single_page(content), None, None, &options).unwrap().rectangles
+    }
+
+    #[test]
+    fn path_fill_does_not_occlude_its_rectangles() {
+        let rectangles = analyze("0 1 0 rg 10 10 100 100 re 10 10 100 100 re 200 10 100 100 re f");
+        assert_eq!(rectangles.len(), 3);
+        assert!(rectangles.iter().all(|rect| rect.operation_type == "Fill only" && rect.occluded == 0.0));
+    }
+
+    #[test]
+    fn later_fill_occludes_rectangles() {
+        let rectangles = analyze("0 1 0 rg 10 10 100 100 re 200 10 100 100 re f 1 g 0 0 150 150 re f");
+        assert_eq!(rectangles.len(), 2);
+        assert_eq!(rectangles[0].position.x, 200.0);
+        assert_eq!(rectangles[1].position.x, 0.0);
+    }
+
+    #[test]
+    fn diagnostics_report_page_statistics() {
+        let doc = single_page("0 1 0 rg 10 10 100 100 re f 0 0 m 5 5 l S BT /F1 12 Tf (x) Tj ET");
+        let result = analyze_pdf_typst(&doc, None, None, &AnalysisOptions { diagnostics: true, ..Default::default() }).unwrap();
+        let summary = &result.pages.as_ref().unwrap()[0].summary;
+        assert_eq!((summary.total_operations, summary.rectangle_operations, summary.text_operations), (10, 1, 4));
+        let streams = result.content_streams.unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!((streams[0].operations, streams[0].decoded_size), (Some(10), Some(streams[0].raw_size)));
+
+        let result = analyze_pdf_typst(&doc, None, None, &AnalysisOptions::default()).unwrap();
+        assert!(result.pages.is_none() && result.content_streams.is_none());
+    }
+
+    #[test]
+    fn unreadable_streams_are_warnings_unless_strict() {
+        let unsupported = Stream::new(dictionary! { "Filter" => "JBIG2Decode" }, b"garbage".to_vec());
+        let corrupt = Stream::new(dictionary! { "Filter" => "FlateDecode" }, b"not zlib".to_vec());
+        let readable = Stream::new(Dictionary::new(), b"0 1 0 rg 10 10 100 100 re f".to_vec());
+        let doc = page_with_streams(vec![unsupported, corrupt, readable]);
+
+        let result = analyze_pdf_typst(&doc, None, None, &AnalysisOptions::default()).unwrap();
+        let kinds: Vec<AnalysisWarningKind> = result.warnings.iter().map(|warning| warning.kind).collect();
+        assert_eq!(kinds, [WarningKind::UnsupportedFilter, WarningKind::CorruptStream].map(AnalysisWarningKind::ContentStream));
+        assert_eq!(result.rectangles.len(), 1);
+
+        let error = analyze_pdf_typst(&doc, None, None, &AnalysisOptions { strict: true, ..Default::default() }).unwrap_err();
+        assert_eq!(error.reason(), "unsupported_filter");
+    }
+
+    #[test]
+    fn occluded_rectangles_are_field_warnings() {
+        let doc = single_page("0 1 0 rg 10 10 100 100 re f 1 g 0 0 150 150 re f");
+        let options = AnalysisOptions { occlusion_threshold: Some(0.5), strict: true, ..Default::default() };
+        let result = analyze_pdf_typst(&doc, None, None, &options).unwrap();
+        let occluded: Vec<_> = result.warnings.iter().filter(|warning| warning.kind == AnalysisWarningKind::Field(FieldWarningKind::OccludedField)).collect();
+        assert_eq!(occluded.len(), 1);
+        assert_eq!(serde_json::to_value(occluded[0]).unwrap()["kind"], "occluded_field");
+    }
+
+    #[test]
+    fn only_a_page_fill_painted_first_is_background() {
+        let options = AnalysisOptions { diagnostics: true, keep_duplicates: true, ..Default::default() };
+        let page = |content: &str| {
+            let mut result = analyze_pdf_typst(&single_page(content), None, None, &options).unwrap();
+            let page = result.pages.take().unwrap().remove(0);
+            (result.rectangles.len(), page.summary.background_rectangles, page.background)
+        };
+
+        let (rectangles, count, background) = page("1 g 0 0 612 792 re f 0 1 0 rg 10 10 100 100 re f");
+        assert_eq!((rectangles, count), (1, 1));
+        let corners = &background[0];
+        assert_eq!((corners.x1, corners.y1, corners.x2, corners.y2), (0.0, 0.0, 612.0, 792.0));
+
+        let (rectangles, count, background) = page("0 1 0 rg 10 10 100 100 re f 1 g 0 0 612 792 re f");
+        assert_eq!((rectangles, count), (2, 0));
+        assert!(background.is_empty());
+    }
+
+    #[test]
+    fn overlapping_covers_count_once() {
+        let covers = [(0.0, 0.0, 6.0, 10.0), (4.0, 0.0, 10.0, 5.0), (4.0, 2.0, 8.0, 4.0), (-5.0, 20.0, 5.0, 30.0)];
+        assert!((covered_fraction((0.0, 0.0, 10.0, 10.0), &covers) - 0.8).abs() < 1e-9);
+        assert_eq!(covered_fraction((0.0, 0.0, 10.0, 10.0), &[(9.0, 9.0, 20.0, 20.0), (-1.0, -1.0, 11.0, 11.0)]), 1.0);
     }
 }